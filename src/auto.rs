@@ -0,0 +1,151 @@
+// Automatic choice of compression algorithm, file by file.
+//
+// XPRESS4K is cheap to decompress and loses little on small files, or on those
+// already dense enough that nothing will do much better.  LZX is slow, but can
+// win a lot on large files full of text-like data.  Anything in between gets
+// the usual XPRESS8K.
+//
+// Files are judged by their extension, and by the Shannon entropy of their
+// first few KB: near 8 bits per byte is already compressed or encrypted, and
+// text tends to sit well under 6.
+
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::Path;
+
+use crate::folder::extension_of;
+use crate::wof::Compression;
+
+/// The name of automatic mode in settings and on the command line.
+pub const NAME: &str = "AUTO";
+
+/// How much of each file is sampled for its entropy.
+const SAMPLE_BYTES: u64 = 8 * 1024;
+
+/// Files smaller than this aren't worth LZX's extra CPU.
+const LARGE_FILE: u64 = 1024 * 1024;
+
+/// Entropy in bits per byte above which data looks already compressed.
+const DENSE_ENTROPY: f64 = 7.5;
+
+/// Entropy in bits per byte below which data looks like text.
+const TEXT_ENTROPY: f64 = 6.0;
+
+/// Extensions which are text, or close enough to it, whatever their sample.
+const TEXT_EXTENSIONS: &[&str] = &[
+    "txt", "log", "csv", "json", "xml", "html", "htm", "css", "js", "ini", "cfg", "yml", "yaml",
+    "lua", "py", "md", "sql",
+];
+
+/// Why a file was given the algorithm it was.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Choice {
+    Small,
+    Dense,
+    TextLike,
+    Other,
+}
+
+impl Choice {
+    pub fn compression(self) -> Compression {
+        match self {
+            Choice::Small | Choice::Dense => Compression::Xpress4k,
+            Choice::TextLike => Compression::Lzx,
+            Choice::Other => Compression::default(),
+        }
+    }
+}
+
+impl std::fmt::Display for Choice {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Choice::Small => write!(f, "small"),
+            Choice::Dense => write!(f, "dense"),
+            Choice::TextLike => write!(f, "large text-like"),
+            Choice::Other => write!(f, "other"),
+        }
+    }
+}
+
+/// Shannon entropy of some data, in bits per byte.
+fn entropy(data: &[u8]) -> f64 {
+    let mut counts = [0u64; 256];
+    for &b in data {
+        counts[b as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|&&c| c > 0)
+        .map(|&c| {
+            let p = c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Choose for a file given its extension, size, and the start of its contents.
+fn choose_for(extension: &str, size: u64, sample: &[u8]) -> Choice {
+    if size < LARGE_FILE {
+        return Choice::Small;
+    }
+
+    let entropy = entropy(sample);
+    if entropy >= DENSE_ENTROPY {
+        Choice::Dense
+    } else if entropy < TEXT_ENTROPY || TEXT_EXTENSIONS.contains(&extension) {
+        Choice::TextLike
+    } else {
+        Choice::Other
+    }
+}
+
+/// Choose an algorithm for a file, reading the start of it if it's large
+/// enough for that to matter.
+pub fn choose<P: AsRef<Path>>(path: P, size: u64) -> io::Result<Choice> {
+    let path = path.as_ref();
+    let extension = extension_of(path);
+    if size < LARGE_FILE {
+        return Ok(choose_for(&extension, size, &[]));
+    }
+
+    let mut sample = Vec::with_capacity(SAMPLE_BYTES as usize);
+    File::open(path)?
+        .take(SAMPLE_BYTES)
+        .read_to_end(&mut sample)?;
+
+    Ok(choose_for(&extension, size, &sample))
+}
+
+#[test]
+fn it_measures_entropy() {
+    assert_eq!(entropy(&[]), 0.0);
+    assert_eq!(entropy(&[7; 64]), 0.0);
+    assert!((entropy(&[0, 1]) - 1.0).abs() < 1e-9);
+
+    let all: Vec<u8> = (0..=255).collect();
+    assert!((entropy(&all) - 8.0).abs() < 1e-9);
+}
+
+#[test]
+fn it_chooses_algorithms() {
+    let text = "hello world ".repeat(512);
+    let dense: Vec<u8> = (0..8192).map(|i| (i * 7919 % 256) as u8).collect();
+
+    assert_eq!(choose_for("txt", 4096, text.as_bytes()), Choice::Small);
+    assert_eq!(
+        choose_for("txt", LARGE_FILE, text.as_bytes()),
+        Choice::TextLike
+    );
+    assert_eq!(
+        choose_for("bin", LARGE_FILE, text.as_bytes()),
+        Choice::TextLike
+    );
+    assert_eq!(choose_for("bin", LARGE_FILE, &dense), Choice::Dense);
+    assert_eq!(choose_for("txt", LARGE_FILE, &dense), Choice::Dense);
+
+    let mixed: Vec<u8> = (0..8192).map(|i| (i * 7919 % 128) as u8).collect();
+    assert_eq!(choose_for("bin", LARGE_FILE, &mixed), Choice::Other);
+    assert_eq!(Choice::Other.compression(), Compression::default());
+}
@@ -1,24 +1,95 @@
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::time::Duration;
 use std::time::Instant;
+use std::time::SystemTime;
 
 use crossbeam_channel::{bounded, Receiver, RecvTimeoutError};
-use filesize::PathExt;
 
+use crate::auto;
 use crate::background::BackgroundHandle;
-use crate::compression::BackgroundCompactor;
-use crate::folder::{FileKind, FolderInfo, FolderScan};
-use crate::gui::{GuiRequest, GuiWrapper};
-use crate::persistence::{config, pathdb};
-
-pub struct Backend<T> {
-    gui: GuiWrapper<T>,
+use crate::benchmark::Benchmark;
+use crate::compact;
+use crate::compression::{self, BackgroundCompactor, CompactOptions, FileJob, FileResult};
+use crate::config::{Order, Priority};
+use crate::error::CompactorError;
+use crate::estimate::{self, Estimator};
+use crate::folder::{self, FileInfo, FileKind, FolderInfo, FolderScan, FolderSummary};
+use crate::gui::{Frontend, GuiRequest};
+use crate::manifest::{self, Manifest, ManifestEntry};
+use crate::persistence::{self, config_for, pathdb};
+use crate::power::PowerWatch;
+use crate::priority::PriorityGuard;
+use crate::privilege;
+use crate::protected::Protected;
+use crate::snapshot::Snapshot;
+use crate::state::LastRun;
+use crate::wof::{self, Compression};
+
+/// How many of the largest skipped files to list in an exported report.
+const REPORT_LARGEST_FILES: usize = 100;
+
+/// Maximum bytes to read from sampled files when estimating compression.
+const ESTIMATE_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+/// Maximum bytes to copy from sampled files when benchmarking algorithms.
+const BENCHMARK_MAX_BYTES: u64 = 64 * 1024 * 1024;
+
+/// How many excluded files to list when previewing excludes.
+const EXCLUDE_PREVIEW_SAMPLE: usize = 20;
+
+/// How many recently processed files to average throughput over for estimating
+/// time remaining.
+const THROUGHPUT_WINDOW: usize = 64;
+
+/// How far back the live throughput in the status looks, kept short so a
+/// stalled drive shows up within a few seconds.
+const RATE_WINDOW: Duration = Duration::from_secs(3);
+
+/// How many per-file errors to keep for display after a run.
+const ERROR_SAMPLE_SIZE: usize = 100;
+
+/// Minimum time between current file updates, so huge folders of tiny files
+/// don't flood the WebView.
+const CURRENT_FILE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Minimum time between running totals while scanning.  Each is a whole
+/// `FolderSummary`, extension ratios and all, so they're sent less often than
+/// the current path.
+const SUMMARY_INTERVAL: Duration = Duration::from_millis(250);
+
+/// How many files in a row can go missing before checking if the whole folder
+/// has been deleted or moved out from under us.
+const VANISHED_AFTER: usize = 3;
+
+/// The share of compressible data already compressed before LZX is hardly
+/// worth the wait.
+const MOSTLY_COMPRESSED: f64 = 0.9;
+
+pub struct Backend<F> {
+    gui: F,
     msg: Receiver<GuiRequest>,
     info: Option<FolderInfo>,
+    queue: VecDeque<PathBuf>,
+    active: Option<PathBuf>,
+    /// The most recent compression run, for `UndoLast`.
+    last_run: Option<LastRun>,
+    /// A summary of the most recent compression result, for `CopySummary`.
+    last_summary: Option<String>,
+    /// Subfolders deselected for the next compression run only.
+    excluded_subfolders: Vec<PathBuf>,
+}
+
+/// How a scan, compression or decompression run finished.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Outcome {
+    Completed,
+    Partial,
+    Stopped,
 }
 
-fn format_size(size: u64, decimal: bool) -> String {
+pub fn format_size(size: u64, decimal: bool) -> String {
     use humansize::{file_size_opts as options, FileSize};
 
     size.file_size(if decimal {
@@ -29,66 +100,933 @@ fn format_size(size: u64, decimal: bool) -> String {
     .expect("file size")
 }
 
-impl<T> Backend<T> {
-    pub fn new(gui: GuiWrapper<T>, msg: Receiver<GuiRequest>) -> Self {
+/// Check if a file was modified or created after a given time, counting any
+/// error as a change.
+///
+/// Copying a file keeps its modification time but gives it a new creation
+/// time, so both are needed to spot newcomers.
+fn changed_since(path: &Path, since: SystemTime) -> bool {
+    std::fs::metadata(compact::long_path(path)).map_or(true, |md| {
+        md.modified().map_or(true, |t| t >= since) || md.created().map_or(true, |t| t >= since)
+    })
+}
+
+/// Count the files from a run which have since gone or been modified, and so
+/// may not be as the run left them.
+fn changed_files(run: &LastRun) -> usize {
+    run.files
+        .iter()
+        .filter(|path| changed_since(path, run.finished))
+        .count()
+}
+
+/// Check if a file, relative to its folder, is within any of the given
+/// subfolders, which may be either relative or full paths.
+fn in_subfolders(base: &Path, path: &Path, subfolders: &[PathBuf]) -> bool {
+    subfolders
+        .iter()
+        .any(|sub| path.starts_with(sub.strip_prefix(base).unwrap_or(sub)))
+}
+
+/// Move compressible files matching `skip` to the skipped group, returning how
+/// many were moved.
+fn skip_compressible<F: FnMut(&FileInfo) -> bool>(folder: &mut FolderInfo, mut skip: F) -> usize {
+    let mut keep = Vec::new();
+    let mut skipped = 0;
+
+    while let Some(fi) = folder.pop(FileKind::Compressible) {
+        if skip(&fi) {
+            skipped += 1;
+            folder.push(FileKind::Skipped, fi);
+        } else {
+            keep.push(fi);
+        }
+    }
+
+    for fi in keep.into_iter().rev() {
+        folder.push(FileKind::Compressible, fi);
+    }
+
+    skipped
+}
+
+/// Skip compressible files modified since `cutoff`, by their time as of the
+/// scan, returning how many were skipped.  Files without one are skipped too,
+/// since they might be new.
+fn skip_too_new(folder: &mut FolderInfo, cutoff: SystemTime) -> usize {
+    skip_compressible(folder, |fi| fi.modified.map_or(true, |t| t >= cutoff))
+}
+
+/// Sort files into the order they should be compressed in.  The sort is
+/// stable, so equally sized files keep the order they were found in.
+fn sort_files(files: &mut [FileInfo], order: Order) {
+    match order {
+        Order::Default => (),
+        Order::LargestFirst => files.sort_by(|a, b| b.logical_size.cmp(&a.logical_size)),
+        Order::SmallestFirst => files.sort_by_key(|fi| fi.logical_size),
+    }
+}
+
+/// Reorder a folder's compressible files.
+fn order_compressible(folder: &mut FolderInfo, order: Order) {
+    if order == Order::Default {
+        return;
+    }
+
+    let mut files = Vec::with_capacity(folder.len(FileKind::Compressible));
+    while let Some(fi) = folder.pop(FileKind::Compressible) {
+        files.push(fi);
+    }
+
+    sort_files(&mut files, order);
+
+    for fi in files {
+        folder.push(FileKind::Compressible, fi);
+    }
+}
+
+/// Check if most of the data worth compressing in a folder already is.
+fn mostly_compressed(summary: &FolderSummary) -> bool {
+    let compressed = summary.compressed.logical_size;
+    let total = compressed + summary.compressible.logical_size;
+
+    total > 0 && compressed as f64 >= total as f64 * MOSTLY_COMPRESSED
+}
+
+/// The fraction of a job's bytes which are done, for progress bars.
+///
+/// Counting files instead would have the bar stall on any large ones.
+fn progress(done: u64, total: u64) -> f32 {
+    if total == 0 {
+        1.0
+    } else {
+        (done as f64 / total as f64) as f32
+    }
+}
+
+/// Describe a file error, calling out files other programs are using since
+/// they're likely to work another time.
+fn describe_error(err: &io::Error) -> String {
+    if compression::is_in_use(err) {
+        format!("In use by another program ({})", err)
+    } else {
+        err.to_string()
+    }
+}
+
+/// The space saved by a compression run, and as a percentage of the original.
+fn saving(before: &FolderSummary, after: &FolderSummary) -> (u64, f64) {
+    let saved = before.physical_size.saturating_sub(after.physical_size);
+    let pct = if before.physical_size > 0 {
+        saved as f64 / before.physical_size as f64 * 100.0
+    } else {
+        0.0
+    };
+
+    (saved, pct)
+}
+
+/// Describe the space saved by a compression run, for notifications.
+fn describe_result(before: &FolderSummary, after: &FolderSummary, decimal: bool) -> String {
+    let (saved, pct) = saving(before, after);
+    format!("Saved {} ({:.1}%)", format_size(saved, decimal), pct)
+}
+
+/// Format a count with thousands separators.
+fn format_count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() * 4 / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Sum up a compression run in a sentence, for pasting elsewhere.
+fn summary_line(
+    files: u64,
+    before: &FolderSummary,
+    after: &FolderSummary,
+    algorithm: &str,
+    decimal: bool,
+) -> String {
+    let (saved, pct) = saving(before, after);
+    format!(
+        "Compressed {} files, saved {} ({:.0}%) with {}",
+        format_count(files),
+        format_size(saved, decimal),
+        pct,
+        algorithm
+    )
+}
+
+/// Undo whatever was done to a file the user skipped partway through, once the
+/// compactor is finished with it.
+fn undo_skipped(path: &Path, result: io::Result<bool>) {
+    if let Ok(true) = result {
+        if let Err(e) = wof::decompress_file(compact::long_path(path)) {
+            log::warn!("Error decompressing skipped {}: {}", path.display(), e);
+        }
+    }
+}
+
+/// Run at `priority` until the returned guard is dropped.
+fn lower_priority(priority: Priority) -> Option<PriorityGuard> {
+    PriorityGuard::set(priority)
+        .map_err(|e| log::warn!("Error setting priority: {}", e))
+        .ok()
+}
+
+/// A moving average of processing speed over the last few files.
+///
+/// A simple average over the whole run swings wildly when it hits a run of
+/// quickly-skipped incompressible files, so only recent history is kept.
+#[derive(Debug)]
+struct Throughput {
+    window: usize,
+    samples: VecDeque<(u64, Duration)>,
+    last: Instant,
+}
+
+impl Throughput {
+    fn new(window: usize) -> Self {
+        Self {
+            window,
+            samples: VecDeque::with_capacity(window),
+            last: Instant::now(),
+        }
+    }
+
+    /// Record a file of the given size finishing now.
+    fn record(&mut self, bytes: u64) {
+        let now = Instant::now();
+        self.record_with(bytes, now - self.last);
+        self.last = now;
+    }
+
+    fn record_with(&mut self, bytes: u64, elapsed: Duration) {
+        self.samples.push_back((bytes, elapsed));
+
+        if self.samples.len() > self.window {
+            self.samples.pop_front();
+        }
+    }
+
+    /// Don't count time spent paused.
+    fn restart_clock(&mut self) {
+        self.last = Instant::now();
+    }
+
+    /// Estimated seconds to process the given number of bytes.
+    fn eta(&self, remaining: u64) -> Option<u64> {
+        let (bytes, elapsed) = self.samples.iter().fold(
+            (0u64, Duration::from_secs(0)),
+            |(b, e), (bytes, elapsed)| (b + bytes, e + *elapsed),
+        );
+
+        if bytes == 0 || elapsed.as_secs_f64() <= 0.0 {
+            return None;
+        }
+
+        Some((remaining as f64 * elapsed.as_secs_f64() / bytes as f64).round() as u64)
+    }
+}
+
+/// Bytes processed over the last few seconds, for a live throughput figure.
+///
+/// Unlike `Throughput` this is by time rather than by file, so it falls to
+/// nothing while files are stuck rather than waiting for them to finish.
+#[derive(Debug)]
+struct Rate {
+    window: Duration,
+    start: Instant,
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Rate {
+    fn new(window: Duration) -> Self {
+        Self {
+            window,
+            start: Instant::now(),
+            samples: VecDeque::new(),
+        }
+    }
+
+    fn record(&mut self, bytes: u64) {
+        self.record_at(Instant::now(), bytes);
+    }
+
+    fn record_at(&mut self, at: Instant, bytes: u64) {
+        self.samples.push_back((at, bytes));
+    }
+
+    /// Don't count time spent paused.
+    fn restart_clock(&mut self) {
+        self.start = Instant::now();
+        self.samples.clear();
+    }
+
+    fn mib_per_sec(&mut self) -> Option<f32> {
+        self.mib_per_sec_at(Instant::now())
+    }
+
+    /// MiB per second over the window up to `now`, or over the time since the
+    /// start if that's shorter.
+    fn mib_per_sec_at(&mut self, now: Instant) -> Option<f32> {
+        while let Some(&(at, _)) = self.samples.front() {
+            if now.duration_since(at) <= self.window {
+                break;
+            }
+            self.samples.pop_front();
+        }
+
+        let secs = now
+            .duration_since(self.start)
+            .min(self.window)
+            .as_secs_f32();
+        if secs <= 0.0 {
+            return None;
+        }
+
+        let bytes: u64 = self.samples.iter().map(|&(_, bytes)| bytes).sum();
+        Some(bytes as f32 / (1024.0 * 1024.0) / secs)
+    }
+}
+
+/// Holds back new files to keep compression under a throughput limit.
+struct Throttle {
+    limit: Option<u64>,
+    bytes: u64,
+    start: Instant,
+}
+
+impl Throttle {
+    /// Limit to the given number of bytes per second, if any.
+    fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit: limit.filter(|&limit| limit > 0),
+            bytes: 0,
+            start: Instant::now(),
+        }
+    }
+
+    /// Record a file of the given size being started.
+    fn record(&mut self, bytes: u64) {
+        self.bytes += bytes;
+    }
+
+    /// Don't let time spent paused count towards the limit.
+    fn restart_clock(&mut self) {
+        self.bytes = 0;
+        self.start = Instant::now();
+    }
+
+    /// Check if another file may be started yet.
+    fn ready(&self) -> bool {
+        self.ready_after(self.start.elapsed())
+    }
+
+    fn ready_after(&self, elapsed: Duration) -> bool {
+        match self.limit {
+            Some(limit) => self.bytes as f64 / limit as f64 <= elapsed.as_secs_f64(),
+            None => true,
+        }
+    }
+}
+
+/// Files which failed during a run, keeping only the first few to show.
+#[derive(Debug, Default)]
+struct ErrorLog {
+    count: usize,
+    sample: Vec<String>,
+    /// The path of each file in `sample`, relative to the folder.
+    paths: Vec<PathBuf>,
+}
+
+impl ErrorLog {
+    fn record(&mut self, path: &Path, err: &io::Error) {
+        self.count += 1;
+        log::warn!("{}: {}", path.display(), err);
+
+        if self.sample.len() < ERROR_SAMPLE_SIZE {
+            self.sample
+                .push(format!("{}: {}", path.display(), describe_error(err)));
+            self.paths.push(path.to_path_buf());
+        }
+    }
+}
+
+/// Progress decompressing lists of files, across calls to `rollback_files`.
+#[derive(Debug, Default)]
+struct Rollback {
+    total: u64,
+    done: u64,
+    undone: u64,
+    errors: ErrorLog,
+    /// Files to try again if the undo is itself undone or fails.
+    remaining: Vec<PathBuf>,
+    stopped: bool,
+}
+
+impl Rollback {
+    fn new(total: usize) -> Self {
+        Self {
+            total: total as u64,
+            ..Self::default()
+        }
+    }
+
+    /// Files left alone for not being compressed how they were.
+    fn untouched(&self) -> u64 {
+        self.done - self.undone - self.errors.count as u64
+    }
+}
+
+impl Outcome {
+    fn of(stopped: bool, errors: usize) -> Self {
+        if stopped {
+            Outcome::Stopped
+        } else if errors > 0 {
+            Outcome::Partial
+        } else {
+            Outcome::Completed
+        }
+    }
+}
+
+impl<F: Frontend> Backend<F> {
+    pub fn new(gui: F, msg: Receiver<GuiRequest>) -> Self {
         Self {
             gui,
             msg,
             info: None,
+            queue: VecDeque::new(),
+            active: None,
+            last_run: persistence::last_run(),
+            last_summary: None,
+            excluded_subfolders: Vec::new(),
+        }
+    }
+
+    /// Select a folder without analysing it, ready for an `Analyse` request.
+    pub fn restore_folder(&mut self, path: PathBuf) {
+        let path = compact::normal_path(path);
+        self.disk_info(&path);
+        self.info = Some(FolderInfo::new(path));
+    }
+
+    /// Tell the GUI how much space there is on the folder's volume.
+    fn disk_info(&self, path: &Path) {
+        let dir = match path.parent() {
+            Some(parent) if path.is_file() => parent,
+            _ => path,
+        };
+
+        match compact::disk_space(dir) {
+            Ok((free, total)) => self.gui.disk_info(free, total),
+            Err(e) => log::warn!("Error getting free space of {}: {}", dir.display(), e),
         }
     }
 
     pub fn run(&mut self) {
         loop {
             match self.msg.recv() {
-                Ok(GuiRequest::ChooseFolder) => {
-                    let path = self.gui.choose_folder().recv().ok().flatten();
+                Ok(msg) => {
+                    log::debug!("Request: {:?}", msg);
+                    let outcome = self.handle(msg);
+                    if let Some(outcome) = outcome {
+                        log::info!("Finished: {:?}", outcome);
+                    }
+                    self.process_queue(outcome);
+                }
+                Err(_) => {
+                    log::info!("Backend: exit run loop");
+                    break;
+                }
+            }
+        }
+    }
+
+    /// Scan and compress queued folders in turn, until the queue is empty or
+    /// a job is stopped.
+    fn process_queue(&mut self, mut outcome: Option<Outcome>) {
+        loop {
+            if outcome == Some(Outcome::Stopped) {
+                self.clear_queue();
+            }
+
+            let path = match self.queue.pop_front() {
+                Some(path) => path,
+                None => break,
+            };
+
+            log::info!("Starting queued folder {}", path.display());
+            self.active = Some(path.clone());
+            self.send_queue();
+
+            self.gui.folder(&path);
+            self.gui.config(config_for(&path));
+            self.disk_info(&path);
+            self.check_network(&path);
+            outcome = self
+                .check_filesystem(&path)
+                .and_then(|_| self.check_protected(&path))
+                .map(|_| self.scan_loop(path));
+
+            // Queued folders are still worth analysing for a report, even if
+            // they're not to be compressed after all.
+            if self.info.is_some() && outcome.is_some() && outcome != Some(Outcome::Stopped) {
+                if let Some(compressed) = self.start_compress(false, false) {
+                    outcome = Some(compressed);
+                }
+            }
+
+            self.active = None;
+            self.send_queue();
+        }
+    }
+
+    /// Windows system files are skipped unless the config allows them, so make
+    /// sure the user knows before working on a folder containing any.
+    ///
+    /// Returns `None` if they'd rather not carry on.
+    fn check_protected(&mut self, path: &Path) -> Option<()> {
+        if config_for(path).allow_protected || !Protected::for_volume(path).overlaps(path) {
+            return Some(());
+        }
+
+        let message = format!(
+            "{} contains Windows system files. These will be skipped, but compressing \
+             anything else here may still cause problems.\n\nContinue anyway?",
+            path.display()
+        );
+
+        if self.gui.confirm("Protected folder", &message).recv() == Ok(true) {
+            return Some(());
+        }
+
+        self.gui.status(
+            format!(
+                "Not compressing {}: it contains Windows system files",
+                path.display()
+            ),
+            Some(1.0),
+        );
+        self.gui.stopped();
+        self.info = None;
+        None
+    }
+
+    /// Compress the scanned folder, if changes are allowed and the user still
+    /// wants to, returning `None` if not.
+    fn start_compress(&mut self, force: bool, incremental: bool) -> Option<Outcome> {
+        self.check_writable(&self.info.as_ref().expect("fileinfo").path)?;
+        self.confirm_compress(force)?;
+        Some(self.compress_loop(force, incremental))
+    }
+
+    /// Give the user a last chance to back out of compressing the wrong folder,
+    /// if they've asked for one.
+    ///
+    /// Returns `None` if they'd rather not go ahead.
+    fn confirm_compress(&mut self, force: bool) -> Option<()> {
+        let folder = self.info.as_ref().expect("fileinfo");
+        let conf = config_for(&folder.path);
+        if !conf.confirm_before_compress || !self.gui.interactive() {
+            return Some(());
+        }
+
+        let summary = folder.summary();
+        let (count, size) = if force {
+            (
+                summary.compressible.count + summary.compressed.count,
+                summary.compressible.logical_size + summary.compressed.logical_size,
+            )
+        } else {
+            (
+                summary.compressible.count,
+                summary.compressible.logical_size,
+            )
+        };
+        let algorithm = if conf.auto_compression {
+            "an automatically chosen algorithm".to_string()
+        } else {
+            conf.compression.to_string()
+        };
+        let message = format!(
+            "Compress {} files ({}) in {} with {}?",
+            count,
+            format_size(size, conf.decimal),
+            folder.path.display(),
+            algorithm
+        );
+
+        if self.gui.confirm("Compress folder", &message).recv() == Ok(true) {
+            return Some(());
+        }
+
+        self.gui.status("Compression cancelled", Some(1.0));
+        self.gui.scanned();
+        None
+    }
+
+    /// Undoing a run long after the fact could decompress files that have
+    /// since been replaced, so check with the user if anything's changed.
+    ///
+    /// Returns `None` if they'd rather leave things be.
+    fn confirm_undo(&mut self, run: &LastRun) -> Option<()> {
+        self.gui.phase("analysing", 0, 0);
+        let changed = changed_files(run);
+        if changed == 0 {
+            return Some(());
+        }
+
+        let message = format!(
+            "{} of the {} files compressed in {} have changed or gone since it was \
+             compressed.\n\nDecompress the rest anyway?",
+            changed,
+            run.files.len(),
+            run.folder.display()
+        );
+
+        if self.gui.confirm("Folder changed", &message).recv() == Ok(true) {
+            return Some(());
+        }
+
+        self.gui.status("Undo cancelled", Some(1.0));
+        None
+    }
 
-                    if let Some(path) = path {
-                        self.gui.folder(&path);
-                        self.scan_loop(path);
+    /// Read-only mode is for auditing, so refuse anything that would change a
+    /// folder.
+    ///
+    /// Returns `None` after telling the user if changes aren't allowed.
+    fn check_writable(&self, path: &Path) -> Option<()> {
+        if !config_for(path).read_only_mode {
+            return Some(());
+        }
+
+        self.gui.status(
+            "Read-only mode is on: folders can be analysed, but not compressed or decompressed",
+            Some(1.0),
+        );
+        None
+    }
+
+    /// Compression works on network shares, but everything crosses the
+    /// network twice, so let the user know what they're in for.
+    fn check_network(&self, path: &Path) {
+        if compact::is_network_path(path) {
+            self.gui.warning(format!(
+                "{} is on a network drive. Compressing it reads and writes every file over \
+                 the network, so it may be slow.",
+                path.display()
+            ));
+        }
+    }
+
+    /// WOF compression only works on NTFS, so don't bother trying elsewhere.
+    ///
+    /// Returns `None` after telling the user if the folder can't be compressed.
+    fn check_filesystem(&mut self, path: &Path) -> Option<()> {
+        match compact::filesystem_name(path) {
+            Ok(fs) if !fs.eq_ignore_ascii_case("NTFS") => {
+                self.gui.status(
+                    format!(
+                        "Can't compress {}: it's on a {} volume, and only NTFS is supported",
+                        path.display(),
+                        fs
+                    ),
+                    Some(1.0),
+                );
+                self.gui.stopped();
+                self.info = None;
+                None
+            }
+            Ok(_) => Some(()),
+            Err(e) => {
+                // Network shares and the like may not say, so try anyway.
+                log::warn!("Error detecting filesystem of {}: {}", path.display(), e);
+                Some(())
+            }
+        }
+    }
+
+    fn enqueue(&mut self, path: PathBuf) {
+        match compact::expand_env(&path) {
+            Ok(path) => {
+                self.queue.push_back(compact::normal_path(path));
+                self.send_queue();
+            }
+            Err(e) => self.gui.error("Can't queue folder", &e),
+        }
+    }
+
+    fn clear_queue(&mut self) {
+        if !self.queue.is_empty() {
+            self.queue.clear();
+            self.send_queue();
+        }
+    }
+
+    fn send_queue(&self) {
+        self.gui
+            .queue_updated(self.queue.iter().cloned().collect(), self.active.clone());
+    }
+
+    /// Act on a single request, returning the outcome of any job it started.
+    pub fn handle(&mut self, msg: GuiRequest) -> Option<Outcome> {
+        match msg {
+            GuiRequest::ChooseFolder | GuiRequest::ChooseFile => {
+                let chosen = match msg {
+                    GuiRequest::ChooseFile => self.gui.choose_file(),
+                    _ => self.gui.choose_folder(),
+                };
+
+                // A closed chooser, or one which never opened, still tells us.
+                let mut paths = chosen.recv().unwrap_or_default();
+                match paths.len() {
+                    0 => {
+                        self.gui.folder_cancelled();
+                        None
+                    }
+                    1 => self.open_folder(paths.remove(0)),
+                    _ => {
+                        // Several at once go through the queue, one after another.
+                        log::info!("Queueing {} chosen folders", paths.len());
+                        self.gui.folders(&paths);
+                        for path in paths {
+                            self.enqueue(path);
+                        }
+                        None
                     }
                 }
-                Ok(GuiRequest::Analyse) if self.info.is_some() => {
-                    let path = self.info.take().unwrap().path;
-                    self.gui.folder(&path);
-                    self.scan_loop(path);
+            }
+            GuiRequest::OpenFolder { path } => self.open_folder(path),
+            // Whatever the browser hands over may not be a real local path.
+            GuiRequest::DroppedPath { path } => {
+                if !path.is_absolute() || !(path.is_dir() || path.is_file()) {
+                    self.gui.warning(format!(
+                        "Can't open {}: drop a folder or file from this computer",
+                        path.display()
+                    ));
+                    return None;
                 }
-                Ok(GuiRequest::Compress) if self.info.is_some() => {
-                    self.compress_loop();
+
+                if let Err(e) = persistence::set_last_folder(&path) {
+                    log::warn!("Error saving last folder: {}", e);
                 }
-                Ok(GuiRequest::Decompress) if self.info.is_some() => {
-                    self.uncompress_loop();
+                self.gui.recent_folders(persistence::recent_folders());
+
+                self.open_folder(path)
+            }
+            GuiRequest::SelectRecent { index } => {
+                let path = persistence::recent_folders().into_iter().nth(index)?;
+
+                if let Err(e) = persistence::set_last_folder(&path) {
+                    log::warn!("Error saving last folder: {}", e);
                 }
-                Ok(msg) => {
-                    eprintln!("Backend: Ignored message: {:?}", msg);
+                self.gui.recent_folders(persistence::recent_folders());
+
+                self.open_folder(path)
+            }
+            GuiRequest::Analyse if self.info.is_some() => {
+                let path = self.info.take().unwrap().path;
+                self.gui.folder(&path);
+                self.disk_info(&path);
+                self.check_filesystem(&path)?;
+                self.check_protected(&path)?;
+                Some(self.scan_loop(path))
+            }
+            GuiRequest::Compress { force, incremental } if self.info.is_some() => {
+                self.start_compress(force, incremental)
+            }
+            GuiRequest::Decompress { only } if self.info.is_some() => {
+                self.check_writable(&self.info.as_ref().unwrap().path)?;
+                match only.map(|s| s.parse::<Compression>()).transpose() {
+                    Ok(only) => Some(self.uncompress_loop(only)),
+                    Err(()) => {
+                        self.gui
+                            .status("Unknown compression to decompress", Some(1.0));
+                        None
+                    }
                 }
-                Err(_) => {
-                    eprintln!("Backend: exit run loop");
-                    break;
+            }
+            GuiRequest::UndoLast => {
+                let run = match self.last_run.clone() {
+                    Some(run) => run,
+                    None => {
+                        self.gui.status("Nothing to undo", Some(1.0));
+                        return None;
+                    }
+                };
+                self.check_writable(&run.folder)?;
+                self.confirm_undo(&run)?;
+
+                let folder = run.folder.clone();
+                let outcome = self.undo_loop(run);
+
+                // Show what the folder looks like now it's back.
+                if outcome != Outcome::Stopped && folder.exists() {
+                    self.gui.folder(&folder);
+                    self.disk_info(&folder);
+                    self.scan_loop(folder);
+                }
+
+                Some(outcome)
+            }
+            GuiRequest::DecompressAllTracked => self.decompress_tracked_loop(),
+            GuiRequest::DecompressFromManifest { path } => self.manifest_loop(path),
+            GuiRequest::SetExcludedSubfolders { paths } => {
+                self.excluded_subfolders = paths;
+                None
+            }
+            GuiRequest::CopySummary => {
+                match &self.last_summary {
+                    Some(summary) => match clipboard_win::set_clipboard_string(summary) {
+                        Ok(()) => self
+                            .gui
+                            .status(format!("Copied to clipboard: {}", summary), Some(1.0)),
+                        Err(e) => self
+                            .gui
+                            .status(format!("Error copying to clipboard: {}", e), Some(1.0)),
+                    },
+                    None => self.gui.status("No compression results to copy", Some(1.0)),
+                }
+                None
+            }
+            GuiRequest::EstimateCompression { compression } if self.info.is_some() => {
+                Some(self.estimate_loop(compression.parse().unwrap_or_default()))
+            }
+            GuiRequest::Benchmark if self.info.is_some() => Some(self.benchmark_loop()),
+            GuiRequest::PreviewExcludes if self.info.is_some() => {
+                let path = &self.info.as_ref().unwrap().path;
+                match config_for(path).globset() {
+                    Ok(excludes) => self.gui.exclude_preview(folder::preview_excludes(
+                        path,
+                        &excludes,
+                        EXCLUDE_PREVIEW_SAMPLE,
+                    )),
+                    Err(e) => self.gui.error("Invalid excludes", &e),
+                }
+                None
+            }
+            GuiRequest::TestConfig => {
+                self.test_config();
+                None
+            }
+            GuiRequest::ExportReport { path } if self.info.is_some() => {
+                let report = self.info.as_ref().unwrap().report(REPORT_LARGEST_FILES);
+
+                match report.write(&path) {
+                    Ok(()) => self.gui.report_exported(&path),
+                    Err(e) => self
+                        .gui
+                        .error("Error exporting report", &CompactorError::path(&path, e)),
+                }
+
+                None
+            }
+            GuiRequest::ExportCsv { path } if self.info.is_some() => {
+                match self.info.as_ref().unwrap().write_csv(&path) {
+                    Ok(()) => self.gui.report_exported(&path),
+                    Err(e) => self
+                        .gui
+                        .error("Error exporting file list", &CompactorError::path(&path, e)),
+                }
+
+                None
+            }
+            GuiRequest::ExportTreemap { path } if self.info.is_some() => {
+                match self.info.as_ref().unwrap().write_treemap(&path) {
+                    Ok(()) => self.gui.report_exported(&path),
+                    Err(e) => self
+                        .gui
+                        .error("Error exporting treemap", &CompactorError::path(&path, e)),
                 }
+
+                None
+            }
+            GuiRequest::QueueFolder { path } => {
+                self.enqueue(path);
+                None
+            }
+            GuiRequest::Stop => {
+                self.clear_queue();
+                None
+            }
+            msg => {
+                log::warn!("Backend: Ignored message: {:?}", msg);
+                None
             }
         }
     }
 
-    fn scan_loop(&mut self, path: PathBuf) {
-        let excludes = config().read().unwrap().current().globset().expect("globs");
+    /// Select and scan a new folder, or a single file.
+    fn open_folder(&mut self, path: PathBuf) -> Option<Outcome> {
+        let path = match compact::expand_env(&path) {
+            Ok(path) => path,
+            Err(e) => {
+                self.gui.error("Can't open folder", &e);
+                self.gui.folder_cancelled();
+                return None;
+            }
+        };
+        // Choosers can hand back `\\?\` paths, which wouldn't match profiles.
+        let path = compact::normal_path(path);
+        self.gui.folder(&path);
+        self.gui.config(config_for(&path));
+        self.disk_info(&path);
+        self.check_network(&path);
+        self.check_filesystem(&path)?;
+        self.check_protected(&path)?;
+        Some(self.scan_loop(path))
+    }
+
+    pub fn info(&self) -> Option<&FolderInfo> {
+        self.info.as_ref()
+    }
 
-        let scanner = FolderScan::new(path, excludes);
+    fn scan_loop(&mut self, path: PathBuf) -> Outcome {
+        // A fresh breakdown means a fresh selection.
+        self.excluded_subfolders.clear();
+        let conf = config_for(&path);
+        let excludes = conf.globset().expect("globs");
+        let includes = conf.include_globset().expect("globs");
+        log::info!("Scanning {}", path.display());
+
+        let scanner = FolderScan::new(path, excludes, conf.compression)
+            .include_only(includes)
+            .any_algorithm(conf.auto_compression)
+            .include_readonly(conf.clear_readonly)
+            .compress_sparse(conf.compress_sparse)
+            .size_range(conf.min_size, conf.max_size)
+            .max_depth(conf.max_depth)
+            .follow_links(conf.follow_links)
+            .sorted(conf.sorted_walk)
+            .skip_hidden(conf.skip_hidden)
+            .skip_system(conf.skip_system);
         let task = BackgroundHandle::spawn(scanner);
         let start = Instant::now();
+        let status_interval = conf.status_interval();
+        let mut last_status = start;
+        let mut last_summary = start;
 
+        self.gui.phase("scanning", 0, 0);
         self.gui.status("Scanning", None);
         loop {
             let msg = self.msg.recv_timeout(Duration::from_millis(25));
 
             match msg {
-                Ok(GuiRequest::Pause) => {
+                Ok(GuiRequest::Pause) if !task.is_paused() => {
                     task.pause();
                     self.gui.status("Paused", Some(0.5));
                     self.gui.paused();
                 }
-                Ok(GuiRequest::Resume) => {
+                Ok(GuiRequest::Resume) if task.is_paused() => {
                     task.resume();
                     self.gui.status("Scanning", None);
                     self.gui.resumed();
@@ -96,8 +1034,9 @@ impl<T> Backend<T> {
                 Ok(GuiRequest::Stop) | Err(RecvTimeoutError::Disconnected) => {
                     task.cancel();
                 }
+                Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
                 Ok(msg) => {
-                    eprintln!("Ignored message: {:?}", msg);
+                    log::warn!("Ignored message: {:?}", msg);
                 }
                 Err(RecvTimeoutError::Timeout) => (),
             }
@@ -107,9 +1046,10 @@ impl<T> Backend<T> {
                     self.gui
                         .status(format!("Scanned in {:.2?}", start.elapsed()), Some(1.0));
                     self.gui.summary(info.summary());
+                    self.gui.subfolder_breakdown(info.subfolders());
                     self.gui.scanned();
                     self.info = Some(info);
-                    break;
+                    break Outcome::Completed;
                 }
                 Some(Err(info)) => {
                     self.gui.status(
@@ -117,229 +1057,1318 @@ impl<T> Backend<T> {
                         Some(0.5),
                     );
                     self.gui.summary(info.summary());
+                    self.gui.subfolder_breakdown(info.subfolders());
                     self.gui.stopped();
                     self.info = Some(info);
-                    break;
+                    break Outcome::Stopped;
                 }
                 None => {
-                    if let Some(status) = task.status() {
-                        self.gui
-                            .status(format!("Scanning: {}", status.0.display()), None);
-                        self.gui.summary(status.1);
+                    if let Some((path, summary)) = task.status() {
+                        // A status set just before pausing shouldn't clobber "Paused"
+                        if !task.is_paused() && last_status.elapsed() >= status_interval {
+                            self.gui
+                                .status(format!("Scanning: {}", path.display()), None);
+                            last_status = Instant::now();
+                        }
+                        let found = summary.compressible.count
+                            + summary.compressed.count
+                            + summary.skipped.count
+                            + summary.size_skipped.count;
+                        self.gui.phase("scanning", found as u64, 0);
+
+                        if last_summary.elapsed() >= SUMMARY_INTERVAL {
+                            self.gui.summary(summary);
+                            last_summary = Instant::now();
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn estimate_loop(&mut self, compression: Compression) -> Outcome {
+        let folder = self.info.as_ref().expect("fileinfo");
+        let decimal = config_for(&folder.path).decimal;
+        let files = folder.compressible.files.iter().cloned().collect();
+        let estimator = Estimator::new(folder.base(), files, compression, ESTIMATE_MAX_BYTES);
+        let task = BackgroundHandle::spawn(estimator);
+        let start = Instant::now();
+        log::info!("Estimating {} for {}", compression, folder.path.display());
+
+        self.gui.compacting(false);
+        self.gui.phase("analysing", 0, 0);
+        self.gui.status(format!("Estimating {}", compression), None);
+        loop {
+            match self.msg.recv_timeout(Duration::from_millis(25)) {
+                Ok(GuiRequest::Pause) if !task.is_paused() => {
+                    task.pause();
+                    self.gui.status("Paused", Some(0.5));
+                    self.gui.paused();
+                }
+                Ok(GuiRequest::Resume) if task.is_paused() => {
+                    task.resume();
+                    self.gui.status(format!("Estimating {}", compression), None);
+                    self.gui.resumed();
+                }
+                Ok(GuiRequest::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    task.cancel();
+                }
+                Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
+                Ok(msg) => {
+                    log::warn!("Ignored message: {:?}", msg);
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+
+            match task.wait_timeout(Duration::from_millis(25)) {
+                Some(result) => {
+                    let outcome = if result.is_ok() {
+                        Outcome::Completed
+                    } else {
+                        Outcome::Stopped
+                    };
+                    let samples = result.unwrap_or_else(|samples| samples);
+                    let folder = self.info.as_ref().expect("fileinfo");
+                    let (projected_saved, sample_ratio) =
+                        estimate::project(&samples, &folder.compressible.files);
+
+                    self.gui.status(
+                        format!(
+                            "Estimated {} would save {} ({:.2}x) in {:.2?}",
+                            compression,
+                            format_size(projected_saved, decimal),
+                            sample_ratio,
+                            start.elapsed()
+                        ),
+                        Some(1.0),
+                    );
+                    self.gui.estimate(projected_saved, sample_ratio);
+                    self.gui.scanned();
+                    break outcome;
+                }
+                None => {
+                    if let Some((path, sampled, total)) = task.status() {
+                        if !task.is_paused() {
+                            self.gui
+                                .status(format!("Estimating: {}", path.display()), None);
+                        }
+                        self.gui.phase("analysing", sampled, total);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Sanity check the settings for the current folder, or the global ones if
+    /// there isn't one, by compressing a scratch file in the temp folder.
+    fn test_config(&self) {
+        let conf = match &self.info {
+            Some(folder) => config_for(&folder.path),
+            None => persistence::config().read().unwrap().current(),
+        };
+
+        if let Err(e) = conf.globset().and_then(|_| conf.include_globset()) {
+            self.gui.error("Settings test failed", &e);
+            return;
+        }
+
+        self.gui
+            .status(format!("Testing {} compression", conf.compression), None);
+
+        let dir = std::env::temp_dir();
+        match compression::self_test(&dir, conf.compression) {
+            Ok((size, compressed)) => {
+                log::info!("Compression test passed with {}", conf.compression);
+                self.gui.status(
+                    format!(
+                        "Settings test passed: {} compressed a {} test file to {}, and it decompressed intact",
+                        conf.compression,
+                        format_size(size, conf.decimal),
+                        format_size(compressed, conf.decimal)
+                    ),
+                    Some(1.0),
+                );
+            }
+            Err(e) => {
+                let e = CompactorError::wof(&dir, e);
+                log::warn!("Compression test failed with {}: {}", conf.compression, e);
+                self.gui.error("Settings test failed", &e);
+            }
+        }
+    }
+
+    fn benchmark_loop(&mut self) -> Outcome {
+        let folder = self.info.as_ref().expect("fileinfo");
+        let files = folder.compressible.files.iter().cloned().collect();
+        let task =
+            BackgroundHandle::spawn(Benchmark::new(folder.base(), files, BENCHMARK_MAX_BYTES));
+        let start = Instant::now();
+        log::info!("Benchmarking {}", folder.path.display());
+
+        self.gui.compacting(false);
+        self.gui.phase("analysing", 0, 0);
+        self.gui.status("Benchmarking", None);
+        loop {
+            match self.msg.recv_timeout(Duration::from_millis(25)) {
+                Ok(GuiRequest::Pause) if !task.is_paused() => {
+                    task.pause();
+                    self.gui.status("Paused", Some(0.5));
+                    self.gui.paused();
+                }
+                Ok(GuiRequest::Resume) if task.is_paused() => {
+                    task.resume();
+                    self.gui.status("Benchmarking", None);
+                    self.gui.resumed();
+                }
+                Ok(GuiRequest::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                    task.cancel();
+                }
+                Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
+                Ok(msg) => {
+                    log::warn!("Ignored message: {:?}", msg);
+                }
+                Err(RecvTimeoutError::Timeout) => (),
+            }
+
+            match task.wait_timeout(Duration::from_millis(25)) {
+                Some(result) => {
+                    let outcome = if result.is_ok() {
+                        Outcome::Completed
+                    } else {
+                        Outcome::Stopped
+                    };
+                    let results = result.unwrap_or_else(|results| results);
+
+                    self.gui
+                        .status(format!("Benchmarked in {:.2?}", start.elapsed()), Some(1.0));
+                    self.gui.benchmark_result(
+                        results
+                            .iter()
+                            .map(|(c, m)| (c.to_string(), m.ratio(), m.throughput()))
+                            .collect(),
+                    );
+                    self.gui.scanned();
+                    break outcome;
+                }
+                None => {
+                    if let Some((path, sampled, total)) = task.status() {
+                        if !task.is_paused() {
+                            self.gui
+                                .status(format!("Benchmarking: {}", path.display()), None);
+                        }
+                        self.gui.phase("analysing", sampled, total);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Fingerprint the files about to be compressed, or `None` if stopped.
+    fn take_snapshot(
+        &mut self,
+        folder: &FolderInfo,
+        status_interval: Duration,
+    ) -> Option<Snapshot> {
+        let total = folder.compressible.files.len() as u64;
+        let mut snapshot = Snapshot::default();
+        let mut last_update = Instant::now();
+
+        self.gui.phase("analysing", 0, total);
+        self.gui.status("Fingerprinting files", Some(0.0));
+        for (done, fi) in folder.compressible.files.iter().enumerate() {
+            if let Err(e) = snapshot.record(folder.base(), &fi.path) {
+                log::warn!("Unable to fingerprint {}: {}", fi.path.display(), e);
+            }
+
+            if last_update.elapsed() >= status_interval {
+                self.gui
+                    .status("Fingerprinting files", Some(progress(done as u64, total)));
+                self.gui.phase("analysing", done as u64, total);
+                last_update = Instant::now();
+            }
+
+            match self.msg.try_recv() {
+                Ok(GuiRequest::Stop) => return None,
+                Ok(GuiRequest::Pause) => {
+                    self.gui
+                        .status("Paused", Some(progress(done as u64, total)));
+                    self.gui.paused();
+                    loop {
+                        match self.msg.recv() {
+                            Ok(GuiRequest::Resume) => break,
+                            Ok(GuiRequest::Stop) | Err(_) => return None,
+                            Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
+                            Ok(_) => (),
+                        }
+                    }
+                    self.gui
+                        .status("Fingerprinting files", Some(progress(done as u64, total)));
+                    self.gui.resumed();
+                }
+                // There's nothing being compressed yet to skip.
+                Ok(GuiRequest::SkipFile) => (),
+                Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
+                _ => (),
+            }
+        }
+
+        log::info!("Fingerprinted {} files", snapshot.len());
+        Some(snapshot)
+    }
+
+    // Ph'nglui mglw'nafh Cthulhu R'lyeh wgah'nagl fhtagn.
+    fn compress_loop(&mut self, force: bool, incremental: bool) -> Outcome {
+        let mut folder = self.info.take().expect("fileinfo");
+        let conf = config_for(&folder.path);
+        let status_interval = conf.status_interval();
+        let threads = conf.worker_threads();
+        let run_start = SystemTime::now();
+        let before = folder.summary();
+
+        self.gui.phase("analysing", 0, 0);
+
+        let _priority = lower_priority(conf.priority);
+
+        let backup = conf.backup_fallback && {
+            match privilege::enable_backup() {
+                Ok(()) => true,
+                Err(e) => {
+                    self.gui.warning(format!(
+                        "Files in use will be skipped, as the backup privilege is unavailable: {}",
+                        e
+                    ));
+                    false
+                }
+            }
+        };
+
+        let (send_file, send_file_rx) = bounded::<FileJob>(threads);
+        let (recv_result_tx, recv_result) = bounded::<FileResult>(threads);
+
+        if force {
+            while let Some(fi) = folder.pop(FileKind::Compressed) {
+                folder.push(FileKind::Compressible, fi);
+            }
+        }
+
+        // LZX is slow, so say so if there's little left for it to do.
+        if conf.compression == Compression::Lzx
+            && !conf.auto_compression
+            && mostly_compressed(&folder.summary())
+        {
+            self.gui.warning(
+                "Most of this folder is already compressed, so LZX is unlikely to save much more",
+            );
+        }
+
+        let mut stale = 0;
+        let since = if incremental {
+            persistence::last_compressed(&folder.path)
+        } else {
+            None
+        };
+        if let Some(since) = since {
+            let root = folder.base().to_path_buf();
+            stale = skip_compressible(&mut folder, |fi| {
+                !changed_since(&root.join(&fi.path), since)
+            });
+        }
+
+        // Whatever the user's excludes say, leave Windows alone.
+        let mut protected = 0;
+        let guard = Protected::for_volume(&folder.path);
+        if !conf.allow_protected && guard.overlaps(&folder.path) {
+            let root = folder.base().to_path_buf();
+            protected = skip_compressible(&mut folder, |fi| guard.contains(root.join(&fi.path)));
+        }
+
+        let mut deselected = 0;
+        let excluded_subfolders = std::mem::take(&mut self.excluded_subfolders);
+        if !excluded_subfolders.is_empty() {
+            let root = folder.base().to_path_buf();
+            deselected = skip_compressible(&mut folder, |fi| {
+                in_subfolders(&root, &fi.path, &excluded_subfolders)
+            });
+        }
+
+        // For archiving, leave alone files which are still being worked on.
+        let mut too_new = 0;
+        let cutoff = conf
+            .min_age()
+            .and_then(|age| SystemTime::now().checked_sub(age));
+        if let Some(cutoff) = cutoff {
+            too_new = skip_too_new(&mut folder, cutoff);
+        }
+
+        order_compressible(&mut folder, conf.order);
+
+        // Taken after the skipping above, so it covers exactly what's compressed.
+        let mut stopped = false;
+        let snapshot = if conf.verify_folder {
+            self.gui.compacting(true);
+            let snapshot = self.take_snapshot(&folder, status_interval);
+            stopped = snapshot.is_none();
+            snapshot
+        } else {
+            None
+        };
+
+        let compression = Some(conf.compression);
+        let compactor = BackgroundCompactor::new(compression, send_file_rx, recv_result_tx)
+            .threads(threads)
+            .options(CompactOptions {
+                backup,
+                ..CompactOptions::from(&conf)
+            });
+        let task = BackgroundHandle::spawn(compactor);
+        let start = Instant::now();
+        log::info!(
+            "Compressing {} (force: {}, incremental: {}) with {:?}",
+            folder.path.display(),
+            force,
+            incremental,
+            conf
+        );
+
+        let total = folder.len(FileKind::Compressible);
+        let mut done = 0;
+        let mut compressed = 0;
+        // Full paths of the files compressed here, to be able to undo it.
+        let mut compressed_paths = Vec::new();
+        // The same, relative to the folder, and how, for its manifest.
+        let mut manifest_entries = Vec::new();
+        let mut retried = 0;
+        let mut errors = ErrorLog::default();
+
+        // Files handed to the compactor, by full path, awaiting a result.
+        let mut in_flight: HashMap<PathBuf, FileInfo> = HashMap::with_capacity(threads);
+        // Files the user skipped while in flight, whose results are unwanted.
+        let mut abandoned: HashSet<PathBuf> = HashSet::new();
+        let mut user_skipped = 0;
+        // Files decompressed again for having grown.
+        let mut reverted = 0;
+        // Files estimated to save less than `min_savings_pct`.
+        let mut threshold_skipped = 0;
+        // How many files automatic mode gave each algorithm, and why.
+        let mut choices: BTreeMap<auto::Choice, usize> = BTreeMap::new();
+
+        let mut last_update = Instant::now();
+        let mut last_file_update = Instant::now();
+        let mut last_write = Instant::now();
+        let mut paused = false;
+        // Paused because we went onto battery, rather than by request.
+        let mut battery_paused = false;
+        let mut power = PowerWatch::new(conf.pause_on_battery);
+        // Files not found in a row, and whether that's because the folder's gone.
+        let mut missing = 0;
+        let mut vanished = false;
+        // Stopped by running out of time, rather than by request.
+        let time_limit = conf.max_runtime_secs.map(Duration::from_secs);
+        let mut timed_out = false;
+
+        let old_size = folder.physical_size;
+        let compressible_size = folder.summary().compressible.physical_size;
+        let mut remaining_bytes = folder.summary().compressible.logical_size;
+        let total_bytes = remaining_bytes;
+        let mut done_bytes = 0;
+        let mut throughput = Throughput::new(THROUGHPUT_WINDOW);
+        let mut rate = Rate::new(RATE_WINDOW);
+        let mut throttle = Throttle::new(conf.throughput_limit_bytes());
+
+        let incompressible = pathdb();
+        let mut incompressible = incompressible.write().unwrap();
+        let _ = incompressible.load();
+
+        self.gui.compacting(true);
+        self.gui.phase("compacting", 0, total as u64);
+
+        self.gui.status("Compacting".to_string(), Some(0.0));
+        loop {
+            // Pauses take effect once the files already underway are done.
+            while paused && !stopped && in_flight.is_empty() {
+                self.gui.status(
+                    if battery_paused {
+                        "Paused while running on battery"
+                    } else {
+                        "Paused"
+                    },
+                    Some(progress(done_bytes, total_bytes)),
+                );
+
+                self.gui.summary(folder.summary());
+
+                // Wake up now and then to see if the power's back.
+                let resume = match self.msg.recv_timeout(Duration::from_secs(1)) {
+                    Ok(GuiRequest::Pause) => {
+                        battery_paused = false;
+                        false
+                    }
+                    Ok(GuiRequest::Resume) => true,
+                    Ok(GuiRequest::Stop) | Err(RecvTimeoutError::Disconnected) => {
+                        stopped = true;
+                        break;
+                    }
+                    Ok(GuiRequest::QueueFolder { path }) => {
+                        self.enqueue(path);
+                        false
                     }
+                    Ok(_) => false,
+                    Err(RecvTimeoutError::Timeout) => {
+                        battery_paused && power.changed() == Some(false)
+                    }
+                };
+
+                if resume {
+                    if battery_paused {
+                        log::info!("Resuming on mains power");
+                    }
+                    self.gui.status(
+                        "Compacting".to_string(),
+                        Some(progress(done_bytes, total_bytes)),
+                    );
+                    self.gui.resumed();
+                    paused = false;
+                    battery_paused = false;
+                    last_update = Instant::now();
+                    throughput.restart_clock();
+                    rate.restart_clock();
+                    throttle.restart_clock();
                 }
             }
+
+            if last_write.elapsed() > Duration::from_secs(60) {
+                let _ = incompressible.save();
+                last_write = Instant::now();
+            }
+
+            while !paused && !stopped && in_flight.len() < threads && throttle.ready() {
+                match folder.pop(FileKind::Compressible) {
+                    Some(fi) => {
+                        throttle.record(fi.logical_size);
+                        let path = folder.full_path(&fi);
+                        let chosen = if conf.auto_compression {
+                            // Unreadable files will fail in the compactor anyway.
+                            let choice =
+                                auto::choose(&path, fi.logical_size).unwrap_or(auto::Choice::Other);
+                            *choices.entry(choice).or_insert(0) += 1;
+                            Some(choice.compression())
+                        } else {
+                            None
+                        };
+                        send_file
+                            .send((path.clone(), fi.logical_size, chosen))
+                            .expect("send_file");
+
+                        if last_file_update.elapsed() >= CURRENT_FILE_INTERVAL {
+                            let index = done + in_flight.len() + 1;
+                            self.gui.current_file(&path, index as u64, total as u64);
+                            last_file_update = Instant::now();
+                        }
+
+                        in_flight.insert(path, fi);
+                    }
+                    None => break,
+                }
+            }
+
+            if in_flight.is_empty() {
+                if paused && !stopped {
+                    continue;
+                }
+
+                // Held back by the throttle, so wait on results timing out
+                // below, still listening for requests.
+                let throttled = !paused && !stopped && folder.len(FileKind::Compressible) > 0;
+                if !throttled {
+                    break;
+                }
+            }
+
+            if let Ok((path, result, retries, below_threshold)) =
+                recv_result.recv_timeout(Duration::from_millis(25))
+            {
+                if abandoned.remove(&path) {
+                    undo_skipped(&path, result);
+                    continue;
+                }
+
+                let mut fi = in_flight.remove(&path).expect("in-flight file");
+                done += 1;
+                if retries > 0 && result.is_ok() {
+                    retried += 1;
+                }
+                remaining_bytes -= fi.logical_size;
+                done_bytes += fi.logical_size;
+                throughput.record(fi.logical_size);
+                rate.record(fi.logical_size);
+                if result.is_ok() {
+                    missing = 0;
+                }
+                match result {
+                    Ok(true) => {
+                        let (old_physical, was_compressed) =
+                            (fi.physical_size, fi.compression.is_some());
+                        fi.physical_size =
+                            compact::compressed_size(&path).unwrap_or(fi.physical_size);
+                        fi.compression =
+                            wof::query_compression(compact::long_path(&path)).unwrap_or(None);
+
+                        // Irritatingly Windows can return success when it fails.
+                        if fi.physical_size == fi.logical_size {
+                            incompressible.insert(path);
+                            folder.push(FileKind::Skipped, fi);
+                        } else if conf.revert_if_larger
+                            // Decompressing one which was already compressed
+                            // would only make it bigger still.
+                            && !was_compressed
+                            && fi.physical_size >= old_physical
+                        {
+                            match wof::decompress_file(compact::long_path(&path)) {
+                                Ok(()) => {
+                                    log::debug!("Reverted {}, which didn't shrink", path.display());
+                                    reverted += 1;
+                                    fi.physical_size =
+                                        compact::compressed_size(&path).unwrap_or(fi.logical_size);
+                                    fi.compression = None;
+                                }
+                                Err(e) => {
+                                    errors.record(&fi.path, &e);
+                                }
+                            }
+                            incompressible.insert(path);
+                            folder.push(FileKind::Skipped, fi);
+                        } else {
+                            compressed += 1;
+                            compressed_paths.push(path);
+                            if conf.write_manifest {
+                                manifest_entries.push(ManifestEntry {
+                                    path: fi.path.clone(),
+                                    compression: fi.compression,
+                                });
+                            }
+                            folder.push(FileKind::Compressed, fi);
+                        }
+                    }
+                    // Not incompressible, so worth another look if the
+                    // threshold's changed.
+                    Ok(false) if below_threshold => {
+                        threshold_skipped += 1;
+                        folder.push(FileKind::Skipped, fi);
+                    }
+                    Ok(false) => {
+                        incompressible.insert(path);
+                        folder.push(FileKind::Skipped, fi);
+                    }
+                    Err(err) => {
+                        if err.kind() == io::ErrorKind::NotFound {
+                            missing += 1;
+                        } else {
+                            missing = 0;
+                        }
+
+                        if !vanished && missing >= VANISHED_AFTER && !folder.path.exists() {
+                            log::warn!("{} disappeared while compressing", folder.path.display());
+                            vanished = true;
+                            stopped = true;
+                        }
+
+                        // Everything left will fail the same way, so don't list it.
+                        if !vanished {
+                            errors.record(&fi.path, &err);
+                            self.gui.status(
+                                format!("Error: {}, {}", describe_error(&err), fi.path.display()),
+                                Some(progress(done_bytes, total_bytes)),
+                            );
+                        }
+                        folder.push(FileKind::Skipped, fi);
+                    }
+                }
+
+                if last_update.elapsed() >= status_interval {
+                    self.gui.phase("compacting", done as u64, total as u64);
+                    self.gui.summary(folder.summary());
+                }
+            }
+
+            // Kept up while waiting on files too, so a stall shows.
+            if !paused && !stopped && last_update.elapsed() >= status_interval {
+                self.gui.status_progress(
+                    "Compacting",
+                    Some(progress(done_bytes, total_bytes)),
+                    throughput.eta(remaining_bytes),
+                    rate.mib_per_sec(),
+                );
+                last_update = Instant::now();
+            }
+
+            if !stopped && time_limit.map_or(false, |limit| start.elapsed() >= limit) {
+                log::info!("Time limit reached");
+                self.gui.status(
+                    "Time limit reached, stopping after current files",
+                    Some(progress(done_bytes, total_bytes)),
+                );
+                stopped = true;
+                timed_out = true;
+            }
+
+            match power.changed() {
+                Some(true) if !paused && !stopped => {
+                    log::info!("Pausing on battery power");
+                    self.gui.status(
+                        "Running on battery, pausing after current files",
+                        Some(progress(done_bytes, total_bytes)),
+                    );
+                    self.gui.paused();
+                    paused = true;
+                    battery_paused = true;
+                }
+                // Back before the files underway finished.
+                Some(false) if battery_paused => {
+                    self.gui.resumed();
+                    paused = false;
+                    battery_paused = false;
+                }
+                _ => (),
+            }
+
+            match self.msg.try_recv() {
+                Ok(GuiRequest::Pause) if !paused => {
+                    self.gui.status(
+                        "Pausing after current files",
+                        Some(progress(done_bytes, total_bytes)),
+                    );
+                    self.gui.paused();
+                    paused = true;
+                }
+                Ok(GuiRequest::Resume) => {
+                    self.gui.resumed();
+                    paused = false;
+                    battery_paused = false;
+                    // There's no resuming a folder that isn't there.
+                    stopped = vanished;
+                }
+                Ok(GuiRequest::Stop) if !stopped => {
+                    log::info!("Stop requested");
+                    self.gui.status(
+                        "Stopping after current files",
+                        Some(progress(done_bytes, total_bytes)),
+                    );
+                    stopped = true;
+                }
+                // The compactor can't be interrupted, so move on without it
+                // and put things back once it's done.
+                Ok(GuiRequest::SkipFile) if !in_flight.is_empty() => {
+                    for (path, fi) in in_flight.drain() {
+                        done += 1;
+                        remaining_bytes -= fi.logical_size;
+                        done_bytes += fi.logical_size;
+                        user_skipped += 1;
+                        folder.push(FileKind::Skipped, fi);
+                        abandoned.insert(path);
+                    }
+                    self.gui.status(
+                        "Skipping current files",
+                        Some(progress(done_bytes, total_bytes)),
+                    );
+                }
+                Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
+                // Anything else, like a second Compress, is dropped: one run at a time.
+                Ok(_) => (),
+                Err(_) => (),
+            }
+        }
+
+        drop(send_file);
+        task.wait();
+
+        for (path, result, _, _) in recv_result.try_iter() {
+            if abandoned.remove(&path) {
+                undo_skipped(&path, result);
+            }
+        }
+
+        let _ = incompressible.save();
+
+        // The usual summary would only describe the folder as it was.
+        if vanished {
+            self.gui.status(
+                format!(
+                    "Stopped: {} was deleted or moved while compressing",
+                    folder.path.display()
+                ),
+                Some(1.0),
+            );
+            let paths = errors.paths.iter().map(|p| folder.base().join(p)).collect();
+            self.gui.errors(errors.count, errors.sample, paths);
+            self.gui.stopped();
+            return Outcome::Partial;
+        }
+
+        // Which should never happen, so it's an error whatever else went on.
+        let mut changed = 0;
+        if let Some(snapshot) = &snapshot {
+            self.gui.status("Checking contents are unchanged", None);
+            for (path, err) in snapshot.changed(folder.base()) {
+                log::error!("{}: {}", path.display(), err);
+                errors.record(&path, &err);
+                changed += 1;
+            }
+        }
+
+        let new_size = folder.physical_size;
+
+        let verb = if stopped {
+            "Stopped after compacting"
+        } else {
+            "Compacted"
+        };
+        // Without reverting, files which grew can outweigh the savings.
+        let saving = if new_size > old_size {
+            format!(
+                "growing by {}",
+                format_size(new_size - old_size, conf.decimal)
+            )
+        } else {
+            format!("saving {}", format_size(old_size - new_size, conf.decimal))
+        };
+        let mut msg = format!(
+            "{} {} in {} files, {} in {:.2?}",
+            verb,
+            format_size(compressible_size, conf.decimal),
+            done,
+            saving,
+            start.elapsed()
+        );
+        if timed_out {
+            msg.push_str(", having reached the time limit");
+        }
+        if incremental {
+            msg.push_str(&format!(
+                " ({} new, {} unchanged since the last run)",
+                total, stale
+            ));
+        }
+        if protected > 0 {
+            msg.push_str(&format!(", skipping {} Windows system files", protected));
+        }
+        if deselected > 0 {
+            msg.push_str(&format!(
+                ", skipping {} files in deselected subfolders",
+                deselected
+            ));
+        }
+        if too_new > 0 {
+            msg.push_str(&format!(
+                ", skipping {} files modified in the last {} days",
+                too_new,
+                conf.min_age().unwrap_or_default().as_secs() / (24 * 60 * 60)
+            ));
+        }
+        if retried > 0 {
+            msg.push_str(&format!(", {} succeeded after retrying", retried));
+        }
+        if user_skipped > 0 {
+            msg.push_str(&format!(", {} skipped by request", user_skipped));
+        }
+        if changed > 0 {
+            msg.push_str(&format!(
+                ", but {} files read differently afterwards, which should never happen",
+                changed
+            ));
+        }
+        if threshold_skipped > 0 {
+            msg.push_str(&format!(
+                ", skipping {} files estimated to save less than {}%",
+                threshold_skipped, conf.min_savings_pct
+            ));
+        }
+        if reverted > 0 {
+            msg.push_str(&format!(
+                ", decompressing {} files which compression didn't make smaller",
+                reverted
+            ));
+        }
+        if !choices.is_empty() {
+            let choices: Vec<String> = choices
+                .iter()
+                .map(|(choice, count)| {
+                    format!("{} for {} {} files", choice.compression(), count, choice)
+                })
+                .collect();
+            msg.push_str(&format!(", choosing {}", choices.join(", ")));
+        }
+
+        self.gui
+            .status(msg, Some(progress(done_bytes, total_bytes)));
+        self.gui.summary(folder.summary());
+        let after = folder.summary();
+        if conf.notify_on_complete {
+            let title = format!(
+                "{} compressing {}",
+                if stopped { "Stopped" } else { "Finished" },
+                folder.path.display()
+            );
+            self.gui
+                .notify(&title, &describe_result(&before, &after, conf.decimal));
+        }
+        let algorithm = if conf.auto_compression {
+            "automatically chosen algorithms".to_string()
+        } else {
+            conf.compression.to_string()
+        };
+        self.last_summary = Some(summary_line(
+            compressed,
+            &before,
+            &after,
+            &algorithm,
+            conf.decimal,
+        ));
+        self.gui.compression_result(before, after, stopped);
+        let paths = errors.paths.iter().map(|p| folder.base().join(p)).collect();
+        self.gui.errors(errors.count, errors.sample, paths);
+
+        let outcome = Outcome::of(stopped, errors.count);
+        if outcome != Outcome::Stopped {
+            match persistence::record_stats(old_size.saturating_sub(new_size), compressed) {
+                Ok(stats) => self.gui.lifetime_stats(stats),
+                Err(e) => log::warn!("Error saving statistics: {}", e),
+            }
+        }
+
+        // Stopped runs are still worth undoing, but one which changed nothing
+        // shouldn't replace one which did.
+        if !compressed_paths.is_empty() {
+            self.remember_run(LastRun {
+                folder: folder.path.clone(),
+                compression: if conf.auto_compression {
+                    None
+                } else {
+                    Some(conf.compression)
+                },
+                finished: SystemTime::now(),
+                files: compressed_paths,
+                file_list: None,
+            });
+        }
+
+        // Timestamped from the start, so files changed during the run are
+        // picked up next time, or from the minimum age if that's earlier, so
+        // files too new this time are.  Files left out on purpose would look
+        // unchanged next time whatever the time, so it's left alone then.
+        let since = cutoff.map_or(run_start, |cutoff| cutoff.min(run_start));
+        if outcome == Outcome::Completed && deselected == 0 && user_skipped == 0 {
+            if let Err(e) = persistence::set_last_compressed(&folder.path, since) {
+                log::warn!("Error saving last compression time: {}", e);
+            }
+        }
+
+        if compressed > 0 {
+            if let Err(e) = persistence::set_compressed_folder(&folder.path, true) {
+                log::warn!("Error saving compressed folder: {}", e);
+            }
         }
+
+        if !manifest_entries.is_empty() {
+            let path = manifest::path_in(folder.base());
+            let written = Manifest::read_or_new(&path).and_then(|mut manifest| {
+                manifest.add(manifest_entries);
+                manifest.write(&path)
+            });
+            if let Err(e) = written {
+                self.gui.error("Error writing manifest", &e);
+            }
+        }
+
+        self.gui.scanned();
+
+        self.info = Some(folder);
+
+        outcome
     }
 
-    // Ph'nglui mglw'nafh Cthulhu R'lyeh wgah'nagl fhtagn.
-    fn compress_loop(&mut self) {
-        let (send_file, send_file_rx) = bounded::<(PathBuf, u64)>(1);
-        let (recv_result_tx, recv_result) = bounded::<(PathBuf, io::Result<bool>)>(1);
+    /// Save what's left to undo, if anything.
+    fn remember_run(&mut self, run: LastRun) {
+        let run = Some(run).filter(|run| !run.files.is_empty());
 
-        let compression = Some(config().read().unwrap().current().compression);
-        let compactor = BackgroundCompactor::new(compression, send_file_rx, recv_result_tx);
-        let task = BackgroundHandle::spawn(compactor);
+        if let Err(e) = persistence::set_last_run(run.clone()) {
+            log::warn!("Error saving last run: {}", e);
+        }
+        self.gui
+            .undo_available(run.as_ref().map(|run| run.folder.clone()));
+        self.last_run = run;
+    }
+
+    /// Decompress the files a compression run compressed, leaving any
+    /// recompressed with something else since.
+    fn undo_loop(&mut self, mut run: LastRun) -> Outcome {
         let start = Instant::now();
+        log::info!(
+            "Undoing compression of {} files in {} (compression: {:?})",
+            run.files.len(),
+            run.folder.display(),
+            run.compression
+        );
 
-        let mut folder = self.info.take().expect("fileinfo");
-        let total = folder.len(FileKind::Compressible);
-        let mut done = 0;
+        let _priority = lower_priority(config_for(&run.folder).priority);
+        let files = std::mem::take(&mut run.files);
+        let mut rollback = Rollback::new(files.len());
 
-        let mut last_update = Instant::now();
-        let mut last_write = Instant::now();
-        let mut paused = false;
-        let mut stopped = false;
+        self.gui.compacting(false);
+        self.gui.phase("compacting", 0, rollback.total);
+        self.gui.status("Undoing", Some(0.0));
 
-        let old_size = folder.physical_size;
-        let compressible_size = folder.summary().compressible.physical_size;
+        self.rollback_files(
+            &run.folder,
+            run.compression,
+            files,
+            &mut rollback,
+            "Undoing",
+        );
 
-        let incompressible = pathdb();
-        let mut incompressible = incompressible.write().unwrap();
-        let _ = incompressible.load();
+        let mut msg = format!(
+            "Decompressed {} files from the last run in {} in {:.2?}",
+            rollback.undone,
+            run.folder.display(),
+            start.elapsed()
+        );
+        let untouched = rollback.untouched();
+        if untouched > 0 {
+            msg.push_str(&format!(
+                ", leaving {} since decompressed or recompressed",
+                untouched
+            ));
+        }
+        if !rollback.remaining.is_empty() {
+            msg.push_str(&format!(", {} still to undo", rollback.remaining.len()));
+        }
 
-        self.gui.compacting();
+        self.gui
+            .status(msg, Some(progress(rollback.done, rollback.total)));
+        let errors = rollback.errors;
+        self.gui.errors(errors.count, errors.sample, errors.paths);
 
-        self.gui.status("Compacting".to_string(), Some(0.0));
-        loop {
-            while paused && !stopped {
-                self.gui
-                    .status("Paused".to_string(), Some(done as f32 / total as f32));
+        run.files = rollback.remaining;
+        self.remember_run(run);
+        self.gui.scanned();
 
-                self.gui.summary(folder.summary());
+        Outcome::of(rollback.stopped, errors.count)
+    }
 
-                match self.msg.recv() {
-                    Ok(GuiRequest::Pause) => {
-                        paused = true;
-                    }
-                    Ok(GuiRequest::Resume) => {
-                        self.gui
-                            .status("Compacting".to_string(), Some(done as f32 / total as f32));
-                        self.gui.resumed();
-                        paused = false;
-                        last_update = Instant::now();
-                    }
-                    Ok(GuiRequest::Stop) => {
-                        stopped = true;
-                        break;
-                    }
-                    Ok(_) => (),
-                    Err(_) => {
-                        stopped = true;
-                        break;
-                    }
-                }
+    /// Decompress exactly the files listed in a folder's manifest, leaving any
+    /// recompressed with something else since, and forgetting the rest.
+    ///
+    /// `path` may be the manifest itself or the folder it's in.
+    fn manifest_loop(&mut self, path: PathBuf) -> Option<Outcome> {
+        let (folder, path) = if path.is_dir() {
+            (path.clone(), manifest::path_in(&path))
+        } else {
+            (path.parent()?.to_path_buf(), path)
+        };
+
+        self.gui.phase("analysing", 0, 0);
+        let mut manifest = match Manifest::read(&path) {
+            Ok(manifest) => manifest,
+            Err(e) => {
+                self.gui.error("Error reading manifest", &e);
+                return None;
             }
+        };
+        self.check_writable(&folder)?;
 
-            if stopped {
-                break;
-            }
+        let start = Instant::now();
+        log::info!(
+            "Decompressing {} files listed in {}",
+            manifest.files.len(),
+            path.display()
+        );
 
-            if last_write.elapsed() > Duration::from_secs(60) {
-                let _ = incompressible.save();
-                last_write = Instant::now();
+        let _priority = lower_priority(config_for(&folder).priority);
+        let mut rollback = Rollback::new(manifest.files.len());
+        self.gui.folder(&folder);
+        self.gui.compacting(false);
+        self.gui.phase("compacting", 0, rollback.total);
+        self.gui.status("Rolling back", Some(0.0));
+
+        // The compactor only takes one algorithm to leave the rest alone, so
+        // go through them one at a time.
+        let algorithms = Compression::ALL.iter().map(|&c| Some(c)).chain(Some(None));
+        for compression in algorithms {
+            let files: Vec<PathBuf> = manifest
+                .files
+                .iter()
+                .filter(|e| e.compression == compression)
+                .map(|e| folder.join(&e.path))
+                .collect();
+
+            if rollback.stopped {
+                rollback.remaining.extend(files);
+            } else if !files.is_empty() {
+                self.rollback_files(&folder, compression, files, &mut rollback, "Rolling back");
             }
+        }
 
-            let mut displayed = false;
+        let remaining: HashSet<&PathBuf> = rollback.remaining.iter().collect();
+        manifest.remove(|e| !remaining.contains(&folder.join(&e.path)));
+        let saved = if manifest.files.is_empty() {
+            std::fs::remove_file(&path).map_err(|e| CompactorError::path(&path, e))
+        } else {
+            manifest.write(&path)
+        };
+        if let Err(e) = saved {
+            self.gui.error("Error updating manifest", &e);
+        }
 
-            if let Some(mut fi) = folder.pop(FileKind::Compressible) {
-                send_file
-                    .send((folder.path.join(&fi.path), fi.logical_size))
-                    .expect("send_file");
+        let mut msg = format!(
+            "Decompressed {} files from the manifest in {} in {:.2?}",
+            rollback.undone,
+            folder.display(),
+            start.elapsed()
+        );
+        let untouched = rollback.untouched();
+        if untouched > 0 {
+            msg.push_str(&format!(
+                ", leaving {} since decompressed or recompressed",
+                untouched
+            ));
+        }
+        if !manifest.files.is_empty() {
+            msg.push_str(&format!(", {} still to do", manifest.files.len()));
+        }
 
-                if !displayed && last_update.elapsed() > Duration::from_millis(50) {
-                    self.gui.status(
-                        format!("Compacting: {}", fi.path.display()),
-                        Some(done as f32 / total as f32),
-                    );
-                    last_update = Instant::now();
-                    displayed = true;
-                }
+        self.gui
+            .status(msg, Some(progress(rollback.done, rollback.total)));
+        let errors = rollback.errors;
+        self.gui.errors(errors.count, errors.sample, errors.paths);
+        self.gui.scanned();
 
-                loop {
-                    if let Ok((path, result)) = recv_result.recv_timeout(Duration::from_millis(25))
-                    {
-                        done += 1;
-                        match result {
-                            Ok(true) => {
-                                fi.physical_size = path.size_on_disk().unwrap_or(fi.physical_size);
-
-                                // Irritatingly Windows can return success when it fails.
-                                if fi.physical_size == fi.logical_size {
-                                    incompressible.insert(path);
-                                    folder.push(FileKind::Skipped, fi);
-                                } else {
-                                    folder.push(FileKind::Compressed, fi);
-                                }
-                            }
-                            Ok(false) => {
-                                incompressible.insert(path);
-                                folder.push(FileKind::Skipped, fi);
-                            }
-                            Err(err) => {
-                                self.gui.status(
-                                    format!("Error: {}, {}", err, fi.path.display()),
-                                    Some(done as f32 / total as f32),
-                                );
-                                folder.push(FileKind::Skipped, fi);
-                            }
-                        }
+        let outcome = Outcome::of(rollback.stopped, errors.count);
 
-                        if last_update.elapsed() > Duration::from_millis(50) {
-                            self.gui.summary(folder.summary());
-                        }
+        // Show what the folder looks like now it's back.
+        if outcome != Outcome::Stopped {
+            self.disk_info(&folder);
+            self.scan_loop(folder);
+        }
 
-                        break;
-                    }
+        Some(outcome)
+    }
 
-                    if !displayed && last_update.elapsed() > Duration::from_millis(50) {
+    /// Decompress a list of files, leaving any not compressed with `only`,
+    /// and adding to the progress so far.
+    fn rollback_files(
+        &mut self,
+        folder: &Path,
+        only: Option<Compression>,
+        files: Vec<PathBuf>,
+        rollback: &mut Rollback,
+        status: &str,
+    ) {
+        let (send_file, send_file_rx) = bounded::<FileJob>(1);
+        let (recv_result_tx, recv_result) = bounded::<FileResult>(1);
+
+        let conf = config_for(folder);
+        let status_interval = conf.status_interval();
+        let compactor = BackgroundCompactor::new(None, send_file_rx, recv_result_tx)
+            .only(only)
+            .options(CompactOptions {
+                backup: false,
+                ..CompactOptions::from(&conf)
+            });
+        let task = BackgroundHandle::spawn(compactor);
+        let mut last_update = Instant::now();
+        let mut paused = false;
+
+        let mut files = files.into_iter();
+        for path in files.by_ref() {
+            send_file.send((path.clone(), 0, None)).expect("send_file");
+
+            let result = loop {
+                if let Ok((_path, result, _, _)) =
+                    recv_result.recv_timeout(Duration::from_millis(25))
+                {
+                    break result;
+                }
+
+                match self.msg.try_recv() {
+                    Ok(GuiRequest::Stop) if !rollback.stopped => {
                         self.gui.status(
-                            format!("Compacting: {}", fi.path.display()),
-                            Some(done as f32 / total as f32),
+                            format!("Stopping after {}", path.display()),
+                            Some(progress(rollback.done, rollback.total)),
                         );
-
-                        displayed = true;
+                        rollback.stopped = true;
                     }
+                    Ok(GuiRequest::Pause) if !paused => {
+                        self.gui.status(
+                            format!("Pausing after {}", path.display()),
+                            Some(progress(rollback.done, rollback.total)),
+                        );
+                        self.gui.paused();
+                        paused = true;
+                    }
+                    Ok(GuiRequest::Resume) if paused => {
+                        self.gui.resumed();
+                        paused = false;
+                    }
+                    Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
+                    _ => (),
+                }
+            };
+
+            rollback.done += 1;
+            match result {
+                Ok(true) => rollback.undone += 1,
+                Ok(false) => (),
+                Err(err) => {
+                    rollback.errors.record(&path, &err);
+                    rollback.remaining.push(path);
+                }
+            }
 
-                    match self.msg.try_recv() {
-                        Ok(GuiRequest::Pause) if !paused => {
-                            self.gui.status(
-                                format!("Pausing after {}", fi.path.display()),
-                                Some(done as f32 / total as f32),
-                            );
-                            self.gui.paused();
-                            paused = true;
-                        }
-                        Ok(GuiRequest::Resume) => {
-                            self.gui.resumed();
-                            paused = false;
-                            stopped = false;
-                        }
-                        Ok(GuiRequest::Stop) if !stopped => {
-                            self.gui.status(
-                                format!("Stopping after {}", fi.path.display()),
-                                Some(done as f32 / total as f32),
-                            );
-                            stopped = true;
-                        }
-                        Ok(_) => (),
-                        Err(_) => (),
+            if last_update.elapsed() >= status_interval {
+                self.gui
+                    .status(status, Some(progress(rollback.done, rollback.total)));
+                self.gui.phase("compacting", rollback.done, rollback.total);
+                last_update = Instant::now();
+            }
+
+            // Pauses take effect between files.
+            if paused && !rollback.stopped {
+                self.gui
+                    .status("Paused", Some(progress(rollback.done, rollback.total)));
+            }
+            while paused && !rollback.stopped {
+                match self.msg.recv() {
+                    Ok(GuiRequest::Resume) => {
+                        self.gui
+                            .status(status, Some(progress(rollback.done, rollback.total)));
+                        self.gui.resumed();
+                        paused = false;
                     }
+                    Ok(GuiRequest::Stop) | Err(_) => rollback.stopped = true,
+                    Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
+                    Ok(_) => (),
                 }
-            } else {
+            }
+
+            if rollback.stopped {
                 break;
             }
         }
+        rollback.remaining.extend(files);
 
         drop(send_file);
         task.wait();
+    }
 
-        let _ = incompressible.save();
-
-        let new_size = folder.physical_size;
-        let decimal = config().read().unwrap().current().decimal;
+    /// Decompress every folder Compactor has compressed anything in, so it can
+    /// be uninstalled without leaving compressed files behind.
+    ///
+    /// Folders are forgotten once fully decompressed, so stopping partway
+    /// leaves the rest for next time.
+    fn decompress_tracked_loop(&mut self) -> Option<Outcome> {
+        let folders = persistence::compressed_folders();
+        if folders.is_empty() {
+            self.gui
+                .status("Compactor hasn't compressed any folders", Some(1.0));
+            return None;
+        }
 
-        let msg = format!(
-            "Compacted {} in {} files, saving {} in {:.2?}",
-            format_size(compressible_size, decimal),
-            done,
-            format_size(old_size - new_size, decimal),
-            start.elapsed()
+        let list: Vec<String> = folders.iter().map(|p| p.display().to_string()).collect();
+        let message = format!(
+            "Decompress everything in the {} folders Compactor has compressed?\n\n{}",
+            folders.len(),
+            list.join("\n")
         );
+        if self.gui.confirm("Decompress everything", &message).recv() != Ok(true) {
+            self.gui.status("Decompression cancelled", Some(1.0));
+            return None;
+        }
 
-        self.gui.status(msg, Some(done as f32 / total as f32));
-        self.gui.summary(folder.summary());
-        self.gui.scanned();
+        log::info!("Decompressing {} tracked folders", folders.len());
+        let total = folders.len();
+        let mut outcome = Outcome::Completed;
 
-        self.info = Some(folder);
+        for (index, path) in folders.into_iter().enumerate() {
+            if !path.exists() {
+                log::info!("Forgetting vanished folder {}", path.display());
+                if let Err(e) = persistence::set_compressed_folder(&path, false) {
+                    log::warn!("Error saving compressed folder: {}", e);
+                }
+                continue;
+            }
+
+            self.gui.tracked_folder(&path, index + 1, total);
+            if self.check_writable(&path).is_none() {
+                outcome = Outcome::Partial;
+                continue;
+            }
+
+            self.gui.folder(&path);
+            self.gui.config(config_for(&path));
+            self.disk_info(&path);
+            let result = match self.scan_loop(path.clone()) {
+                Outcome::Stopped => Outcome::Stopped,
+                _ => self.uncompress_loop(None),
+            };
+
+            match result {
+                Outcome::Completed => {
+                    if let Err(e) = persistence::set_compressed_folder(&path, false) {
+                        log::warn!("Error saving compressed folder: {}", e);
+                    }
+                }
+                Outcome::Partial => outcome = Outcome::Partial,
+                Outcome::Stopped => return Some(Outcome::Stopped),
+            }
+        }
+
+        Some(outcome)
     }
 
     // Oh no, not again.
-    fn uncompress_loop(&mut self) {
-        let (send_file, send_file_rx) = bounded::<(PathBuf, u64)>(1);
-        let (recv_result_tx, recv_result) = bounded::<(PathBuf, io::Result<bool>)>(1);
+    fn uncompress_loop(&mut self, only: Option<Compression>) -> Outcome {
+        let (send_file, send_file_rx) = bounded::<FileJob>(1);
+        let (recv_result_tx, recv_result) = bounded::<FileResult>(1);
 
-        let compactor = BackgroundCompactor::new(None, send_file_rx, recv_result_tx);
+        self.gui.phase("analysing", 0, 0);
+        let mut folder = self.info.take().expect("fileinfo");
+        let conf = config_for(&folder.path);
+        let status_interval = conf.status_interval();
+        let compactor = BackgroundCompactor::new(None, send_file_rx, recv_result_tx)
+            .only(only)
+            .options(CompactOptions {
+                backup: false,
+                ..CompactOptions::from(&conf)
+            });
         let task = BackgroundHandle::spawn(compactor);
+        let _priority = lower_priority(conf.priority);
         let start = Instant::now();
+        log::info!("Decompressing {} (only: {:?})", folder.path.display(), only);
 
-        let mut folder = self.info.take().expect("fileinfo");
         let total = folder.len(FileKind::Compressed);
+        let total_bytes = folder.summary().compressed.logical_size;
         let mut done = 0;
+        let mut done_bytes = 0;
+        let mut errors = ErrorLog::default();
+        let mut untouched = 0;
+        let mut retried = 0;
 
         let mut last_update = Instant::now();
+        let mut last_file_update = Instant::now();
         let mut paused = false;
         let mut stopped = false;
 
         let old_size = folder.physical_size;
 
-        self.gui.compacting();
+        self.gui.compacting(false);
+        self.gui.phase("compacting", 0, total as u64);
 
         self.gui.status("Expanding".to_string(), Some(0.0));
         loop {
             while paused && !stopped {
-                self.gui
-                    .status("Paused".to_string(), Some(done as f32 / total as f32));
+                self.gui.status(
+                    "Paused".to_string(),
+                    Some(progress(done_bytes, total_bytes)),
+                );
 
                 self.gui.summary(folder.summary());
 
@@ -348,8 +2377,10 @@ impl<T> Backend<T> {
                         paused = true;
                     }
                     Ok(GuiRequest::Resume) => {
-                        self.gui
-                            .status("Expanding".to_string(), Some(done as f32 / total as f32));
+                        self.gui.status(
+                            "Expanding".to_string(),
+                            Some(progress(done_bytes, total_bytes)),
+                        );
                         self.gui.resumed();
                         paused = false;
                         last_update = Instant::now();
@@ -358,6 +2389,7 @@ impl<T> Backend<T> {
                         stopped = true;
                         break;
                     }
+                    Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
                     Ok(_) => (),
                     Err(_) => {
                         stopped = true;
@@ -370,33 +2402,64 @@ impl<T> Backend<T> {
                 break;
             }
 
-            if last_update.elapsed() > Duration::from_millis(50) {
-                self.gui
-                    .status("Expanding".to_string(), Some(done as f32 / total as f32));
+            if last_update.elapsed() >= status_interval {
+                self.gui.status(
+                    "Expanding".to_string(),
+                    Some(progress(done_bytes, total_bytes)),
+                );
                 last_update = Instant::now();
 
                 self.gui.summary(folder.summary());
             }
 
-            if let Some(mut fi) = folder.pop(FileKind::Compressed) {
+            // Untouched files go back on the end of the queue, so stop once
+            // everything has been seen once.
+            let next = if done < total {
+                folder.pop(FileKind::Compressed)
+            } else {
+                None
+            };
+
+            if let Some(mut fi) = next {
+                let path = folder.full_path(&fi);
                 send_file
-                    .send((folder.path.join(&fi.path), fi.logical_size))
+                    .send((path.clone(), fi.logical_size, None))
                     .expect("send_file");
 
+                if last_file_update.elapsed() >= CURRENT_FILE_INTERVAL {
+                    self.gui.current_file(&path, done as u64 + 1, total as u64);
+                    last_file_update = Instant::now();
+                }
+
                 let mut waiting = false;
                 loop {
-                    if let Ok((_path, result)) = recv_result.recv_timeout(Duration::from_millis(25))
+                    if let Ok((_path, result, retries, _)) =
+                        recv_result.recv_timeout(Duration::from_millis(25))
                     {
                         done += 1;
+                        done_bytes += fi.logical_size;
+                        if retries > 0 && result.is_ok() {
+                            retried += 1;
+                        }
                         match result {
-                            Ok(_) => {
+                            Ok(true) => {
                                 fi.physical_size = fi.logical_size;
+                                fi.compression = None;
                                 folder.push(FileKind::Compressible, fi);
                             }
+                            Ok(false) => {
+                                untouched += 1;
+                                folder.push(FileKind::Compressed, fi);
+                            }
                             Err(err) => {
+                                errors.record(&fi.path, &err);
                                 self.gui.status(
-                                    format!("Error: {}, {}", err, fi.path.display()),
-                                    Some(done as f32 / total as f32),
+                                    format!(
+                                        "Error: {}, {}",
+                                        describe_error(&err),
+                                        fi.path.display()
+                                    ),
+                                    Some(progress(done_bytes, total_bytes)),
                                 );
                                 folder.push(FileKind::Skipped, fi);
                             }
@@ -405,11 +2468,10 @@ impl<T> Backend<T> {
                         break;
                     }
 
-                    if !waiting && last_update.elapsed() > Duration::from_millis(50) {
-                        self.gui.status(
-                            format!("Expanding: {}", fi.path.display()),
-                            Some(done as f32 / total as f32),
-                        );
+                    if !waiting && last_update.elapsed() >= status_interval {
+                        self.gui
+                            .status("Expanding", Some(progress(done_bytes, total_bytes)));
+                        self.gui.phase("compacting", done as u64, total as u64);
 
                         last_update = Instant::now();
                         waiting = true;
@@ -419,7 +2481,7 @@ impl<T> Backend<T> {
                         Ok(GuiRequest::Pause) if !paused => {
                             self.gui.status(
                                 format!("Pausing after {}", fi.path.display()),
-                                Some(done as f32 / total as f32),
+                                Some(progress(done_bytes, total_bytes)),
                             );
                             self.gui.paused();
                             paused = true;
@@ -432,10 +2494,11 @@ impl<T> Backend<T> {
                         Ok(GuiRequest::Stop) if !stopped => {
                             self.gui.status(
                                 format!("Stopping after {}", fi.path.display()),
-                                Some(done as f32 / total as f32),
+                                Some(progress(done_bytes, total_bytes)),
                             );
                             stopped = true;
                         }
+                        Ok(GuiRequest::QueueFolder { path }) => self.enqueue(path),
                         Ok(_) => (),
                         Err(_) => (),
                     }
@@ -450,20 +2513,340 @@ impl<T> Backend<T> {
 
         let new_size = folder.physical_size;
 
-        let msg = format!(
+        let mut msg = format!(
             "Expanded {} files wasting {} in {:.2?}",
-            done,
-            format_size(
-                new_size - old_size,
-                config().read().unwrap().current().decimal
-            ),
+            done - untouched,
+            format_size(new_size.saturating_sub(old_size), conf.decimal),
             start.elapsed()
         );
 
-        self.gui.status(msg, Some(done as f32 / total as f32));
+        if let (Some(only), true) = (only, untouched > 0) {
+            msg.push_str(&format!(
+                ", leaving {} files not compressed with {}",
+                untouched, only
+            ));
+        }
+        if retried > 0 {
+            msg.push_str(&format!(", {} succeeded after retrying", retried));
+        }
+
+        self.gui
+            .status(msg, Some(progress(done_bytes, total_bytes)));
         self.gui.summary(folder.summary());
+        let paths = errors.paths.iter().map(|p| folder.base().join(p)).collect();
+        self.gui.errors(errors.count, errors.sample, paths);
         self.gui.scanned();
 
         self.info = Some(folder);
+
+        Outcome::of(stopped, errors.count)
+    }
+}
+
+#[test]
+fn error_log_is_bounded() {
+    let mut log = ErrorLog::default();
+    let err = io::Error::new(io::ErrorKind::PermissionDenied, "Access is denied");
+
+    for _ in 0..ERROR_SAMPLE_SIZE + 10 {
+        log.record(Path::new("game.exe"), &err);
+    }
+
+    assert_eq!(log.count, ERROR_SAMPLE_SIZE + 10);
+    assert_eq!(log.sample.len(), ERROR_SAMPLE_SIZE);
+    assert_eq!(log.sample[0], "game.exe: Access is denied");
+    assert_eq!(log.paths.len(), ERROR_SAMPLE_SIZE);
+    assert_eq!(log.paths[0], Path::new("game.exe"));
+}
+
+#[test]
+fn throughput_estimates() {
+    let mut rate = Throughput::new(4);
+    assert_eq!(rate.eta(1000), None);
+
+    rate.record_with(100, Duration::from_secs(1));
+    rate.record_with(100, Duration::from_secs(1));
+    assert_eq!(rate.eta(1000), Some(10));
+
+    // Older samples fall out of the window
+    for _ in 0..4 {
+        rate.record_with(1000, Duration::from_secs(1));
+    }
+    assert_eq!(rate.eta(1000), Some(1));
+}
+
+#[test]
+fn it_measures_recent_throughput() {
+    let mut rate = Rate::new(Duration::from_secs(2));
+    let start = rate.start;
+    let mib = 1024 * 1024;
+    assert_eq!(rate.mib_per_sec_at(start), None);
+
+    // Early on, only the time so far counts.
+    rate.record_at(start, 3 * mib);
+    assert_eq!(
+        rate.mib_per_sec_at(start + Duration::from_secs(1)),
+        Some(3.0)
+    );
+
+    rate.record_at(start + Duration::from_secs(2), mib);
+    assert_eq!(
+        rate.mib_per_sec_at(start + Duration::from_secs(2)),
+        Some(2.0)
+    );
+
+    // A stall lets it fall away.
+    assert_eq!(
+        rate.mib_per_sec_at(start + Duration::from_secs(3)),
+        Some(0.5)
+    );
+    assert_eq!(
+        rate.mib_per_sec_at(start + Duration::from_secs(10)),
+        Some(0.0)
+    );
+}
+
+#[test]
+fn it_throttles_throughput() {
+    let mut throttle = Throttle::new(Some(1000));
+    assert!(throttle.ready_after(Duration::from_secs(0)));
+
+    throttle.record(2000);
+    assert!(!throttle.ready_after(Duration::from_secs(1)));
+    assert!(throttle.ready_after(Duration::from_secs(2)));
+
+    let mut unlimited = Throttle::new(Some(0));
+    unlimited.record(u64::max_value());
+    assert!(unlimited.ready_after(Duration::from_secs(0)));
+}
+
+#[test]
+fn it_spots_mostly_compressed_folders() {
+    use crate::folder::GroupSummary;
+
+    let group = |logical_size| GroupSummary {
+        count: 1,
+        logical_size,
+        physical_size: logical_size,
+    };
+    let summary = |compressed, compressible| FolderSummary {
+        compressed: group(compressed),
+        compressible: group(compressible),
+        ..FolderSummary::default()
+    };
+
+    assert!(!mostly_compressed(&summary(0, 0)));
+    assert!(!mostly_compressed(&summary(50, 50)));
+    assert!(mostly_compressed(&summary(95, 5)));
+}
+
+#[test]
+fn it_measures_progress_by_bytes() {
+    assert_eq!(progress(0, 0), 1.0);
+    assert_eq!(progress(0, 100), 0.0);
+    assert_eq!(progress(25, 100), 0.25);
+    assert_eq!(progress(100, 100), 1.0);
+}
+
+#[test]
+fn it_describes_compression_results() {
+    let summary = |physical_size| FolderSummary {
+        physical_size,
+        ..FolderSummary::default()
+    };
+
+    assert_eq!(
+        describe_result(&summary(4000), &summary(1000), true),
+        "Saved 3 KB (75.0%)"
+    );
+    assert_eq!(
+        describe_result(&summary(0), &summary(0), true),
+        "Saved 0 B (0.0%)"
+    );
+}
+
+#[test]
+fn it_summarises_compression_results() {
+    let summary = |physical_size| FolderSummary {
+        physical_size,
+        ..FolderSummary::default()
+    };
+
+    assert_eq!(format_count(0), "0");
+    assert_eq!(format_count(999), "999");
+    assert_eq!(format_count(8800), "8,800");
+    assert_eq!(format_count(1234567), "1,234,567");
+
+    assert_eq!(
+        summary_line(8800, &summary(10000), &summary(4000), "LZX", true),
+        "Compressed 8,800 files, saved 6 KB (60%) with LZX"
+    );
+}
+
+#[test]
+fn it_spots_changed_files() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("test.txt");
+    std::fs::write(&path, "hello").unwrap();
+
+    let before = SystemTime::now() - Duration::from_secs(3600);
+    let after = SystemTime::now() + Duration::from_secs(3600);
+
+    assert!(changed_since(&path, before));
+    assert!(!changed_since(&path, after));
+    assert!(changed_since(&dir.path().join("missing.txt"), after));
+}
+
+#[test]
+fn it_skips_files_too_new() {
+    use crate::testutil::file;
+
+    let now = SystemTime::now();
+    let day = Duration::from_secs(24 * 60 * 60);
+    let mut folder = FolderInfo::new("C:\\Games");
+    for (name, modified) in &[
+        ("old.pak", Some(now - day * 10)),
+        ("new.pak", Some(now)),
+        ("unknown.pak", None),
+    ] {
+        let fi = FileInfo {
+            modified: *modified,
+            ..file(name, 8192)
+        };
+        folder.push(FileKind::Compressible, fi);
+    }
+
+    assert_eq!(skip_too_new(&mut folder, now - day), 2);
+    assert_eq!(folder.len(FileKind::Compressible), 1);
+    assert_eq!(folder.len(FileKind::Skipped), 2);
+    assert_eq!(
+        folder.pop(FileKind::Compressible).unwrap().path,
+        PathBuf::from("old.pak")
+    );
+}
+
+#[test]
+fn it_matches_files_in_subfolders() {
+    let base = Path::new("C:\\Games");
+    let subfolders = vec![PathBuf::from("Saves"), PathBuf::from("C:\\Games\\Mods")];
+
+    assert!(in_subfolders(base, Path::new("Saves\\1.sav"), &subfolders));
+    assert!(in_subfolders(
+        base,
+        Path::new("Mods\\a\\b.pak"),
+        &subfolders
+    ));
+    assert!(!in_subfolders(
+        base,
+        Path::new("SavesOld\\1.sav"),
+        &subfolders
+    ));
+    assert!(!in_subfolders(base, Path::new("game.exe"), &subfolders));
+}
+
+#[test]
+fn it_sorts_files_by_size() {
+    use crate::testutil::file;
+
+    let names = |files: &[FileInfo]| -> Vec<String> {
+        files
+            .iter()
+            .map(|fi| fi.path.display().to_string())
+            .collect()
+    };
+    let files = vec![file("a", 2), file("b", 10), file("c", 1), file("d", 2)];
+
+    let mut sorted = files.clone();
+    sort_files(&mut sorted, Order::Default);
+    assert_eq!(names(&sorted), ["a", "b", "c", "d"]);
+
+    let mut sorted = files.clone();
+    sort_files(&mut sorted, Order::LargestFirst);
+    assert_eq!(names(&sorted), ["b", "a", "d", "c"]);
+
+    let mut sorted = files;
+    sort_files(&mut sorted, Order::SmallestFirst);
+    assert_eq!(names(&sorted), ["c", "a", "d", "b"]);
+}
+
+#[test]
+fn it_counts_files_changed_since_a_run() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("test.txt");
+    std::fs::write(&path, "hello").unwrap();
+
+    let mut run = LastRun {
+        folder: dir.path().to_path_buf(),
+        compression: Some(Compression::Lzx),
+        finished: SystemTime::now() + Duration::from_secs(3600),
+        files: vec![path.clone()],
+        file_list: None,
+    };
+    assert_eq!(changed_files(&run), 0);
+
+    run.files.push(dir.path().join("missing.txt"));
+    assert_eq!(changed_files(&run), 1);
+
+    run.finished = SystemTime::now() - Duration::from_secs(3600);
+    assert_eq!(changed_files(&run), 2);
+}
+
+#[test]
+fn it_ignores_compress_while_compacting() {
+    use crate::gui::GuiResponse;
+    use std::cell::RefCell;
+
+    #[derive(Default)]
+    struct Recorder(RefCell<Vec<String>>);
+
+    impl Frontend for Recorder {
+        fn send(&self, msg: &GuiResponse) {
+            let msg = serde_json::to_value(msg).expect("serialize");
+            self.0
+                .borrow_mut()
+                .push(msg["type"].as_str().unwrap().to_owned());
+        }
+
+        fn choose_folder(&self) -> Receiver<Vec<PathBuf>> {
+            bounded(1).1
+        }
+
+        fn choose_file(&self) -> Receiver<Vec<PathBuf>> {
+            bounded(1).1
+        }
+
+        fn confirm(&self, _title: &str, _message: &str) -> Receiver<bool> {
+            let (tx, rx) = bounded(1);
+            let _ = tx.send(false);
+            rx
+        }
+    }
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    std::fs::write(
+        dir.path().join("test.txt"),
+        crate::testutil::compressible_text(),
+    )
+    .unwrap();
+
+    let (tx, rx) = bounded::<GuiRequest>(8);
+    let mut backend = Backend::new(Recorder::default(), rx);
+    backend.restore_folder(dir.path().to_owned());
+    assert_eq!(
+        backend.handle(GuiRequest::Analyse),
+        Some(Outcome::Completed)
+    );
+
+    for _ in 0..2 {
+        tx.send(GuiRequest::Compress {
+            force: false,
+            incremental: false,
+        })
+        .unwrap();
     }
+    drop(tx);
+    backend.run();
+
+    let sent = backend.gui.0.borrow();
+    assert_eq!(sent.iter().filter(|t| *t == "Compacting").count(), 1);
 }
@@ -56,9 +56,11 @@ impl<S> ControlToken<S> {
     pub fn handle_pause(&self) -> bool {
         let mut paused = false;
 
+        // Time out in case we're a worker thread the handle doesn't know to
+        // unpark.
         while self.is_paused() && !self.is_cancelled() {
             paused = true;
-            thread::park();
+            thread::park_timeout(Duration::from_millis(100));
         }
 
         paused
@@ -0,0 +1,194 @@
+// Benchmarks of each compression algorithm against a sample of a folder.
+//
+// WOF can only compress files in place, so sampled files are copied into a
+// scratch folder under the system temporary directory and compressed there
+// with each algorithm in turn, timing the compression and measuring the size
+// on disk afterwards.  The originals are only ever read, and are streamed
+// into the scratch copy rather than read into memory.
+
+use std::io::{self, Read, Write};
+use std::os::windows::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use winapi::um::winnt::{FILE_READ_DATA, FILE_WRITE_ATTRIBUTES};
+
+use crate::background::{Background, ControlToken};
+use crate::compact;
+use crate::estimate::{SampleFile, SampleFiles, SampleStatus};
+use crate::folder::FileInfo;
+use crate::wof::{self, Compression};
+
+/// Maximum bytes copied from any single sampled file.
+const SAMPLE_BYTES_PER_FILE: u64 = 4 * 1024 * 1024;
+
+/// Maximum number of files to sample, spread evenly across the folder.
+const MAX_SAMPLE_FILES: usize = 64;
+
+/// How one algorithm fared across the sample.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Measurement {
+    pub bytes: u64,
+    pub compressed: u64,
+    pub time: Duration,
+}
+
+impl Measurement {
+    /// Size on disk divided by the original size, so lower is better.
+    pub fn ratio(&self) -> f32 {
+        if self.bytes == 0 {
+            1.0
+        } else {
+            self.compressed as f32 / self.bytes as f32
+        }
+    }
+
+    /// Compression speed in MiB per second.
+    pub fn throughput(&self) -> f32 {
+        let secs = self.time.as_secs_f32();
+        if secs > 0.0 {
+            self.bytes as f32 / (1024.0 * 1024.0) / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+pub type Results = Vec<(Compression, Measurement)>;
+
+#[derive(Debug)]
+pub struct Benchmark {
+    root: PathBuf,
+    files: Vec<FileInfo>,
+    max_bytes: u64,
+}
+
+impl Benchmark {
+    pub fn new<P: AsRef<Path>>(root: P, files: Vec<FileInfo>, max_bytes: u64) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            files,
+            max_bytes,
+        }
+    }
+}
+
+/// Copy up to `len` bytes of a file to `dest`, returning how many were copied.
+fn copy_sample(src: &Path, dest: &Path, len: u64) -> io::Result<u64> {
+    let mut input = std::fs::File::open(src)?.take(len);
+    let mut output = io::BufWriter::new(std::fs::File::create(dest)?);
+    let copied = io::copy(&mut input, &mut output)?;
+    output.flush()?;
+    Ok(copied)
+}
+
+/// Compress a scratch file, returning how long it took and its size on disk.
+fn measure(path: &Path, compression: Compression) -> io::Result<(Duration, u64)> {
+    let handle = std::fs::OpenOptions::new()
+        .access_mode(FILE_WRITE_ATTRIBUTES | FILE_READ_DATA)
+        .open(path)?;
+
+    let start = Instant::now();
+    wof::compress_file_handle(&handle, compression)?;
+    let elapsed = start.elapsed();
+    let size = compact::compressed_size(path)?;
+    wof::decompress_file_handle(&handle)?;
+
+    Ok((elapsed, size))
+}
+
+impl Benchmark {
+    fn run_in(&self, scratch: &Path, control: &ControlToken<SampleStatus>) -> Results {
+        let mut results: Results = Compression::ALL
+            .iter()
+            .map(|&c| (c, Measurement::default()))
+            .collect();
+        let mut files = SampleFiles::new(
+            &self.files,
+            MAX_SAMPLE_FILES,
+            SAMPLE_BYTES_PER_FILE,
+            self.max_bytes,
+        );
+        let sample = scratch.join("sample");
+
+        while let Some(SampleFile {
+            info: fi,
+            limit,
+            status,
+        }) = files.next()
+        {
+            if control.is_cancelled_with_pause() {
+                break;
+            }
+
+            control.set_status(status);
+
+            let len = match copy_sample(&self.root.join(&fi.path), &sample, limit) {
+                Ok(len) if len > 0 => len,
+                _ => continue,
+            };
+            files.record(len);
+
+            for (compression, m) in results.iter_mut() {
+                if let Ok((time, size)) = measure(&sample, *compression) {
+                    m.bytes += len;
+                    m.compressed += size.min(len);
+                    m.time += time;
+                }
+            }
+        }
+
+        let _ = std::fs::remove_file(&sample);
+        results
+    }
+}
+
+impl Background for Benchmark {
+    type Output = Result<Results, Results>;
+    type Status = SampleStatus;
+
+    fn run(self, control: &ControlToken<Self::Status>) -> Self::Output {
+        let scratch =
+            std::env::temp_dir().join(format!("compactor-benchmark-{}", std::process::id()));
+        if std::fs::create_dir_all(&scratch).is_err() {
+            return Err(Results::new());
+        }
+
+        let results = self.run_in(&scratch, control);
+        let _ = std::fs::remove_dir_all(&scratch);
+
+        if control.is_cancelled() {
+            Err(results)
+        } else {
+            Ok(results)
+        }
+    }
+}
+
+#[test]
+fn it_measures_ratio_and_throughput() {
+    let m = Measurement {
+        bytes: 4 * 1024 * 1024,
+        compressed: 1024 * 1024,
+        time: Duration::from_secs(2),
+    };
+
+    assert_eq!(m.ratio(), 0.25);
+    assert_eq!(m.throughput(), 2.0);
+    assert_eq!(Measurement::default().ratio(), 1.0);
+    assert_eq!(Measurement::default().throughput(), 0.0);
+}
+
+#[test]
+fn it_truncates_samples() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let src = dir.path().join("big");
+    let dest = dir.path().join("sample");
+    std::fs::write(&src, vec![7u8; 10_000]).unwrap();
+
+    assert_eq!(copy_sample(&src, &dest, 4096).unwrap(), 4096);
+    assert_eq!(std::fs::read(&dest).unwrap(), vec![7u8; 4096]);
+
+    assert_eq!(copy_sample(&src, &dest, 1 << 20).unwrap(), 10_000);
+    assert_eq!(std::fs::metadata(&dest).unwrap().len(), 10_000);
+}
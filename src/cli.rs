@@ -0,0 +1,728 @@
+// Command-line mode, for use from scripts and scheduled tasks.
+//
+// This drives the same `Backend` as the GUI, feeding it requests directly and
+// printing its status updates to stdout instead of sending them to a WebView.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crossbeam_channel::{bounded, Receiver};
+
+use crate::auto;
+use crate::backend::{format_size, Backend, Outcome};
+use crate::compact;
+use crate::config::{check_number, MAX_THREADS};
+use crate::gui::{Frontend, GuiRequest, GuiResponse};
+use crate::persistence::{self, config_for, profiles};
+use crate::schedule;
+use crate::shell;
+use crate::wof::Compression;
+
+pub const EXIT_SUCCESS: i32 = 0;
+pub const EXIT_FAILURE: i32 = 1;
+pub const EXIT_PARTIAL: i32 = 2;
+pub const EXIT_CANCELLED: i32 = 3;
+pub const EXIT_USAGE: i32 = 64;
+
+pub const USAGE: &str = r#"Usage: Compactor [--portable] [--register | --unregister]
+       Compactor --protocol json
+       Compactor --schedule [--at HH:MM] [OPTIONS] FOLDER
+       Compactor --unschedule FOLDER
+       Compactor [--gui] [--analyse | --decompress] [--force] [--verify]
+                 [--incremental] [--allow-protected]
+                 [--compression ALGORITHM] [--only ALGORITHM]
+                 [--excludes GLOBS] [--threads N]
+                 [--report FILE] FOLDER
+
+  --portable               Keep settings beside Compactor.exe rather than in
+                           your profile, as does a portable.txt there
+  --register               Add "Compress with Compactor" to Explorer's folder
+                           context menu
+  --unregister             Remove it again
+  --protocol json          Read requests from stdin and write responses to
+                           stdout as lines of JSON, instead of opening the GUI
+  --schedule               Compress FOLDER daily in the background, with the
+                           given options, while the computer is idle
+  --at HH:MM               With --schedule, the time to start (default 03:00)
+  --unschedule             Stop compressing FOLDER on a schedule
+  --gui                    Open the GUI even if a folder is given, with that
+                           folder chosen
+  --analyse                Only analyse the folder, don't compress it
+  --decompress             Decompress the folder instead of compressing it
+  --force                  Recompress files which are already compressed
+  --verify                 Check files read back correctly after compressing
+  --incremental            Only compress files which are new or changed since
+                           the folder was last compressed
+  --allow-protected        Compress Windows system files too (dangerous)
+  --only ALGORITHM         With --decompress, only decompress files using
+                           the given algorithm
+  --compression ALGORITHM  One of XPRESS4K, XPRESS8K, XPRESS16K or LZX, or
+                           AUTO to choose for each file
+  --excludes GLOBS         Semicolon-separated exclude globs, replacing the
+                           configured ones
+  --threads N              Compress on N threads, or 0 for one per CPU
+  --report FILE            Write a JSON report of the folder to FILE
+
+Exit status is 0 on success, 2 if some files failed, and 3 if cancelled."#;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CliAction {
+    Analyse,
+    Compress,
+    Decompress,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct CliArgs {
+    pub path: PathBuf,
+    pub action: CliAction,
+    pub force: bool,
+    pub verify: bool,
+    pub incremental: bool,
+    pub allow_protected: bool,
+    pub compression: Option<Compression>,
+    pub auto_compression: bool,
+    pub only: Option<Compression>,
+    pub excludes: Option<Vec<String>>,
+    pub threads: Option<usize>,
+    pub report: Option<PathBuf>,
+}
+
+/// What the command line asks for.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    /// Launch the GUI, optionally with a folder already chosen.
+    Gui(Option<PathBuf>),
+    Run(CliArgs),
+    /// Drive the backend with JSON lines over stdin and stdout.
+    Protocol,
+    Register,
+    Unregister,
+    /// Run the given arguments daily at a time of day.
+    Schedule {
+        args: CliArgs,
+        at: String,
+    },
+    Unschedule(PathBuf),
+}
+
+/// The default time of day for scheduled compression.
+const DEFAULT_SCHEDULE_TIME: &str = "03:00";
+
+impl CliArgs {
+    /// The command-line arguments which would parse back into these, for
+    /// scheduled tasks.
+    pub fn to_args(&self) -> Vec<String> {
+        let mut args = vec![];
+        match self.action {
+            CliAction::Analyse => args.push("--analyse".to_string()),
+            CliAction::Decompress => args.push("--decompress".to_string()),
+            CliAction::Compress => (),
+        }
+        for (set, flag) in &[
+            (self.force, "--force"),
+            (self.verify, "--verify"),
+            (self.incremental, "--incremental"),
+            (self.allow_protected, "--allow-protected"),
+        ] {
+            if *set {
+                args.push(flag.to_string());
+            }
+        }
+        if self.auto_compression {
+            args.extend(vec!["--compression".to_string(), auto::NAME.to_string()]);
+        } else if let Some(compression) = self.compression {
+            args.extend(vec!["--compression".to_string(), compression.to_string()]);
+        }
+        if let Some(only) = self.only {
+            args.extend(vec!["--only".to_string(), only.to_string()]);
+        }
+        if let Some(excludes) = &self.excludes {
+            args.extend(vec!["--excludes".to_string(), excludes.join(";")]);
+        }
+        if let Some(threads) = self.threads {
+            args.extend(vec!["--threads".to_string(), threads.to_string()]);
+        }
+        if let Some(report) = &self.report {
+            args.extend(vec!["--report".to_string(), report.display().to_string()]);
+        }
+        args.push(self.path.display().to_string());
+        args
+    }
+}
+
+/// Parse command-line arguments, excluding the program name.
+pub fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut args = args.into_iter();
+    let mut standalone = None;
+    let mut gui = false;
+    let mut path = None;
+    let mut action = CliAction::Compress;
+    let mut force = false;
+    let mut verify = false;
+    let mut incremental = false;
+    let mut allow_protected = false;
+    let mut compression = None;
+    let mut auto_compression = false;
+    let mut only = None;
+    let mut excludes = None;
+    let mut threads = None;
+    let mut report = None;
+    let mut schedule = false;
+    let mut at = None;
+    let mut unschedule = false;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--register" => standalone = Some(Command::Register),
+            "--unregister" => standalone = Some(Command::Unregister),
+            "--protocol" => {
+                let value = args.next().ok_or("--protocol requires an argument")?;
+                if !value.eq_ignore_ascii_case("json") {
+                    return Err(format!("Unknown protocol: {}", value));
+                }
+                standalone = Some(Command::Protocol);
+            }
+            "--schedule" => schedule = true,
+            "--at" => {
+                let value = args.next().ok_or("--at requires an argument")?;
+                if !schedule::valid_time(&value) {
+                    return Err(format!("Invalid time, expected HH:MM: {}", value));
+                }
+                at = Some(value);
+            }
+            "--unschedule" => unschedule = true,
+            "--gui" => gui = true,
+            "--analyse" | "--analyze" => action = CliAction::Analyse,
+            "--decompress" => action = CliAction::Decompress,
+            "--force" => force = true,
+            "--verify" => verify = true,
+            "--incremental" => incremental = true,
+            "--allow-protected" => allow_protected = true,
+            "--compression" => {
+                let value = args.next().ok_or("--compression requires an argument")?;
+                auto_compression = value.eq_ignore_ascii_case(auto::NAME);
+                if !auto_compression {
+                    compression = Some(
+                        value
+                            .to_uppercase()
+                            .parse::<Compression>()
+                            .map_err(|_| format!("Unknown compression: {}", value))?,
+                    );
+                }
+            }
+            "--only" => {
+                let value = args.next().ok_or("--only requires an argument")?;
+                only = Some(
+                    value
+                        .to_uppercase()
+                        .parse::<Compression>()
+                        .map_err(|_| format!("Unknown compression: {}", value))?,
+                );
+            }
+            "--excludes" => {
+                let value = args.next().ok_or("--excludes requires an argument")?;
+                excludes = Some(
+                    value
+                        .split(';')
+                        .filter(|s| !s.is_empty())
+                        .map(str::to_owned)
+                        .collect(),
+                );
+            }
+            "--threads" => {
+                let value = args.next().ok_or("--threads requires an argument")?;
+                let count = value
+                    .parse::<usize>()
+                    .map_err(|_| format!("Invalid thread count: {}", value))?;
+                check_number("--threads", count as f64, MAX_THREADS).map_err(|e| e.to_string())?;
+                threads = Some(count);
+            }
+            "--report" => {
+                let value = args.next().ok_or("--report requires an argument")?;
+                report = Some(compact::expand_env(value).map_err(|e| e.to_string())?);
+            }
+            flag if flag.starts_with("--") => {
+                return Err(format!("Unknown option: {}", flag));
+            }
+            _ if path.is_some() => {
+                return Err("Only one folder may be given".to_string());
+            }
+            _ => path = Some(compact::expand_env(arg).map_err(|e| e.to_string())?),
+        }
+    }
+
+    if let Some(standalone) = standalone {
+        return Ok(standalone);
+    }
+
+    if at.is_some() && !schedule {
+        return Err("--at requires --schedule".to_string());
+    }
+    if (schedule || unschedule) && path.is_none() {
+        return Err("A folder to schedule is required".to_string());
+    }
+    if unschedule {
+        return Ok(Command::Unschedule(path.unwrap()));
+    }
+
+    match path {
+        Some(path) if !gui => {
+            let args = CliArgs {
+                path,
+                action,
+                force,
+                verify,
+                incremental,
+                allow_protected,
+                compression,
+                auto_compression,
+                only,
+                excludes,
+                threads,
+                report,
+            };
+
+            if schedule {
+                let at = at.unwrap_or_else(|| DEFAULT_SCHEDULE_TIME.to_string());
+                Ok(Command::Schedule { args, at })
+            } else {
+                Ok(Command::Run(args))
+            }
+        }
+        path => Ok(Command::Gui(path)),
+    }
+}
+
+/// Pull out `--portable`, which can go with any other command, returning
+/// whether it was there and the rest of the arguments.
+pub fn take_portable<I: IntoIterator<Item = String>>(args: I) -> (bool, Vec<String>) {
+    let (portable, rest): (Vec<String>, Vec<String>) =
+        args.into_iter().partition(|arg| arg == "--portable");
+    (!portable.is_empty(), rest)
+}
+
+/// Add or remove the Explorer context menu entry.
+pub fn run_shell(register: bool) -> i32 {
+    let (ret, done) = if register {
+        (shell::register(), "Added")
+    } else {
+        (shell::unregister(), "Removed")
+    };
+
+    match ret {
+        Ok(()) => {
+            println!("{} the Explorer folder menu entry", done);
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error updating the Explorer folder menu: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// The task may run from anywhere, so give it an absolute path.
+fn absolute(path: &Path) -> io::Result<PathBuf> {
+    if path.is_absolute() {
+        Ok(path.to_path_buf())
+    } else {
+        Ok(std::env::current_dir()?.join(path))
+    }
+}
+
+/// Add a scheduled task compressing a folder with the given options.
+pub fn run_schedule(args: CliArgs, at: &str) -> i32 {
+    let ret = absolute(&args.path).and_then(|path| {
+        let args = CliArgs { path, ..args };
+        let mut task_args = args.to_args();
+        if persistence::portable_dir().is_some() {
+            task_args.insert(0, "--portable".to_string());
+        }
+        schedule::schedule(&args.path, &task_args, at).map(|_| args.path)
+    });
+
+    match ret {
+        Ok(path) => {
+            println!("Scheduled {} daily at {}", path.display(), at);
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error adding scheduled task: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Remove the scheduled task for a folder.
+pub fn run_unschedule(path: PathBuf) -> i32 {
+    match absolute(&path).and_then(|path| schedule::unschedule(&path)) {
+        Ok(()) => {
+            println!("Unscheduled {}", path.display());
+            EXIT_SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Error removing scheduled task: {}", e);
+            EXIT_FAILURE
+        }
+    }
+}
+
+/// Prints backend status updates to the console.
+struct Console {
+    path: PathBuf,
+}
+
+impl Frontend for Console {
+    fn send(&self, msg: &GuiResponse) {
+        match msg {
+            GuiResponse::Status {
+                status,
+                pct,
+                eta_secs,
+                throughput_mbps,
+            } => {
+                let eta = match (eta_secs, throughput_mbps) {
+                    (Some(secs), Some(rate)) => {
+                        format!(" ({}:{:02} left, {:.1} MiB/s)", secs / 60, secs % 60, rate)
+                    }
+                    (Some(secs), None) => format!(" ({}:{:02} left)", secs / 60, secs % 60),
+                    (None, Some(rate)) => format!(" ({:.1} MiB/s)", rate),
+                    (None, None) => String::new(),
+                };
+
+                match pct {
+                    Some(pct) if pct.is_finite() => {
+                        println!("[{:5.1}%] {}{}", pct * 100.0, status, eta)
+                    }
+                    _ => println!("[  ...  ] {}{}", status, eta),
+                }
+            }
+            GuiResponse::Errors { count, sample, .. } if *count > 0 => {
+                eprintln!("{} files could not be processed:", count);
+                for error in sample {
+                    eprintln!("  {}", error);
+                }
+                if *count > sample.len() {
+                    eprintln!("  ... and {} more", count - sample.len());
+                }
+            }
+            GuiResponse::Warning { message } => {
+                eprintln!("Warning: {}", message);
+            }
+            GuiResponse::ReportExported { path } => {
+                println!("Report written to {}", path.display());
+            }
+            _ => (),
+        }
+    }
+
+    fn choose_folder(&self) -> Receiver<Vec<PathBuf>> {
+        let (tx, rx) = bounded::<Vec<PathBuf>>(1);
+        let _ = tx.send(vec![self.path.clone()]);
+        rx
+    }
+
+    fn choose_file(&self) -> Receiver<Vec<PathBuf>> {
+        self.choose_folder()
+    }
+
+    fn confirm(&self, _title: &str, message: &str) -> Receiver<bool> {
+        // There's nobody to ask, so stay on the safe side.
+        eprintln!("{}", message);
+        let (tx, rx) = bounded::<bool>(1);
+        let _ = tx.send(false);
+        rx
+    }
+}
+
+pub fn run_cli(args: CliArgs) -> i32 {
+    persistence::init();
+
+    if !args.path.exists() {
+        eprintln!("No such file or folder: {}", args.path.display());
+        return EXIT_FAILURE;
+    }
+
+    let mut conf = config_for(&args.path);
+    if let Some(compression) = args.compression {
+        conf.compression = compression;
+        conf.auto_compression = false;
+    }
+    if args.auto_compression {
+        conf.auto_compression = true;
+    }
+    if let Some(excludes) = args.excludes {
+        conf.excludes = excludes;
+    }
+    if let Some(threads) = args.threads {
+        conf.threads = threads;
+    }
+    if args.verify {
+        conf.verify = true;
+    }
+    if args.allow_protected {
+        conf.allow_protected = true;
+    }
+    if let Err(msg) = conf.globset() {
+        eprintln!("Invalid excludes: {}", msg);
+        return EXIT_USAGE;
+    }
+    if let Err(msg) = conf.include_globset() {
+        eprintln!("Invalid included extensions: {}", msg);
+        return EXIT_USAGE;
+    }
+    let decimal = conf.decimal;
+
+    // Only set in memory - command-line options are not saved.
+    profiles().write().unwrap().insert(&args.path, conf);
+
+    let (to_backend, from_cli) = bounded::<GuiRequest>(128);
+    let stop = to_backend.clone();
+    ctrlc::set_handler(move || {
+        let _ = stop.send(GuiRequest::Stop);
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    let mut backend = Backend::new(Console { path: args.path }, from_cli);
+
+    let mut outcome = backend.handle(GuiRequest::ChooseFolder);
+
+    if outcome == Some(Outcome::Completed) {
+        outcome = match args.action {
+            CliAction::Analyse => outcome,
+            CliAction::Compress => backend.handle(GuiRequest::Compress {
+                force: args.force,
+                incremental: args.incremental,
+            }),
+            CliAction::Decompress => backend.handle(GuiRequest::Decompress {
+                only: args.only.map(|c| c.to_string()),
+            }),
+        };
+    }
+
+    if let Some(info) = backend.info() {
+        let s = info.summary();
+        println!(
+            "{} in {} files, {} on disk: {} compressed, {} compressible, {} skipped ({} by size)",
+            format_size(s.logical_size, decimal),
+            s.compressed.count + s.compressible.count + s.skipped.count + s.size_skipped.count,
+            format_size(s.physical_size, decimal),
+            s.compressed.count,
+            s.compressible.count,
+            s.skipped.count + s.size_skipped.count,
+            s.size_skipped.count
+        );
+        if s.reparse_skipped > 0 {
+            println!(
+                "{} junctions and symbolic links not followed",
+                s.reparse_skipped
+            );
+        }
+        if s.attribute_skipped > 0 {
+            println!(
+                "{} hidden or system files and folders skipped",
+                s.attribute_skipped
+            );
+        }
+        if s.sparse_skipped > 0 {
+            println!("{} sparse files skipped", s.sparse_skipped);
+        }
+    }
+
+    if let Some(path) = args.report {
+        backend.handle(GuiRequest::ExportReport { path });
+    }
+
+    drop(to_backend);
+
+    match outcome {
+        Some(Outcome::Completed) => EXIT_SUCCESS,
+        Some(Outcome::Partial) => EXIT_PARTIAL,
+        Some(Outcome::Stopped) => EXIT_CANCELLED,
+        None => EXIT_FAILURE,
+    }
+}
+
+#[cfg(test)]
+fn parse(args: &[&str]) -> Result<Command, String> {
+    parse_args(args.iter().map(|s| s.to_string()))
+}
+
+#[test]
+fn it_launches_the_gui_without_a_folder() {
+    assert_eq!(parse(&[]), Ok(Command::Gui(None)));
+    assert_eq!(
+        parse(&["--gui", "C:\\Games"]),
+        Ok(Command::Gui(Some(PathBuf::from("C:\\Games"))))
+    );
+}
+
+#[test]
+fn it_parses_shell_integration() {
+    assert_eq!(parse(&["--register"]), Ok(Command::Register));
+    assert_eq!(parse(&["--unregister"]), Ok(Command::Unregister));
+}
+
+#[test]
+fn it_parses_schedules() {
+    match parse(&[
+        "--schedule",
+        "--at",
+        "01:30",
+        "--compression",
+        "lzx",
+        "C:\\Downloads",
+    ]) {
+        Ok(Command::Schedule { args, at }) => {
+            assert_eq!(at, "01:30");
+            assert_eq!(args.compression, Some(Compression::Lzx));
+            assert_eq!(
+                args.to_args(),
+                vec!["--compression", "LZX", "C:\\Downloads"]
+            );
+        }
+        other => panic!("Expected a schedule, got {:?}", other),
+    }
+
+    match parse(&["--schedule", "C:\\Downloads"]) {
+        Ok(Command::Schedule { at, .. }) => assert_eq!(at, DEFAULT_SCHEDULE_TIME),
+        other => panic!("Expected a schedule, got {:?}", other),
+    }
+
+    assert_eq!(
+        parse(&["--unschedule", "C:\\Downloads"]),
+        Ok(Command::Unschedule(PathBuf::from("C:\\Downloads")))
+    );
+    assert!(parse(&["--schedule", "--at", "25:00", "C:\\Downloads"]).is_err());
+    assert!(parse(&["--unschedule"]).is_err());
+    assert!(parse(&["--at", "01:30", "C:\\Downloads"]).is_err());
+}
+
+#[test]
+fn it_round_trips_args() {
+    let args = parse(&[
+        "--decompress",
+        "--only",
+        "xpress4k",
+        "--excludes",
+        "*.jpg;*.png",
+        "--threads",
+        "2",
+        "--force",
+        "C:\\Games",
+    ]);
+
+    match args {
+        Ok(Command::Run(args)) => {
+            assert_eq!(parse_args(args.to_args()), Ok(Command::Run(args)));
+        }
+        other => panic!("Expected CLI args, got {:?}", other),
+    }
+}
+
+#[test]
+fn it_parses_protocol_mode() {
+    assert_eq!(parse(&["--protocol", "json"]), Ok(Command::Protocol));
+    assert!(parse(&["--protocol", "xml"]).is_err());
+    assert!(parse(&["--protocol"]).is_err());
+}
+
+#[test]
+fn it_parses_args() {
+    let args = parse(&[
+        "--compression",
+        "lzx",
+        "--excludes",
+        "*.jpg;*.{png,gif}",
+        "--analyse",
+        "--verify",
+        "--incremental",
+        "--allow-protected",
+        "--only",
+        "xpress4k",
+        "--report",
+        "report.json",
+        "--threads",
+        "4",
+        "C:\\Games",
+    ]);
+
+    let args = match args {
+        Ok(Command::Run(args)) => args,
+        other => panic!("Expected CLI args, got {:?}", other),
+    };
+
+    assert_eq!(args.path, PathBuf::from("C:\\Games"));
+    assert_eq!(args.action, CliAction::Analyse);
+    assert!(args.verify);
+    assert!(args.incremental);
+    assert!(args.allow_protected);
+    assert_eq!(args.compression, Some(Compression::Lzx));
+    assert_eq!(args.only, Some(Compression::Xpress4k));
+    assert_eq!(args.report, Some(PathBuf::from("report.json")));
+    assert_eq!(args.threads, Some(4));
+    assert_eq!(
+        args.excludes,
+        Some(vec!["*.jpg".to_string(), "*.{png,gif}".to_string()])
+    );
+}
+
+#[test]
+fn it_parses_auto_compression() {
+    match parse(&["--compression", "auto", "C:\\Games"]) {
+        Ok(Command::Run(args)) => {
+            assert!(args.auto_compression);
+            assert_eq!(args.compression, None);
+        }
+        other => panic!("Expected CLI args, got {:?}", other),
+    }
+}
+
+#[test]
+fn it_rejects_bad_args() {
+    assert!(parse(&["--compression", "zstd", "C:\\Games"]).is_err());
+    assert!(parse(&["--compression"]).is_err());
+    assert!(parse(&["--compression", "automatic", "C:\\Games"]).is_err());
+    assert!(parse(&["--threads", "lots", "C:\\Games"]).is_err());
+    assert!(parse(&["--only", "zstd", "C:\\Games"]).is_err());
+    assert!(parse(&["--frobnicate", "C:\\Games"]).is_err());
+    assert!(parse(&["C:\\Games", "D:\\Games"]).is_err());
+}
+
+#[test]
+fn it_rejects_too_many_threads() {
+    assert!(parse(&["--threads", "256", "C:\\Games"]).is_ok());
+    assert_eq!(
+        parse(&["--threads", "100000", "C:\\Games"]).err(),
+        Some("--threads must be a whole number from 0 to 256, not 100000".to_string())
+    );
+}
+
+#[test]
+fn it_takes_portable_from_anywhere() {
+    let args = |args: &[&str]| args.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+
+    assert_eq!(
+        take_portable(args(&["--gui", "--portable", "C:\\Games"])),
+        (true, args(&["--gui", "C:\\Games"]))
+    );
+    assert_eq!(
+        take_portable(args(&["--analyse", "C:\\Games"])),
+        (false, args(&["--analyse", "C:\\Games"]))
+    );
+}
+
+#[test]
+fn it_expands_paths() {
+    let profile = std::env::var("USERPROFILE").expect("USERPROFILE");
+    match parse(&["%USERPROFILE%\\Downloads"]) {
+        Ok(Command::Run(args)) => {
+            assert_eq!(args.path, PathBuf::from(profile).join("Downloads"))
+        }
+        other => panic!("Expected CLI args, got {:?}", other),
+    }
+
+    let err = parse(&["%COMPACTOR_UNDEFINED_TEST_VAR%\\Games"]).unwrap_err();
+    assert!(err.contains("COMPACTOR_UNDEFINED_TEST_VAR"));
+}
@@ -1,390 +1,350 @@
-#![allow(non_camel_case_types, non_snake_case, dead_code)]
+// Volume and path helpers for the files Compactor works on.  The compression
+// itself lives in `wof`.
 
-use std::convert::TryFrom;
-use std::ffi::{CString, OsStr};
+use std::ffi::{OsStr, OsString};
 use std::os::windows::ffi::OsStrExt;
-use std::os::windows::io::AsRawHandle;
-use std::path::Path;
-use std::str::FromStr;
-
-use serde_derive::{Deserialize, Serialize};
-
-use winapi::shared::minwindef::{BOOL, DWORD, PBOOL, PULONG, ULONG};
-use winapi::shared::ntdef::PVOID;
-use winapi::shared::winerror::{HRESULT_CODE, SUCCEEDED};
-use winapi::um::ioapiset::DeviceIoControl;
-use winapi::um::winioctl::{FSCTL_DELETE_EXTERNAL_BACKING, FSCTL_SET_EXTERNAL_BACKING};
-use winapi::um::winnt::{HANDLE, HRESULT, LPCWSTR};
-use winapi::um::winver::{GetFileVersionInfoA, GetFileVersionInfoSizeA, VerQueryValueA};
-use winapi::STRUCT;
-
-STRUCT! {
-    struct _WOF_FILE_COMPRESSION_INFO_V1 {
-        Algorithm: ULONG,
-        Flags: ULONG,
-    }
-}
+use std::path::{Component, Path, PathBuf, Prefix};
 
-STRUCT! {
-    struct _WOF_EXTERNAL_INFO {
-        Version: ULONG,
-        Provider: ULONG,
-    }
-}
-
-STRUCT! {
-    struct _FILE_PROVIDER_EXTERNAL_INFO_V1 {
-        Version: ULONG,
-        Algorithm: ULONG,
-        Flags: ULONG,
-    }
-}
+use winapi::shared::minwindef::{DWORD, MAX_PATH};
+use winapi::shared::ntdef::ULARGE_INTEGER;
+use winapi::shared::winerror::NO_ERROR;
+use winapi::um::fileapi::{
+    GetCompressedFileSizeW, GetDiskFreeSpaceExW, GetDriveTypeW, GetVolumeInformationW,
+    GetVolumePathNameW, INVALID_FILE_SIZE,
+};
+use winapi::um::winbase::DRIVE_REMOTE;
 
-STRUCT! {
-    struct VS_FIXEDFILEINFO {
-        dwSignature: DWORD,
-        dwStrucVersion: DWORD,
-        dwFileVersionMS: DWORD,
-        dwFileVersionLS: DWORD,
-        dwProductVersionMS: DWORD,
-        dwProductVersionLS: DWORD,
-        dwFileFlagsMask: DWORD,
-        dwFileFlags: DWORD,
-        dwFileOS: DWORD,
-        dwFileType: DWORD,
-        dwFileSubtype: DWORD,
-        dwFileDateMS: DWORD,
-        dwFileDateLS: DWORD,
-    }
-}
+use crate::error::{self, CompactorError};
 
-const VS_FIXEDFILEINFO_SIGNATURE: DWORD = 0xFEEF_04BD;
+/// The name of the filesystem a path is on, such as "NTFS" or "ReFS".
+pub fn filesystem_name<P: AsRef<OsStr>>(path: P) -> std::io::Result<String> {
+    let mut p: Vec<u16> = path.as_ref().encode_wide().collect();
+    p.push(0);
 
-const FILE_PROVIDER_COMPRESSION_XPRESS4K: ULONG = 0;
-const FILE_PROVIDER_COMPRESSION_LZX: ULONG = 1;
-const FILE_PROVIDER_COMPRESSION_XPRESS8K: ULONG = 2;
-const FILE_PROVIDER_COMPRESSION_XPRESS16K: ULONG = 3;
+    let mut volume = vec![0u16; p.len().max(MAX_PATH)];
+    let ret = unsafe { GetVolumePathNameW(p.as_ptr(), volume.as_mut_ptr(), volume.len() as DWORD) };
 
-const ERROR_SUCCESS: HRESULT = 0;
-const ERROR_COMPRESSION_NOT_BENEFICIAL: HRESULT = 344;
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
+    }
 
-const FILE_PROVIDER_CURRENT_VERSION: ULONG = 1;
-const WOF_CURRENT_VERSION: ULONG = 1;
-const WOF_PROVIDER_FILE: ULONG = 2;
+    let mut name = [0u16; MAX_PATH + 1];
+    let ret = unsafe {
+        GetVolumeInformationW(
+            volume.as_ptr(),
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            std::ptr::null_mut(),
+            name.as_mut_ptr(),
+            name.len() as DWORD,
+        )
+    };
 
-impl Default for _FILE_PROVIDER_EXTERNAL_INFO_V1 {
-    fn default() -> Self {
-        Self {
-            Version: FILE_PROVIDER_CURRENT_VERSION,
-            Algorithm: Compression::default().into(),
-            Flags: 0,
-        }
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
     }
-}
 
-impl Default for _WOF_EXTERNAL_INFO {
-    fn default() -> Self {
-        Self {
-            Version: WOF_CURRENT_VERSION,
-            Provider: WOF_PROVIDER_FILE,
-        }
-    }
+    let len = name.iter().position(|&c| c == 0).unwrap_or(name.len());
+    Ok(String::from_utf16_lossy(&name[..len]))
 }
 
-impl From<Compression> for _FILE_PROVIDER_EXTERNAL_INFO_V1 {
-    fn from(compression: Compression) -> Self {
-        Self {
-            Version: FILE_PROVIDER_CURRENT_VERSION,
-            Algorithm: compression.into(),
-            Flags: 0,
-        }
-    }
-}
+/// The free and total space in bytes on the volume containing a folder.
+///
+/// Free space counts only what's available to the current user, so disk quotas
+/// are taken into account.
+pub fn disk_space<P: AsRef<OsStr>>(path: P) -> std::io::Result<(u64, u64)> {
+    let mut p: Vec<u16> = path.as_ref().encode_wide().collect();
+    p.push(0);
 
-#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
-pub enum Compression {
-    Xpress4k,
-    Xpress8k,
-    Xpress16k,
-    Lzx,
-}
+    let mut free: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let mut total: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+    let ret =
+        unsafe { GetDiskFreeSpaceExW(p.as_ptr(), &mut free, &mut total, std::ptr::null_mut()) };
 
-impl Default for Compression {
-    fn default() -> Self {
-        Compression::Xpress8k
+    if ret == 0 {
+        return Err(std::io::Error::last_os_error());
     }
-}
 
-impl std::fmt::Display for Compression {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match self {
-            Compression::Xpress4k => write!(f, "XPRESS4k"),
-            Compression::Xpress8k => write!(f, "XPRESS8K"),
-            Compression::Xpress16k => write!(f, "XPRESS16K"),
-            Compression::Lzx => write!(f, "LZX"),
-        }
-    }
+    Ok(unsafe { (*free.QuadPart(), *total.QuadPart()) })
 }
 
-impl FromStr for Compression {
-    type Err = ();
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        match s {
-            "XPRESS4K" => Ok(Compression::Xpress4k),
-            "XPRESS8K" => Ok(Compression::Xpress8k),
-            "XPRESS16K" => Ok(Compression::Xpress16k),
-            "LZX" => Ok(Compression::Lzx),
-            _ => Err(()),
+/// The bytes a file actually occupies on disk.
+///
+/// Unlike the allocation size, this accounts for WOF and NTFS compression, and
+/// for sparse files.
+pub fn compressed_size<P: AsRef<Path>>(path: P) -> std::io::Result<u64> {
+    let p: Vec<u16> = long_path(path)
+        .as_os_str()
+        .encode_wide()
+        .chain(Some(0))
+        .collect();
+
+    let mut high: DWORD = 0;
+    let low = unsafe { GetCompressedFileSizeW(p.as_ptr(), &mut high) };
+
+    // INVALID_FILE_SIZE is also a valid low word, so check the error too.
+    if low == INVALID_FILE_SIZE {
+        let err = std::io::Error::last_os_error();
+        if err.raw_os_error() != Some(NO_ERROR as i32) {
+            return Err(err);
         }
     }
-}
-
-impl TryFrom<ULONG> for Compression {
-    type Error = ();
 
-    fn try_from(value: ULONG) -> Result<Self, Self::Error> {
-        match value {
-            FILE_PROVIDER_COMPRESSION_XPRESS4K => Ok(Compression::Xpress4k),
-            FILE_PROVIDER_COMPRESSION_XPRESS8K => Ok(Compression::Xpress8k),
-            FILE_PROVIDER_COMPRESSION_XPRESS16K => Ok(Compression::Xpress16k),
-            FILE_PROVIDER_COMPRESSION_LZX => Ok(Compression::Lzx),
-            _ => Err(()),
-        }
-    }
+    Ok((u64::from(high) << 32) | u64::from(low))
 }
 
-impl From<Compression> for ULONG {
-    fn from(value: Compression) -> Self {
-        match value {
-            Compression::Xpress4k => FILE_PROVIDER_COMPRESSION_XPRESS4K,
-            Compression::Xpress8k => FILE_PROVIDER_COMPRESSION_XPRESS8K,
-            Compression::Xpress16k => FILE_PROVIDER_COMPRESSION_XPRESS16K,
-            Compression::Lzx => FILE_PROVIDER_COMPRESSION_LZX,
+/// Add the `\\?\` extended-length prefix to an absolute path, so Win32 calls
+/// on it aren't limited to `MAX_PATH` characters.
+///
+/// Relative paths, already-prefixed paths, and paths with `..` components are
+/// returned unchanged.
+pub fn long_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let mut components = path.components();
+
+    let mut long = match components.next() {
+        Some(Component::Prefix(prefix)) if path.has_root() => match prefix.kind() {
+            Prefix::Disk(_) => {
+                let mut s = OsString::from(r"\\?\");
+                s.push(prefix.as_os_str());
+                s
+            }
+            Prefix::UNC(server, share) => {
+                let mut s = OsString::from(r"\\?\UNC\");
+                s.push(server);
+                s.push(r"\");
+                s.push(share);
+                s
+            }
+            _ => return path.to_path_buf(),
+        },
+        _ => return path.to_path_buf(),
+    };
+
+    // Extended-length paths are passed through to the filesystem as-is, so
+    // they must only use backslashes and can't contain `.` or `..`.
+    let mut empty = true;
+    for component in components {
+        match component {
+            Component::RootDir => (),
+            Component::Normal(name) => {
+                long.push(r"\");
+                long.push(name);
+                empty = false;
+            }
+            _ => return path.to_path_buf(),
         }
     }
-}
-
-pub fn system_supports_compression() -> std::io::Result<bool> {
-    let dll = CString::new("WofUtil.dll").unwrap();
-    let path = CString::new("\\").unwrap();
-    let mut handle = 0;
-
-    let len = unsafe { GetFileVersionInfoSizeA(dll.as_ptr(), &mut handle) };
 
-    if len == 0 {
-        return Err(std::io::Error::last_os_error());
+    if empty {
+        long.push(r"\");
     }
 
-    let mut buf = vec![0u8; len as usize];
+    PathBuf::from(long)
+}
 
-    let ret = unsafe {
-        GetFileVersionInfoA(
-            dll.as_ptr(),
-            handle,
-            len,
-            buf.as_mut_ptr() as *mut _ as PVOID,
-        )
+/// Strip the `\\?\` extended-length prefix from a path, if it has one, so
+/// paths from folder choosers and the like compare equal to typed ones.
+///
+/// This is the reverse of `long_path`.
+pub fn normal_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    let path = path.as_ref();
+    let mut components = path.components();
+
+    let mut normal = match components.next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::VerbatimDisk(disk) => OsString::from(format!("{}:", disk as char)),
+            Prefix::VerbatimUNC(server, share) => {
+                let mut s = OsString::from(r"\\");
+                s.push(server);
+                s.push(r"\");
+                s.push(share);
+                s
+            }
+            _ => return path.to_path_buf(),
+        },
+        _ => return path.to_path_buf(),
     };
 
-    if ret == 0 {
-        return Err(std::io::Error::last_os_error());
+    let mut empty = true;
+    for component in components {
+        match component {
+            Component::RootDir => (),
+            Component::Normal(name) => {
+                normal.push(r"\");
+                normal.push(name);
+                empty = false;
+            }
+            _ => return path.to_path_buf(),
+        }
     }
 
-    let mut pinfo: PVOID = std::ptr::null_mut();
-    let mut pinfo_size = 0;
-
-    let ret = unsafe {
-        VerQueryValueA(
-            buf.as_mut_ptr() as *mut _ as PVOID,
-            path.as_ptr(),
-            &mut pinfo,
-            &mut pinfo_size,
-        )
-    };
-
-    if ret == 0 {
-        return Err(std::io::Error::last_os_error());
+    if empty {
+        normal.push(r"\");
     }
 
-    assert!(pinfo_size as usize >= std::mem::size_of::<VS_FIXEDFILEINFO>());
-    assert!(!pinfo.is_null());
-
-    let pinfo: &VS_FIXEDFILEINFO = unsafe { &*(pinfo as *const VS_FIXEDFILEINFO) };
-    assert!(pinfo.dwSignature == VS_FIXEDFILEINFO_SIGNATURE);
-
-    Ok((pinfo.dwFileVersionMS >> 16) & 0xffff >= 10)
+    PathBuf::from(normal)
 }
 
-pub fn file_supports_compression<P: AsRef<Path>>(path: P) -> std::io::Result<bool> {
-    let file = std::fs::File::open(path)?;
-    let mut version: ULONG = 0;
+/// Expand `%NAME%` environment variables in a path, as in `%USERPROFILE%\Downloads`.
+///
+/// A variable which isn't set is an error, rather than a folder literally
+/// named `%NAME%`.  A lone `%` is left alone, as are paths which aren't valid
+/// Unicode, which can't name a variable anyway.
+pub fn expand_env<P: AsRef<Path>>(path: P) -> error::Result<PathBuf> {
+    expand_env_with(path.as_ref(), |name| std::env::var_os(name))
+}
 
-    let ret = unsafe {
-        WofGetDriverVersion(
-            file.as_raw_handle() as HANDLE,
-            WOF_PROVIDER_FILE,
-            &mut version,
-        )
+fn expand_env_with<F: Fn(&str) -> Option<OsString>>(
+    path: &Path,
+    lookup: F,
+) -> error::Result<PathBuf> {
+    let s = match path.to_str() {
+        Some(s) if s.contains('%') => s,
+        _ => return Ok(path.to_path_buf()),
     };
 
-    if SUCCEEDED(ret) && version > 0 {
-        Ok(true)
-    } else {
-        Ok(false)
+    let mut expanded = OsString::new();
+    let mut rest = s;
+    while let Some(start) = rest.find('%') {
+        let after = &rest[start + 1..];
+        let end = match after.find('%') {
+            Some(end) if end > 0 && !after[..end].contains(|c| c == '\\' || c == '/') => end,
+            _ => {
+                expanded.push(&rest[..=start]);
+                rest = after;
+                continue;
+            }
+        };
+
+        let name = &after[..end];
+        let value = lookup(name).ok_or_else(|| CompactorError::UndefinedVariable {
+            name: name.to_string(),
+            path: path.to_path_buf(),
+        })?;
+        expanded.push(&rest[..start]);
+        expanded.push(value);
+        rest = &after[end + 1..];
     }
-}
+    expanded.push(rest);
 
-pub fn detect_compression<P: AsRef<OsStr>>(path: P) -> std::io::Result<Option<Compression>> {
-    let mut p: Vec<u16> = path.as_ref().encode_wide().collect();
-    p.push(0);
-
-    let mut is_external: BOOL = 0;
-    let mut provider: ULONG = 0;
-    let mut file_info: _WOF_FILE_COMPRESSION_INFO_V1 = unsafe { std::mem::zeroed() };
-    let mut len: ULONG = std::mem::size_of::<_WOF_FILE_COMPRESSION_INFO_V1>() as ULONG;
-
-    let ret = unsafe {
-        WofIsExternalFile(
-            p.as_ptr(),
-            &mut is_external,
-            &mut provider,
-            &mut file_info as *mut _ as PVOID,
-            &mut len,
-        )
-    };
+    Ok(PathBuf::from(expanded))
+}
 
-    if SUCCEEDED(ret) {
-        if is_external > 0 && provider == WOF_PROVIDER_FILE {
-            Ok(Compression::try_from(file_info.Algorithm).ok())
-        } else {
-            Ok(None)
-        }
-    } else {
-        Err(std::io::Error::from_raw_os_error(HRESULT_CODE(ret)))
+/// Check if a path is on another machine, either as a UNC path or on a mapped
+/// network drive.
+pub fn is_network_path<P: AsRef<Path>>(path: P) -> bool {
+    match path.as_ref().components().next() {
+        Some(Component::Prefix(prefix)) => match prefix.kind() {
+            Prefix::UNC(..) | Prefix::VerbatimUNC(..) => true,
+            Prefix::Disk(disk) | Prefix::VerbatimDisk(disk) => {
+                let root: Vec<u16> = OsStr::new(&format!("{}:\\", disk as char))
+                    .encode_wide()
+                    .chain(Some(0))
+                    .collect();
+                unsafe { GetDriveTypeW(root.as_ptr()) == DRIVE_REMOTE }
+            }
+            _ => false,
+        },
+        _ => false,
     }
 }
 
-unsafe fn as_byte_slice<T: Sized + Copy>(p: &T) -> &[u8] {
-    std::slice::from_raw_parts((p as *const T) as *const u8, std::mem::size_of::<T>())
+#[test]
+fn it_finds_the_filesystem() {
+    let fs = filesystem_name("Cargo.lock").expect("filesystem_name");
+    assert!(!fs.is_empty());
 }
 
-pub fn compress_file<P: AsRef<Path>>(path: P, compression: Compression) -> std::io::Result<bool> {
-    let file = std::fs::File::open(path)?;
-    compress_file_handle(&file, compression)
+#[test]
+fn it_finds_disk_space() {
+    let (free, total) = disk_space(".").expect("disk_space");
+    assert!(total > 0);
+    assert!(free <= total);
 }
 
-pub fn compress_file_handle(
-    file: &std::fs::File,
-    compression: Compression,
-) -> std::io::Result<bool> {
-    const LEN: usize = std::mem::size_of::<_WOF_EXTERNAL_INFO>()
-        + std::mem::size_of::<_FILE_PROVIDER_EXTERNAL_INFO_V1>();
-
-    let mut data = [0u8; LEN];
-    let (wof, inf) = data.split_at_mut(std::mem::size_of::<_WOF_EXTERNAL_INFO>());
-    unsafe {
-        wof.copy_from_slice(as_byte_slice(&_WOF_EXTERNAL_INFO::default()));
-        inf.copy_from_slice(as_byte_slice(&_FILE_PROVIDER_EXTERNAL_INFO_V1::from(
-            compression,
-        )));
-    }
-
-    let mut bytes_returned: DWORD = 0;
-
-    let ret = unsafe {
-        DeviceIoControl(
-            file.as_raw_handle() as HANDLE,
-            FSCTL_SET_EXTERNAL_BACKING,
-            &mut data as *mut _ as PVOID,
-            data.len() as DWORD,
-            std::ptr::null_mut(),
-            0,
-            &mut bytes_returned,
-            std::ptr::null_mut(),
-        )
-    };
-
-    // BOOL my arse
-    if SUCCEEDED(ret) {
-        Ok(true)
-    } else {
-        let e = HRESULT_CODE(ret);
-
-        if e == ERROR_COMPRESSION_NOT_BENEFICIAL {
-            Ok(false)
-        } else {
-            Err(std::io::Error::from_raw_os_error(e))
-        }
-    }
+#[test]
+fn it_makes_long_paths() {
+    assert_eq!(
+        long_path("C:\\Games\\foo.exe"),
+        PathBuf::from(r"\\?\C:\Games\foo.exe")
+    );
+    assert_eq!(
+        long_path("C:/Games/./foo.exe"),
+        PathBuf::from(r"\\?\C:\Games\foo.exe")
+    );
+    assert_eq!(long_path("C:\\"), PathBuf::from(r"\\?\C:\"));
+    assert_eq!(
+        long_path("\\\\server\\share\\foo.exe"),
+        PathBuf::from(r"\\?\UNC\server\share\foo.exe")
+    );
+    assert_eq!(long_path(r"\\?\C:\Games"), PathBuf::from(r"\\?\C:\Games"));
+    assert_eq!(long_path("Games\\foo.exe"), PathBuf::from("Games\\foo.exe"));
+    assert_eq!(
+        long_path("C:\\Games\\..\\foo.exe"),
+        PathBuf::from("C:\\Games\\..\\foo.exe")
+    );
 }
 
-pub fn uncompress_file<P: AsRef<Path>>(path: P) -> std::io::Result<()> {
-    let file = std::fs::File::open(path)?;
-    uncompress_file_handle(&file)
+#[test]
+fn it_handles_unc_paths() {
+    let unc = Path::new(r"\\fileserver\games\Steam\common");
+    let long = long_path(unc);
+
+    assert_eq!(
+        long,
+        PathBuf::from(r"\\?\UNC\fileserver\games\Steam\common")
+    );
+    assert_eq!(normal_path(&long), unc);
+    assert_eq!(normal_path(unc), unc);
+    assert_eq!(
+        normal_path(r"\\?\UNC\fileserver\games"),
+        PathBuf::from(r"\\fileserver\games\")
+    );
+    assert_eq!(normal_path(r"\\?\C:\Games"), PathBuf::from(r"C:\Games"));
+    assert_eq!(
+        normal_path("Games\\foo.exe"),
+        PathBuf::from("Games\\foo.exe")
+    );
+
+    assert!(is_network_path(unc));
+    assert!(is_network_path(&long));
+    assert!(!is_network_path("Games\\foo.exe"));
 }
 
-pub fn uncompress_file_handle(file: &std::fs::File) -> std::io::Result<()> {
-    let mut bytes_returned: DWORD = 0;
-
-    let ret = unsafe {
-        DeviceIoControl(
-            file.as_raw_handle() as HANDLE,
-            FSCTL_DELETE_EXTERNAL_BACKING,
-            std::ptr::null_mut(),
-            0,
-            std::ptr::null_mut(),
-            0,
-            &mut bytes_returned,
-            std::ptr::null_mut(),
-        )
+#[test]
+fn it_expands_environment_variables() {
+    let lookup = |name: &str| match name {
+        "USERPROFILE" => Some(OsString::from(r"C:\Users\Me")),
+        "ProgramFiles(x86)" => Some(OsString::from(r"C:\Program Files (x86)")),
+        _ => None,
     };
-
-    if SUCCEEDED(ret) {
-        Ok(())
-    } else {
-        Err(std::io::Error::from_raw_os_error(HRESULT_CODE(ret)))
-    }
-}
-
-#[link(name = "WofUtil")]
-extern "system" {
-    pub fn WofGetDriverVersion(
-        file_or_volume_handle: HANDLE,
-        provider: ULONG,
-        version: PULONG,
-    ) -> HRESULT;
-
-    pub fn WofIsExternalFile(
-        file_path: LPCWSTR,
-        is_external_file: PBOOL,
-        provider: PULONG,
-        external_file_info: PVOID,
-        length: PULONG,
-    ) -> HRESULT;
-
-    // This is a slightly simpler way of setting file backing.
-    pub fn WofSetFileDataLocation(
-        file_handle: HANDLE,
-        provider: ULONG,
-        external_file_info: PVOID,
-        length: ULONG,
-    ) -> HRESULT;
+    let expand = |path: &str| expand_env_with(Path::new(path), lookup);
+
+    assert_eq!(
+        expand(r"%USERPROFILE%\Downloads").unwrap(),
+        PathBuf::from(r"C:\Users\Me\Downloads")
+    );
+    assert_eq!(
+        expand(r"%ProgramFiles(x86)%\Steam").unwrap(),
+        PathBuf::from(r"C:\Program Files (x86)\Steam")
+    );
+    assert_eq!(expand(r"C:\Games").unwrap(), PathBuf::from(r"C:\Games"));
+    assert_eq!(
+        expand(r"C:\100% Orange Juice\50%\save").unwrap(),
+        PathBuf::from(r"C:\100% Orange Juice\50%\save")
+    );
 }
 
 #[test]
-fn compact_works_i_guess() {
-    let path = std::path::PathBuf::from("Cargo.lock");
-
-    let supported = system_supports_compression().expect("system_supports_compression");
-
-    if supported && file_supports_compression(&path).expect("file_supports_compression") {
-        uncompress_file(&path).expect("uncompress_file");
-        assert_eq!(None, detect_compression(&path).expect("detect_compression"));
-        compress_file(&path, Compression::default()).expect("compress_file");
-        assert_eq!(
-            Some(Compression::default()),
-            detect_compression(&path).expect("detect_compression")
-        );
-    }
+fn it_rejects_undefined_variables() {
+    let err = expand_env_with(Path::new(r"%NOPE%\Downloads"), |_| None).unwrap_err();
+
+    assert_eq!(err.code(), "undefined_variable");
+    assert_eq!(
+        err.to_string(),
+        r"%NOPE%\Downloads: environment variable %NOPE% isn't set"
+    );
 }
@@ -1,54 +1,331 @@
-use std::io;
+use std::cell::Cell;
+use std::hash::Hasher;
+use std::io::{self, Read, Seek, SeekFrom};
 use std::os::windows::fs::OpenOptionsExt;
-use std::path::PathBuf;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 use compresstimator::Compresstimator;
 use crossbeam_channel::{Receiver, Sender};
 use filetime::FileTime;
 use fs2::FileExt;
-use winapi::um::winnt::{FILE_READ_DATA, FILE_WRITE_ATTRIBUTES};
+use siphasher::sip128::{Hasher128, SipHasher};
+use winapi::shared::winerror::{
+    ERROR_LOCK_VIOLATION, ERROR_SHARING_VIOLATION, ERROR_USER_MAPPED_FILE,
+};
+use winapi::um::winbase::FILE_FLAG_BACKUP_SEMANTICS;
+use winapi::um::winnt::{
+    FILE_READ_DATA, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, FILE_WRITE_ATTRIBUTES,
+};
 
 use crate::background::Background;
 use crate::background::ControlToken;
-use crate::compact::{self, Compression};
+use crate::compact;
+use crate::config::Config;
+use crate::wof::{self, Compression};
+
+/// Files estimated to compress worse than this aren't worth compressing.
+pub const COMPRESSIBLE_RATIO: f64 = 0.95;
+
+/// The wait before retrying a locked file, doubling with each attempt.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+thread_local! {
+    static CATCHING_PANICS: Cell<bool> = Cell::new(false);
+}
+
+/// Whether this thread is in `catch_panics`, so the panic hook knows not to
+/// treat a panic as a crash.
+pub fn catching_panics() -> bool {
+    CATCHING_PANICS.with(Cell::get)
+}
+
+/// Run `f`, turning a panic into an error, so one bad file can't take down the
+/// whole run and leave the GUI waiting forever for its result.
+fn catch_panics<T>(f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    CATCHING_PANICS.with(|c| c.set(true));
+    let ret = panic::catch_unwind(AssertUnwindSafe(f));
+    CATCHING_PANICS.with(|c| c.set(false));
+
+    ret.unwrap_or_else(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .map(|s| s.to_string())
+            .or_else(|| payload.downcast_ref::<String>().cloned())
+            .unwrap_or_else(|| "unknown panic".to_string());
+
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("Internal error: {}", msg),
+        ))
+    })
+}
+
+/// A file to process, its size, and an algorithm to use for it instead of the
+/// compactor's own.
+pub type FileJob = (PathBuf, u64, Option<Compression>);
+
+/// The outcome of processing a file, how many retries it took, and whether it
+/// was left alone for being estimated to compress worse than `max_ratio`.
+pub type FileResult = (PathBuf, io::Result<bool>, u32, bool);
+
+/// How `handle_file` left a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Handled {
+    Changed,
+    Unchanged,
+    /// Compressible, but not by as much as asked for.
+    BelowThreshold,
+}
+
+/// How to treat each file, shared by every thread of a `BackgroundCompactor`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CompactOptions {
+    /// Read compressed files back and check they still match the original,
+    /// failing with `InvalidData` if they don't.
+    pub verify: bool,
+    /// Retry files which are locked by another process, such as a virus
+    /// scanner, up to this many times.
+    pub retries: u32,
+    /// Have one last go at files still in use after retrying, opening them
+    /// with backup semantics and without locking them.  This needs the backup
+    /// privilege, from `privilege::enable_backup`.
+    pub backup: bool,
+    /// Clear the read-only attribute from files while working on them,
+    /// putting it back afterwards.
+    pub clear_readonly: bool,
+    /// Leave alone files estimated to compress worse than this.  Those which
+    /// would still compress a little are reported as `Ok(false)`, flagged as
+    /// below the threshold.
+    pub max_ratio: f64,
+}
+
+impl Default for CompactOptions {
+    fn default() -> Self {
+        Self {
+            verify: false,
+            retries: 0,
+            backup: false,
+            clear_readonly: false,
+            max_ratio: COMPRESSIBLE_RATIO,
+        }
+    }
+}
+
+/// The largest estimated ratio worth compressing to save at least `pct`
+/// percent, never more than `COMPRESSIBLE_RATIO`.
+pub fn max_ratio_for(pct: u32) -> f64 {
+    COMPRESSIBLE_RATIO.min(1.0 - f64::from(pct.min(100)) / 100.0)
+}
+
+impl From<&Config> for CompactOptions {
+    /// The options asked for by a config.  `backup` also needs the privilege,
+    /// so callers should clear it if that's unavailable.
+    fn from(conf: &Config) -> Self {
+        Self {
+            verify: conf.verify,
+            retries: conf.retry_count,
+            backup: conf.backup_fallback,
+            clear_readonly: conf.clear_readonly,
+            max_ratio: max_ratio_for(conf.min_savings_pct),
+        }
+    }
+}
 
 #[derive(Debug)]
 pub struct BackgroundCompactor {
     compression: Option<Compression>,
-    files_in: Receiver<(PathBuf, u64)>,
-    files_out: Sender<(PathBuf, io::Result<bool>)>,
+    only: Option<Compression>,
+    threads: usize,
+    options: CompactOptions,
+    files_in: Receiver<FileJob>,
+    files_out: Sender<FileResult>,
 }
 
 impl BackgroundCompactor {
     pub fn new(
         compression: Option<Compression>,
-        files_in: Receiver<(PathBuf, u64)>,
-        files_out: Sender<(PathBuf, io::Result<bool>)>,
+        files_in: Receiver<FileJob>,
+        files_out: Sender<FileResult>,
     ) -> Self {
         Self {
             compression,
+            only: None,
+            threads: 1,
+            options: CompactOptions::default(),
             files_in,
             files_out,
         }
     }
+
+    /// When decompressing, leave alone any files not compressed with the given
+    /// algorithm.  These are reported as `Ok(false)`.
+    pub fn only(self, only: Option<Compression>) -> Self {
+        Self { only, ..self }
+    }
+
+    /// How to treat each file, by default compressing anything estimated to
+    /// compress at all, with no retries.
+    pub fn options(self, options: CompactOptions) -> Self {
+        Self { options, ..self }
+    }
+
+    /// Process files on the given number of threads.  Results are sent in
+    /// the order they finish, not the order they were received.
+    pub fn threads(self, threads: usize) -> Self {
+        Self {
+            threads: threads.max(1),
+            ..self
+        }
+    }
 }
 
-fn handle_file(file: &PathBuf, compression: Option<Compression>) -> io::Result<bool> {
+fn hash_contents(mut file: &std::fs::File) -> io::Result<u128> {
+    let mut hash = SipHasher::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    file.seek(SeekFrom::Start(0))?;
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            len => hash.write(&buf[..len]),
+        }
+    }
+
+    let h = hash.finish128();
+    Ok((u128::from(h.h1) << 64) | u128::from(h.h2))
+}
+
+fn verify_contents(file: &std::fs::File, expected: u128) -> io::Result<()> {
+    if hash_contents(file)? == expected {
+        return Ok(());
+    }
+
+    // Don't leave a file we can't trust compressed.
+    let _ = wof::decompress_file_handle(file);
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Verification failed: contents changed after compression",
+    ))
+}
+
+/// Check if an error means another process has the file open or mapped.
+pub fn is_in_use(err: &io::Error) -> bool {
+    is_transient(err) || err.raw_os_error() == Some(ERROR_USER_MAPPED_FILE as i32)
+}
+
+/// Check if an error is likely to go away by itself if we wait a bit.
+fn is_transient(err: &io::Error) -> bool {
+    match err.raw_os_error() {
+        Some(code) => code == ERROR_SHARING_VIOLATION as i32 || code == ERROR_LOCK_VIOLATION as i32,
+        None => false,
+    }
+}
+
+/// Run `f` with a file's read-only attribute cleared, restoring it after.
+///
+/// Failing to restore it is an error even if `f` succeeded, so the user can
+/// find out which files were left changed.
+fn with_readonly_cleared<T>(file: &Path, f: impl FnOnce() -> io::Result<T>) -> io::Result<T> {
+    let file = compact::long_path(file);
+    let mut perms = std::fs::metadata(&file)?.permissions();
+    if !perms.readonly() {
+        return f();
+    }
+
+    perms.set_readonly(false);
+    std::fs::set_permissions(&file, perms.clone())?;
+    let ret = f();
+
+    perms.set_readonly(true);
+    match std::fs::set_permissions(&file, perms) {
+        Ok(()) => ret,
+        Err(e) => Err(io::Error::new(
+            e.kind(),
+            format!("Error restoring read-only attribute: {}", e),
+        )),
+    }
+}
+
+/// Compress or decompress a file.
+///
+/// Normally the file is locked while we work on it.  With `backup` it's opened
+/// with backup semantics instead, sharing it with anyone who already has it
+/// open.  Only `verify` and `max_ratio` are used from the options.
+fn handle_file(
+    file: &PathBuf,
+    compression: Option<Compression>,
+    only: Option<Compression>,
+    options: CompactOptions,
+    backup: bool,
+) -> io::Result<Handled> {
+    let CompactOptions {
+        verify, max_ratio, ..
+    } = options;
+    // Deeply nested files can exceed MAX_PATH, which most Win32 calls can't
+    // handle without the extended-length prefix.
+    let file = &compact::long_path(file);
+
+    if let (None, Some(only)) = (compression, only) {
+        if wof::query_compression(file)? != Some(only) {
+            return Ok(Handled::Unchanged);
+        }
+    }
+
     let est = Compresstimator::with_block_size(8192);
     let meta = std::fs::metadata(&file)?;
-    let handle = std::fs::OpenOptions::new()
-        .access_mode(FILE_WRITE_ATTRIBUTES | FILE_READ_DATA)
-        .open(&file)?;
+    let mut options = std::fs::OpenOptions::new();
+    options.access_mode(FILE_WRITE_ATTRIBUTES | FILE_READ_DATA);
+    if backup {
+        options
+            .share_mode(FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE)
+            .custom_flags(FILE_FLAG_BACKUP_SEMANTICS);
+    }
+    let handle = options.open(&file)?;
 
-    handle.try_lock_exclusive()?;
+    if !backup {
+        handle.try_lock_exclusive()?;
+    }
 
     let ret = match compression {
         Some(compression) => match est.compresstimate(&handle, meta.len()) {
-            Ok(ratio) if ratio < 0.95 => compact::compress_file_handle(&handle, compression),
-            Ok(_) => Ok(false),
+            // Changing algorithm requires removing any existing backing first.
+            Ok(ratio) if ratio < max_ratio => {
+                let expected = if verify {
+                    hash_contents(&handle).map(Some)
+                } else {
+                    Ok(None)
+                };
+
+                expected.and_then(|expected| {
+                    wof::query_compression(&file)
+                        .and_then(|current| match current {
+                            Some(_) => wof::decompress_file_handle(&handle),
+                            None => Ok(()),
+                        })
+                        .and_then(|_| wof::compress_file_handle(&handle, compression))
+                        .and_then(|compressed| match expected {
+                            Some(hash) if compressed => {
+                                verify_contents(&handle, hash).map(|_| true)
+                            }
+                            _ => Ok(compressed),
+                        })
+                        .map(|compressed| {
+                            if compressed {
+                                Handled::Changed
+                            } else {
+                                Handled::Unchanged
+                            }
+                        })
+                })
+            }
+            Ok(ratio) if ratio < COMPRESSIBLE_RATIO => Ok(Handled::BelowThreshold),
+            Ok(_) => Ok(Handled::Unchanged),
             Err(e) => Err(e),
         },
-        None => compact::uncompress_file_handle(&handle).map(|_| true),
+        None => wof::decompress_file_handle(&handle).map(|_| Handled::Changed),
     };
 
     let _ = filetime::set_file_handle_times(
@@ -57,8 +334,63 @@ fn handle_file(file: &PathBuf, compression: Option<Compression>) -> io::Result<b
         Some(FileTime::from_last_modification_time(&meta)),
     );
 
-    handle.unlock()?;
+    if !backup {
+        handle.unlock()?;
+    }
+
+    ret
+}
+
+fn test_failed(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, msg)
+}
+
+/// Check compression works at all, by compressing and verifying a scratch file
+/// in `dir` the same way as real files, then decompressing it again.
+///
+/// Returns the size of the scratch file and its size while compressed.
+pub fn self_test(dir: &Path, compression: Compression) -> io::Result<(u64, u64)> {
+    if !wof::system_supports_compression()? {
+        return Err(test_failed(
+            "Windows Overlay Filter compression isn't available".to_string(),
+        ));
+    }
+
+    let file = dir.join(format!("compactor-test-{}.txt", std::process::id()));
+    let contents = "Compactor compression test. ".repeat(4096);
+    std::fs::write(&file, &contents)?;
+
+    let test = || {
+        let options = CompactOptions {
+            verify: true,
+            ..CompactOptions::default()
+        };
+        if handle_file(&file, Some(compression), None, options, false)? != Handled::Changed {
+            return Err(test_failed("The test file wasn't compressed".to_string()));
+        }
+
+        let detected = wof::query_compression(&file)?;
+        if detected != Some(compression) {
+            return Err(test_failed(format!(
+                "The test file was compressed with {:?} instead of {}",
+                detected, compression
+            )));
+        }
+
+        let compressed = compact::compressed_size(&file)?;
+        handle_file(&file, None, None, CompactOptions::default(), false)?;
+
+        if wof::query_compression(&file)?.is_some() || std::fs::read_to_string(&file)? != contents {
+            return Err(test_failed(
+                "The test file didn't decompress intact".to_string(),
+            ));
+        }
+
+        Ok((contents.len() as u64, compressed))
+    };
 
+    let ret = test();
+    let _ = std::fs::remove_file(&file);
     ret
 }
 
@@ -67,16 +399,307 @@ impl Background for BackgroundCompactor {
     type Status = ();
 
     fn run(self, control: &ControlToken<Self::Status>) -> Self::Output {
-        for file in &self.files_in {
-            if control.is_cancelled_with_pause() {
-                break;
-            }
+        let workers: Vec<_> = (1..self.threads)
+            .map(|_| {
+                let files_in = self.files_in.clone();
+                let files_out = self.files_out.clone();
+                let control = control.clone();
+                let (compression, only, options) = (self.compression, self.only, self.options);
+
+                std::thread::spawn(move || {
+                    compact_files(
+                        &files_in,
+                        &files_out,
+                        compression,
+                        only,
+                        options,
+                        &control,
+                        handle_file,
+                    )
+                })
+            })
+            .collect();
+
+        compact_files(
+            &self.files_in,
+            &self.files_out,
+            self.compression,
+            self.only,
+            self.options,
+            control,
+            handle_file,
+        );
+
+        for worker in workers {
+            let _ = worker.join();
+        }
+    }
+}
 
-            let file = file.0;
-            let ret = handle_file(&file, self.compression);
-            if self.files_out.send((file, ret)).is_err() {
-                break;
+/// Process files until `files_in` runs dry, with `handle` doing each one as
+/// `handle_file` does.
+fn compact_files<H>(
+    files_in: &Receiver<FileJob>,
+    files_out: &Sender<FileResult>,
+    compression: Option<Compression>,
+    only: Option<Compression>,
+    options: CompactOptions,
+    control: &ControlToken<()>,
+    handle: H,
+) where
+    H: Fn(
+        &PathBuf,
+        Option<Compression>,
+        Option<Compression>,
+        CompactOptions,
+        bool,
+    ) -> io::Result<Handled>,
+{
+    let CompactOptions {
+        retries,
+        backup,
+        clear_readonly,
+        ..
+    } = options;
+    for file in files_in {
+        if control.is_cancelled_with_pause() {
+            break;
+        }
+
+        let (file, _, chosen) = file;
+        let compression = chosen.or(compression);
+        let mut attempts = 0;
+        let mut process = || loop {
+            match catch_panics(|| handle(&file, compression, only, options, false)) {
+                Err(ref e) if is_transient(e) && attempts < retries && !control.is_cancelled() => {
+                    // Capped so a silly retry count can't have us waiting for days.
+                    std::thread::sleep(RETRY_DELAY * 2u32.pow(attempts.min(6)));
+                    attempts += 1;
+                }
+                Err(e) if backup && is_in_use(&e) => {
+                    attempts += 1;
+                    // Keep the original error if this fails too, it's clearer.
+                    break catch_panics(|| handle(&file, compression, only, options, true))
+                        .map_err(|_| e);
+                }
+                ret => break ret,
             }
+        };
+        let ret = if clear_readonly {
+            with_readonly_cleared(&file, process)
+        } else {
+            process()
+        };
+
+        let below_threshold = matches!(ret, Ok(Handled::BelowThreshold));
+        let ret = ret.map(|handled| handled == Handled::Changed);
+        if files_out
+            .send((file, ret, attempts, below_threshold))
+            .is_err()
+        {
+            break;
         }
     }
 }
+
+#[test]
+fn it_hashes_contents() {
+    use std::io::Write;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("test.txt");
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .open(&path)
+        .unwrap();
+
+    file.write_all(b"hello").unwrap();
+    let hash = hash_contents(&file).unwrap();
+    assert_eq!(hash_contents(&file).unwrap(), hash);
+    assert!(verify_contents(&file, hash).is_ok());
+
+    file.write_all(b" world").unwrap();
+    assert_ne!(hash_contents(&file).unwrap(), hash);
+    assert_eq!(
+        verify_contents(&file, hash).unwrap_err().kind(),
+        io::ErrorKind::InvalidData
+    );
+}
+
+#[test]
+fn it_spots_transient_errors() {
+    assert!(is_transient(&io::Error::from_raw_os_error(
+        ERROR_SHARING_VIOLATION as i32
+    )));
+    assert!(is_transient(&io::Error::from_raw_os_error(
+        ERROR_LOCK_VIOLATION as i32
+    )));
+    assert!(!is_transient(&io::Error::from_raw_os_error(5)));
+    assert!(!is_transient(&io::Error::new(
+        io::ErrorKind::InvalidData,
+        "Verification failed"
+    )));
+
+    assert!(is_in_use(&io::Error::from_raw_os_error(
+        ERROR_USER_MAPPED_FILE as i32
+    )));
+    assert!(!is_transient(&io::Error::from_raw_os_error(
+        ERROR_USER_MAPPED_FILE as i32
+    )));
+}
+
+#[test]
+fn it_clears_readonly_temporarily() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("test.txt");
+    std::fs::write(&path, "hello").unwrap();
+
+    let mut perms = std::fs::metadata(&path).unwrap().permissions();
+    perms.set_readonly(true);
+    std::fs::set_permissions(&path, perms.clone()).unwrap();
+
+    let writable = with_readonly_cleared(&path, || {
+        Ok(!std::fs::metadata(&path)?.permissions().readonly())
+    });
+    assert!(writable.unwrap());
+    assert!(std::fs::metadata(&path).unwrap().permissions().readonly());
+
+    perms.set_readonly(false);
+    std::fs::set_permissions(&path, perms).unwrap();
+}
+
+#[test]
+fn it_compresses_long_paths() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let mut path = dir.path().to_path_buf();
+    while path.as_os_str().len() < 300 {
+        path.push("a-rather-long-directory-name");
+    }
+    std::fs::create_dir_all(&path).unwrap();
+
+    let file = path.join("test.txt");
+    assert!(file.as_os_str().len() > 260);
+    std::fs::write(&file, crate::testutil::compressible_text()).unwrap();
+
+    if !wof::file_supports_compression(&file).unwrap_or(false) {
+        return;
+    }
+
+    assert_eq!(
+        handle_file(
+            &file,
+            Some(Compression::Xpress4k),
+            None,
+            CompactOptions {
+                verify: true,
+                ..CompactOptions::default()
+            },
+            false
+        )
+        .unwrap(),
+        Handled::Changed
+    );
+    assert_eq!(
+        wof::query_compression(compact::long_path(&file)).unwrap(),
+        Some(Compression::Xpress4k)
+    );
+    assert!(compact::compressed_size(&file).unwrap() < 16384 * 12);
+}
+
+#[test]
+fn it_skips_files_below_the_savings_threshold() {
+    assert_eq!(CompactOptions::default().max_ratio, COMPRESSIBLE_RATIO);
+    assert_eq!(max_ratio_for(0), COMPRESSIBLE_RATIO);
+    assert_eq!(max_ratio_for(2), COMPRESSIBLE_RATIO);
+    assert!((max_ratio_for(40) - 0.6).abs() < 1e-9);
+    assert_eq!(max_ratio_for(250), 0.0);
+
+    let conf = Config {
+        min_savings_pct: 40,
+        ..Config::default()
+    };
+    assert_eq!(CompactOptions::from(&conf).max_ratio, max_ratio_for(40));
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let file = dir.path().join("test.txt");
+    std::fs::write(&file, crate::testutil::compressible_text()).unwrap();
+
+    if !wof::file_supports_compression(&file).unwrap_or(false) {
+        return;
+    }
+
+    assert_eq!(
+        handle_file(
+            &file,
+            Some(Compression::Xpress4k),
+            None,
+            CompactOptions {
+                max_ratio: 0.0,
+                ..CompactOptions::default()
+            },
+            false
+        )
+        .unwrap(),
+        Handled::BelowThreshold
+    );
+    assert_eq!(
+        wof::query_compression(compact::long_path(&file)).unwrap(),
+        None
+    );
+}
+
+#[test]
+fn it_tests_compression() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let probe = dir.path().join("probe");
+    std::fs::write(&probe, "probe").unwrap();
+    let supported = wof::file_supports_compression(&probe).unwrap_or(false);
+    std::fs::remove_file(&probe).unwrap();
+
+    if !supported {
+        return;
+    }
+
+    let (size, compressed) = self_test(dir.path(), Compression::Xpress4k).unwrap();
+    assert!(compressed < size);
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn it_survives_panics() {
+    let (send_file, files_in) = crossbeam_channel::unbounded::<FileJob>();
+    let (files_out, results) = crossbeam_channel::unbounded::<FileResult>();
+    for file in &["a.bin", "bad.bin", "c.bin"] {
+        send_file.send((PathBuf::from(file), 0, None)).unwrap();
+    }
+    drop(send_file);
+
+    let handle = |file: &PathBuf, _, _, _, _| -> io::Result<Handled> {
+        if file.ends_with("bad.bin") {
+            panic!("corrupt header in {}", file.display());
+        }
+        Ok(Handled::Changed)
+    };
+    compact_files(
+        &files_in,
+        &files_out,
+        Some(Compression::default()),
+        None,
+        CompactOptions::default(),
+        &ControlToken::new(),
+        handle,
+    );
+    drop(files_out);
+
+    let results: Vec<FileResult> = results.iter().collect();
+    assert_eq!(results.len(), 3);
+    assert!(results[0].1.as_ref().unwrap());
+    assert_eq!(results[1].0, PathBuf::from("bad.bin"));
+    let err = results[1].1.as_ref().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::Other);
+    assert!(err.to_string().contains("corrupt header in bad.bin"));
+    assert!(results[2].1.as_ref().unwrap());
+    assert!(!catching_panics());
+}
@@ -1,22 +1,197 @@
-use std::io;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
 
-use globset::{Glob, GlobSet, GlobSetBuilder};
+use globset::{Glob, GlobBuilder, GlobSet, GlobSetBuilder};
 use serde_derive::{Deserialize, Serialize};
 
-use crate::compact::Compression;
+use crate::compact;
+use crate::error::{self, CompactorError};
+use crate::jsonfile::JsonFile;
+use crate::wof::Compression;
 
-#[derive(Debug, Default)]
-pub struct ConfigFile {
-    backing: Option<PathBuf>,
-    config: Config,
-}
+pub type ConfigFile = JsonFile<Config>;
+
+pub type ProfileFile = JsonFile<Profiles>;
+
+pub type PresetFile = JsonFile<Presets>;
+
+/// Settings for particular folders and everything in them, by path.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Profiles(#[serde(deserialize_with = "expand_profile_paths")] HashMap<PathBuf, Config>);
+
+/// Settings saved under a name of the user's choosing, to be loaded again as
+/// the global config.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Presets(HashMap<String, Config>);
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Config {
     pub decimal: bool,
     pub compression: Compression,
+    /// Choose an algorithm for each file instead of using `compression` for
+    /// all of them.  See `auto` for how.
+    pub auto_compression: bool,
     pub excludes: Vec<String>,
+    pub exclude_extensions: Vec<String>,
+    /// Only compress files with these extensions, unless excluded anyway.
+    /// Empty to consider every extension.
+    pub include_only: Vec<String>,
+    /// Match exclude globs regardless of case, as Windows itself does.
+    pub case_insensitive: bool,
+    /// Also exclude `INCOMPRESSIBLE_EXTENSIONS`, formats which are compressed
+    /// already and never worth compressing again.
+    pub skip_incompressible: bool,
+    /// How many folders deep to look for files, or `None` for no limit.
+    pub max_depth: Option<usize>,
+    /// Walk into junctions and symbolic links, which may lead outside the
+    /// folder or back into it.
+    pub follow_links: bool,
+    /// Walk folders in order of name rather than filesystem order, for scans
+    /// which always find files in the same order.  Not in the interface.
+    pub sorted_walk: bool,
+    pub skip_hidden: bool,
+    /// Skip system files and folders, rather than just never compressing
+    /// system files.
+    pub skip_system: bool,
+    pub min_size: Option<u64>,
+    pub max_size: Option<u64>,
+    /// Only compress files which haven't been modified for this many days,
+    /// leaving those still in use alone.
+    pub min_age_days: Option<u64>,
+    pub threads: usize,
+    pub verify: bool,
+    /// Fingerprint every file before compressing a folder and check them all
+    /// again afterwards, reporting any whose contents changed.
+    pub verify_folder: bool,
+    /// Compress Windows system files too.  Only for people who really mean it.
+    pub allow_protected: bool,
+    /// Retry files in use by other programs with backup privileges, which needs
+    /// Compactor to be run as administrator.
+    pub backup_fallback: bool,
+    /// Clear the read-only attribute from files while compressing them, rather
+    /// than skipping them.
+    pub clear_readonly: bool,
+    /// Compress sparse files, such as some virtual machine images, rather than
+    /// skipping them.  WOF fills in their holes, which can leave them bigger on
+    /// disk than before.
+    pub compress_sparse: bool,
+    /// Decompress files again if compressing them left them no smaller on disk
+    /// than they were, rather than leaving them compressed for no benefit.
+    /// Files already compressed another way are left as they are.
+    pub revert_if_larger: bool,
+    /// Analyse the last folder as soon as Compactor starts, rather than waiting
+    /// to be asked.  Off by default, since it may be a slow network drive.
+    pub auto_analyse: bool,
+    /// Pop up a notification when a compression run finishes.
+    pub notify_on_complete: bool,
+    /// Pause compression while running on battery, resuming on mains power.
+    pub pause_on_battery: bool,
+    /// Ask before starting to compress, showing what will be compressed.
+    pub confirm_before_compress: bool,
+    /// Write a list of each file compressed, and how, into the folder as
+    /// `manifest::MANIFEST_NAME`, so exactly those files can be decompressed later.
+    pub write_manifest: bool,
+    /// Only ever analyse folders, refusing to compress or decompress anything,
+    /// for auditing machines without risk of changing them.
+    pub read_only_mode: bool,
+    /// How many times to retry files locked by another process.
+    pub retry_count: u32,
+    /// Minimum milliseconds between progress updates, with any in between
+    /// dropped, so slow machines aren't swamped redrawing them.
+    pub status_interval_ms: u64,
+    pub priority: Priority,
+    /// Which files to compress first.
+    pub order: Order,
+    /// How much to write to the diagnostic log.  Only the global setting is
+    /// used, since the log isn't per-folder.
+    pub log_level: LogLevel,
+    /// The colour scheme of the interface.
+    pub theme: Theme,
+    /// Limit compression to this many MiB per second, to leave the disk free
+    /// for other things.
+    pub throughput_limit: Option<u64>,
+    /// Stop compressing after this many seconds, leaving the rest for next
+    /// time.
+    pub max_runtime_secs: Option<u64>,
+    /// Leave alone files estimated to save less than this percentage of their
+    /// size, sparing the disk writes.  0 leaves only incompressible files.
+    pub min_savings_pct: u32,
+    pub preset: Option<Preset>,
+}
+
+/// How much CPU time to take from other programs while compressing.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Priority {
+    Normal,
+    BelowNormal,
+    Idle,
+}
+
+impl Default for Priority {
+    fn default() -> Self {
+        Priority::Normal
+    }
+}
+
+/// The order to compress files in.  Largest first gets the biggest savings in
+/// early, which helps when a run might be stopped or time out.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Order {
+    /// The order files were found in while analysing.
+    Default,
+    LargestFirst,
+    SmallestFirst,
+}
+
+impl Default for Order {
+    fn default() -> Self {
+        Order::Default
+    }
+}
+
+/// How much detail to write to the diagnostic log.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum LogLevel {
+    Off,
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl Default for LogLevel {
+    fn default() -> Self {
+        LogLevel::Info
+    }
+}
+
+/// Colour schemes for the interface.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Theme {
+    /// Follow the Windows light, dark, and high contrast settings.
+    System,
+    Light,
+    Dark,
+    HighContrast,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme::System
+    }
+}
+
+/// One-click settings for people who don't want to think about algorithms.
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Preset {
+    Fast,
+    Balanced,
+    Maximum,
 }
 
 impl Default for Config {
@@ -24,6 +199,7 @@ impl Default for Config {
         Self {
             decimal: false,
             compression: Compression::default(),
+            auto_compression: false,
             excludes: vec![
                 "*:\\Windows*",
                 "*:\\System Volume Information*",
@@ -75,53 +251,459 @@ impl Default for Config {
             .into_iter()
             .map(String::from)
             .collect(),
+            exclude_extensions: vec![],
+            include_only: vec![],
+            case_insensitive: true,
+            skip_incompressible: true,
+            max_depth: None,
+            follow_links: false,
+            sorted_walk: false,
+            skip_hidden: false,
+            skip_system: true,
+            min_size: None,
+            max_size: None,
+            min_age_days: None,
+            threads: 0,
+            verify: false,
+            verify_folder: false,
+            allow_protected: false,
+            backup_fallback: false,
+            clear_readonly: false,
+            compress_sparse: false,
+            revert_if_larger: true,
+            auto_analyse: false,
+            notify_on_complete: false,
+            pause_on_battery: false,
+            confirm_before_compress: true,
+            write_manifest: false,
+            read_only_mode: false,
+            retry_count: 3,
+            status_interval_ms: 100,
+            priority: Priority::default(),
+            order: Order::default(),
+            log_level: LogLevel::default(),
+            theme: Theme::default(),
+            throughput_limit: None,
+            max_runtime_secs: None,
+            min_savings_pct: 0,
+            preset: None,
         }
     }
 }
 
-impl ConfigFile {
-    pub fn new<P: AsRef<Path>>(path: P) -> Self {
-        Self {
-            backing: Some(path.as_ref().to_owned()),
-            config: std::fs::read(path)
-                .and_then(|data| {
-                    serde_json::from_slice::<Config>(&data)
-                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
-                })
-                .unwrap_or_default(),
+impl Preset {
+    pub fn compression(self) -> Compression {
+        match self {
+            Preset::Fast => Compression::Xpress4k,
+            Preset::Balanced => Compression::Xpress8k,
+            Preset::Maximum => Compression::Lzx,
         }
     }
 
-    pub fn save(&self) -> io::Result<()> {
-        match &self.backing {
-            Some(path) => {
-                if let Some(dir) = path.parent() {
-                    std::fs::create_dir_all(dir)?;
-                }
+    /// Set the underlying fields of a config to match this preset.
+    pub fn apply(self, config: &mut Config) {
+        config.compression = self.compression();
+        config.auto_compression = false;
+        config.excludes = Config::default().excludes;
+        config.min_size = None;
+        config.max_size = None;
+        config.preset = Some(self);
+    }
 
-                let data = serde_json::to_string_pretty(&self.config).expect("Serialize");
-                std::fs::write(path, &data)
-            }
-            None => Ok(()),
+    /// Check if a config still has the settings this preset would give it.
+    pub fn matches(self, config: &Config) -> bool {
+        config.compression == self.compression()
+            && !config.auto_compression
+            && config.excludes == Config::default().excludes
+            && config.min_size.is_none()
+            && config.max_size.is_none()
+    }
+}
+
+impl std::fmt::Display for Preset {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Preset::Fast => write!(f, "Fast"),
+            Preset::Balanced => write!(f, "Balanced"),
+            Preset::Maximum => write!(f, "Maximum"),
+        }
+    }
+}
+
+impl FromStr for Preset {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Fast" => Ok(Preset::Fast),
+            "Balanced" => Ok(Preset::Balanced),
+            "Maximum" => Ok(Preset::Maximum),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Priority {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Priority::Normal => write!(f, "Normal"),
+            Priority::BelowNormal => write!(f, "BelowNormal"),
+            Priority::Idle => write!(f, "Idle"),
+        }
+    }
+}
+
+impl FromStr for Priority {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Normal" => Ok(Priority::Normal),
+            "BelowNormal" => Ok(Priority::BelowNormal),
+            "Idle" => Ok(Priority::Idle),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Order {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Order::Default => write!(f, "Default"),
+            Order::LargestFirst => write!(f, "LargestFirst"),
+            Order::SmallestFirst => write!(f, "SmallestFirst"),
+        }
+    }
+}
+
+impl FromStr for Order {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Default" => Ok(Order::Default),
+            "LargestFirst" => Ok(Order::LargestFirst),
+            "SmallestFirst" => Ok(Order::SmallestFirst),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LogLevel::Off => write!(f, "Off"),
+            LogLevel::Error => write!(f, "Error"),
+            LogLevel::Warn => write!(f, "Warn"),
+            LogLevel::Info => write!(f, "Info"),
+            LogLevel::Debug => write!(f, "Debug"),
+        }
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "Off" => Ok(LogLevel::Off),
+            "Error" => Ok(LogLevel::Error),
+            "Warn" => Ok(LogLevel::Warn),
+            "Info" => Ok(LogLevel::Info),
+            "Debug" => Ok(LogLevel::Debug),
+            _ => Err(()),
+        }
+    }
+}
+
+impl std::fmt::Display for Theme {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Theme::System => write!(f, "System"),
+            Theme::Light => write!(f, "Light"),
+            Theme::Dark => write!(f, "Dark"),
+            Theme::HighContrast => write!(f, "HighContrast"),
+        }
+    }
+}
+
+impl FromStr for Theme {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "System" => Ok(Theme::System),
+            "Light" => Ok(Theme::Light),
+            "Dark" => Ok(Theme::Dark),
+            "HighContrast" => Ok(Theme::HighContrast),
+            _ => Err(()),
+        }
+    }
+}
+
+impl From<LogLevel> for log::LevelFilter {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Off => log::LevelFilter::Off,
+            LogLevel::Error => log::LevelFilter::Error,
+            LogLevel::Warn => log::LevelFilter::Warn,
+            LogLevel::Info => log::LevelFilter::Info,
+            LogLevel::Debug => log::LevelFilter::Debug,
+        }
+    }
+}
+
+/// Profiles may be written by hand with `%USERPROFILE%` and the like, which
+/// are expanded so they match chosen folders.  One which can't be is kept as
+/// it is, and so never matches.
+fn expand_profile_path(path: PathBuf) -> PathBuf {
+    compact::expand_env(&path).unwrap_or_else(|e| {
+        log::warn!("Profile for {}: {}", path.display(), e);
+        path
+    })
+}
+
+fn expand_profile_paths<'de, D>(d: D) -> Result<HashMap<PathBuf, Config>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let profiles: HashMap<PathBuf, Config> = serde::Deserialize::deserialize(d)?;
+    Ok(profiles
+        .into_iter()
+        .map(|(path, c)| (expand_profile_path(path), c))
+        .collect())
+}
+
+impl Profiles {
+    /// Find the profile for a folder, or its closest parent with one.
+    pub fn get(&self, path: &Path) -> Option<Config> {
+        path.ancestors()
+            .find_map(|p| self.0.get(p))
+            .cloned()
+    }
+
+    pub fn insert<P: AsRef<Path>>(&mut self, path: P, c: Config) {
+        self.0.insert(path.as_ref().to_owned(), c);
+    }
+
+    pub fn remove<P: AsRef<Path>>(&mut self, path: P) -> Option<Config> {
+        self.0.remove(path.as_ref())
+    }
+}
+
+impl Presets {
+    /// The names of all saved presets, in alphabetical order.
+    pub fn names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.0.keys().cloned().collect();
+        names.sort();
+        names
+    }
+
+    pub fn get(&self, name: &str) -> Option<Config> {
+        self.0.get(name).cloned()
+    }
+
+    pub fn insert<S: Into<String>>(&mut self, name: S, c: Config) {
+        self.0.insert(name.into(), c);
+    }
+}
+
+/// Lowercase an extension and strip any leading dots, so `.JPG` and `jpg` are
+/// equivalent.
+pub fn normalize_extension(ext: &str) -> String {
+    ext.trim().trim_start_matches('.').to_lowercase()
+}
+
+/// Parse a comma-separated list of extensions, as entered in the GUI.
+pub fn parse_extensions(s: &str) -> Vec<String> {
+    let mut exts: Vec<String> = s
+        .split(',')
+        .map(normalize_extension)
+        .filter(|e| !e.is_empty())
+        .collect();
+    exts.sort();
+    exts.dedup();
+    exts
+}
+
+fn glob_error(index: usize, e: globset::Error) -> CompactorError {
+    CompactorError::Glob {
+        line: index + 1,
+        message: e.to_string(),
+    }
+}
+
+/// Check a newline-separated list of exclude globs, as entered in the GUI,
+/// reporting the line and pattern of the first which doesn't parse.
+pub fn validate_excludes(excludes: &str) -> error::Result<()> {
+    for (i, glob) in excludes.split('\n').enumerate() {
+        Glob::new(glob).map_err(|e| glob_error(i, e))?;
+    }
+
+    Ok(())
+}
+
+/// Read exclude globs from a .gitignore-style file, one per line, skipping
+/// blank lines and `#` comments, and reporting the line of the first which
+/// doesn't parse.
+pub fn parse_exclude_file(text: &str) -> error::Result<Vec<String>> {
+    let mut globs = vec![];
+    for (i, line) in text.trim_start_matches('\u{feff}').lines().enumerate() {
+        let glob = line.trim();
+        if glob.is_empty() || glob.starts_with('#') {
+            continue;
         }
+
+        Glob::new(glob).map_err(|e| glob_error(i, e))?;
+        globs.push(glob.to_string());
     }
 
-    pub fn current(&self) -> Config {
-        self.config.clone()
+    Ok(globs)
+}
+
+/// Upper bounds on numeric settings, past which they can only be typos.
+pub const MAX_THREADS: u64 = 256;
+pub const MAX_DEPTH: u64 = 4096;
+pub const MAX_FILE_SIZE: u64 = 1 << 50;
+pub const MAX_THROUGHPUT_LIMIT: u64 = 1 << 20;
+pub const MAX_RUNTIME_SECS: u64 = 30 * 24 * 60 * 60;
+pub const MAX_MIN_SAVINGS_PCT: u64 = 99;
+pub const MAX_MIN_AGE_DAYS: u64 = 100 * 365;
+
+/// Check a number from the settings page is a whole number from 0 to `max`,
+/// naming the setting if not.
+pub fn check_number(name: &str, value: f64, max: u64) -> error::Result<u64> {
+    if value.is_finite() && value >= 0.0 && value.fract() == 0.0 && value <= max as f64 {
+        Ok(value as u64)
+    } else {
+        Err(CompactorError::Config(format!(
+            "{} must be a whole number from 0 to {}, not {}",
+            name, max, value
+        )))
     }
+}
+
+/// Well-known formats with compression of their own, so new users don't spend
+/// an hour compressing their photo and video folders for nothing.
+pub const INCOMPRESSIBLE_EXTENSIONS: &[&str] = &[
+    "7z", "aac", "apk", "avi", "avif", "bz2", "cab", "docx", "flac", "gif", "gz", "heic", "jpeg",
+    "jpg", "lz4", "m4a", "m4v", "mkv", "mov", "mp3", "mp4", "ogg", "opus", "png", "pptx", "rar",
+    "webm", "webp", "xlsx", "xz", "zip", "zst",
+];
 
-    pub fn replace(&mut self, c: Config) {
-        self.config = c;
+/// Add a case-insensitive `*.ext` glob for each extension.
+fn add_extensions<S: AsRef<str>>(globs: &mut GlobSetBuilder, exts: &[S]) -> error::Result<()> {
+    let exts = exts.iter().map(|e| normalize_extension(e.as_ref()));
+    for ext in exts.filter(|e| !e.is_empty()) {
+        let glob = GlobBuilder::new(&format!("*.{}", ext))
+            .case_insensitive(true)
+            .build()
+            .map_err(|e| CompactorError::Extension {
+                extension: ext.clone(),
+                message: e.to_string(),
+            })?;
+        globs.add(glob);
     }
+    Ok(())
 }
 
 impl Config {
-    pub fn globset(&self) -> Result<GlobSet, String> {
+    /// The number of threads to compress with, resolving 0 to one per CPU, and
+    /// limited to `MAX_THREADS` however the setting was made.
+    pub fn worker_threads(&self) -> usize {
+        match self.threads {
+            0 => std::thread::available_parallelism().map_or(1, |n| n.get()),
+            n => n.min(MAX_THREADS as usize),
+        }
+    }
+
+    /// Check settings which are each fine alone, but not together.
+    pub fn validate(&self) -> error::Result<()> {
+        match (self.min_size, self.max_size) {
+            (Some(min), Some(max)) if min > max => Err(CompactorError::Config(format!(
+                "The minimum file size ({} bytes) is larger than the maximum ({} bytes)",
+                min, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    pub fn status_interval(&self) -> Duration {
+        Duration::from_millis(self.status_interval_ms)
+    }
+
+    /// How long ago files must have last been modified to be compressed,
+    /// limited to `MAX_MIN_AGE_DAYS` however the setting was made.
+    pub fn min_age(&self) -> Option<Duration> {
+        self.min_age_days
+            .map(|days| Duration::from_secs(days.min(MAX_MIN_AGE_DAYS) * 24 * 60 * 60))
+    }
+
+    /// The throughput limit in bytes per second, limited to
+    /// `MAX_THROUGHPUT_LIMIT` MiB however the setting was made.
+    pub fn throughput_limit_bytes(&self) -> Option<u64> {
+        self.throughput_limit
+            .map(|mib| mib.min(MAX_THROUGHPUT_LIMIT) * 1024 * 1024)
+    }
+
+    /// Add any exclude globs not already present, returning how many were.
+    pub fn merge_excludes<I: IntoIterator<Item = String>>(&mut self, globs: I) -> usize {
+        let before = self.excludes.len();
+        for glob in globs {
+            if !self.excludes.contains(&glob) {
+                self.excludes.push(glob);
+            }
+        }
+
+        self.excludes.len() - before
+    }
+
+    /// Build a matcher for both the exclude globs and excluded extensions.
+    pub fn globset(&self) -> error::Result<GlobSet> {
         let mut globs = GlobSetBuilder::new();
-        for glob in &self.excludes {
-            globs.add(Glob::new(glob).map_err(|e| e.to_string())?);
+        for (i, glob) in self.excludes.iter().enumerate() {
+            let glob = GlobBuilder::new(glob)
+                .case_insensitive(self.case_insensitive)
+                .build()
+                .map_err(|e| glob_error(i, e))?;
+            globs.add(glob);
         }
-        globs.build().map_err(|e| e.to_string())
+        add_extensions(&mut globs, &self.exclude_extensions)?;
+        if self.skip_incompressible {
+            add_extensions(&mut globs, INCOMPRESSIBLE_EXTENSIONS)?;
+        }
+        globs
+            .build()
+            .map_err(|e| CompactorError::Config(e.to_string()))
+    }
+
+    /// Build a matcher for the extensions in `include_only`, or `None` if
+    /// there's no restriction.
+    pub fn include_globset(&self) -> error::Result<Option<GlobSet>> {
+        if self.include_only.is_empty() {
+            return Ok(None);
+        }
+
+        let mut globs = GlobSetBuilder::new();
+        add_extensions(&mut globs, &self.include_only)?;
+        globs
+            .build()
+            .map(Some)
+            .map_err(|e| CompactorError::Config(e.to_string()))
+    }
+
+    /// The nearest `compact.exe` command to compressing the current folder
+    /// with these settings.
+    ///
+    /// compact.exe has no way to exclude files, and no per-file algorithm, so
+    /// automatic compression shows the algorithm it falls back on.
+    pub fn equivalent_command(&self) -> String {
+        let mut cmd = String::from("compact /c /s /i");
+        if !self.skip_hidden && !self.skip_system {
+            cmd.push_str(" /a");
+        }
+        cmd.push_str(&format!(" /exe:{}", self.compression));
+        cmd
     }
 }
 
@@ -136,3 +718,351 @@ fn test_config() {
     assert!(gs.is_match("C:\\Windows\\System32\\floop\\bla.txt"));
     assert!(gs.is_match("C:\\x.lz4"));
 }
+
+#[test]
+fn test_validate_excludes() {
+    assert!(validate_excludes("*.jpg\n*.{png,gif}").is_ok());
+
+    let err = validate_excludes("*.jpg\n*.{png,gif")
+        .unwrap_err()
+        .to_string();
+    assert!(err.starts_with("Line 2: "), "{}", err);
+    assert!(err.contains("*.{png,gif"), "{}", err);
+
+    let s = Config {
+        excludes: vec!["*.jpg".to_string(), "[".to_string()],
+        ..Config::default()
+    };
+    assert!(s.globset().unwrap_err().to_string().starts_with("Line 2: "));
+}
+
+#[test]
+fn test_import_excludes() {
+    let globs = parse_exclude_file("\u{feff}# Build output\r\n*.pdb\r\n\r\n  target/**  \n*.jpg\n")
+        .unwrap();
+    assert_eq!(globs, vec!["*.pdb", "target/**", "*.jpg"]);
+
+    let err = parse_exclude_file("# Images\n*.{png,gif\n").unwrap_err();
+    assert!(err.to_string().starts_with("Line 2: "), "{}", err);
+
+    let mut s = Config::default();
+    let before = s.excludes.len();
+    assert_eq!(s.merge_excludes(globs.clone()), 3);
+    assert_eq!(s.merge_excludes(globs), 0);
+    assert_eq!(s.excludes.len(), before + 3);
+    assert!(s.globset().is_ok());
+}
+
+#[test]
+fn test_case_insensitive() {
+    let mut s = Config {
+        excludes: vec!["*.JPG".to_string()],
+        ..Config::default()
+    };
+    assert!(s.globset().unwrap().is_match("C:\\Photos\\holiday.jpg"));
+
+    s.case_insensitive = false;
+    s.skip_incompressible = false;
+    assert!(!s.globset().unwrap().is_match("C:\\Photos\\holiday.jpg"));
+    assert!(s.globset().unwrap().is_match("C:\\Photos\\HOLIDAY.JPG"));
+}
+
+#[test]
+fn test_skip_incompressible() {
+    let mut s = Config {
+        excludes: vec![],
+        ..Config::default()
+    };
+    assert!(s.skip_incompressible);
+    assert!(s.globset().unwrap().is_match("C:\\Videos\\Holiday.MP4"));
+    assert!(s.globset().unwrap().is_match("C:\\Downloads\\stuff.zip"));
+    assert!(!s.globset().unwrap().is_match("C:\\Games\\game.exe"));
+
+    s.skip_incompressible = false;
+    assert!(!s.globset().unwrap().is_match("C:\\Videos\\Holiday.MP4"));
+}
+
+#[test]
+fn test_exclude_extensions() {
+    assert_eq!(
+        parse_extensions(" .JPG, jpg,,zip ,.tar.gz"),
+        vec!["jpg".to_string(), "tar.gz".to_string(), "zip".to_string()]
+    );
+
+    let s = Config {
+        excludes: vec!["*.zip".to_string()],
+        exclude_extensions: parse_extensions(".JPG, zip"),
+        ..Config::default()
+    };
+    let gs = s.globset().unwrap();
+
+    assert!(gs.is_match("C:\\Photos\\holiday.jpg"));
+    assert!(gs.is_match("C:\\Photos\\HOLIDAY.JPG"));
+    assert!(gs.is_match("C:\\Downloads\\stuff.zip"));
+    assert!(!gs.is_match("C:\\Photos\\jpg.txt"));
+}
+
+#[test]
+fn test_include_only() {
+    assert!(Config::default().include_globset().unwrap().is_none());
+
+    let s = Config {
+        include_only: parse_extensions("pak, .BIN"),
+        ..Config::default()
+    };
+    let gs = s.include_globset().unwrap().unwrap();
+
+    assert!(gs.is_match("C:\\Games\\data.pak"));
+    assert!(gs.is_match("C:\\Games\\DATA.BIN"));
+    assert!(!gs.is_match("C:\\Games\\game.exe"));
+
+    let s = Config {
+        include_only: vec!["[".to_string()],
+        ..Config::default()
+    };
+    assert!(s.include_globset().is_err());
+}
+
+#[test]
+fn test_threads() {
+    let mut s = Config::default();
+    assert!(s.worker_threads() >= 1);
+
+    s.threads = 3;
+    assert_eq!(s.worker_threads(), 3);
+}
+
+#[test]
+fn test_check_number() {
+    assert_eq!(check_number("Threads", 0.0, MAX_THREADS).unwrap(), 0);
+    assert_eq!(check_number("Threads", 8.0, MAX_THREADS).unwrap(), 8);
+    assert_eq!(check_number("Threads", 256.0, MAX_THREADS).unwrap(), 256);
+
+    let err = check_number("Threads", -1.0, MAX_THREADS).unwrap_err();
+    assert_eq!(
+        err.to_string(),
+        "Threads must be a whole number from 0 to 256, not -1"
+    );
+    assert_eq!(err.code(), "invalid_config");
+    assert!(check_number("Threads", 257.0, MAX_THREADS).is_err());
+    assert!(check_number("Threads", 1.5, MAX_THREADS).is_err());
+    assert!(check_number("Threads", f64::NAN, MAX_THREADS).is_err());
+    assert!(check_number("Minimum size", 1e30, MAX_FILE_SIZE).is_err());
+
+    let mut s = Config {
+        threads: 100_000,
+        ..Config::default()
+    };
+    assert_eq!(s.worker_threads(), MAX_THREADS as usize);
+    assert!(s.validate().is_ok());
+    s.min_size = Some(4096);
+    s.max_size = Some(1024);
+    assert!(s.validate().is_err());
+    s.max_size = None;
+    assert!(s.validate().is_ok());
+}
+
+#[test]
+fn test_status_interval() {
+    assert_eq!(
+        Config::default().status_interval(),
+        Duration::from_millis(100)
+    );
+
+    let s: Config = serde_json::from_str(r#"{"status_interval_ms": 500}"#).unwrap();
+    assert_eq!(s.status_interval(), Duration::from_millis(500));
+}
+
+#[test]
+fn it_limits_min_age() {
+    assert_eq!(Config::default().min_age(), None);
+
+    let s: Config = serde_json::from_str(r#"{"min_age_days": 2}"#).unwrap();
+    assert_eq!(s.min_age(), Some(Duration::from_secs(2 * 24 * 60 * 60)));
+
+    let s = Config {
+        min_age_days: Some(u64::MAX),
+        ..Config::default()
+    };
+    assert_eq!(
+        s.min_age(),
+        Some(Duration::from_secs(MAX_MIN_AGE_DAYS * 24 * 60 * 60))
+    );
+}
+
+#[test]
+fn it_limits_throughput_limit() {
+    assert_eq!(Config::default().throughput_limit_bytes(), None);
+
+    let mut s = Config {
+        throughput_limit: Some(50),
+        ..Config::default()
+    };
+    assert_eq!(s.throughput_limit_bytes(), Some(50 * 1024 * 1024));
+
+    s.throughput_limit = Some(u64::MAX);
+    assert_eq!(
+        s.throughput_limit_bytes(),
+        Some(MAX_THROUGHPUT_LIMIT * 1024 * 1024)
+    );
+}
+
+#[test]
+fn test_equivalent_command() {
+    let mut s = Config::default();
+    s.skip_hidden = false;
+    s.skip_system = false;
+    s.compression = Compression::Lzx;
+    assert_eq!(s.equivalent_command(), "compact /c /s /i /a /exe:LZX");
+
+    s.skip_hidden = true;
+    s.compression = Compression::Xpress4k;
+    assert_eq!(s.equivalent_command(), "compact /c /s /i /exe:XPRESS4K");
+}
+
+#[test]
+fn test_presets() {
+    let mut s = Config {
+        excludes: vec!["*.txt".to_string()],
+        min_size: Some(65536),
+        ..Config::default()
+    };
+
+    assert!(!Preset::Balanced.matches(&s));
+
+    Preset::Maximum.apply(&mut s);
+    assert_eq!(s.compression, Compression::Lzx);
+    assert_eq!(s.min_size, None);
+    assert_eq!(s.preset, Some(Preset::Maximum));
+    assert!(Preset::Maximum.matches(&s));
+    assert!(!Preset::Fast.matches(&s));
+
+    Preset::Fast.apply(&mut s);
+    assert_eq!(s.compression, Compression::Xpress4k);
+    assert_eq!("Fast".parse(), Ok(Preset::Fast));
+}
+
+#[test]
+fn test_priority() {
+    assert_eq!(Config::default().priority, Priority::Normal);
+
+    for p in &[Priority::Normal, Priority::BelowNormal, Priority::Idle] {
+        assert_eq!(p.to_string().parse(), Ok(*p));
+    }
+    assert_eq!("High".parse::<Priority>(), Err(()));
+}
+
+#[test]
+fn test_order() {
+    assert_eq!(Config::default().order, Order::Default);
+
+    for o in &[Order::Default, Order::LargestFirst, Order::SmallestFirst] {
+        assert_eq!(o.to_string().parse(), Ok(*o));
+    }
+    assert_eq!("Random".parse::<Order>(), Err(()));
+}
+
+#[test]
+fn test_theme() {
+    assert_eq!(Config::default().theme, Theme::System);
+
+    for t in &[
+        Theme::System,
+        Theme::Light,
+        Theme::Dark,
+        Theme::HighContrast,
+    ] {
+        assert_eq!(t.to_string().parse(), Ok(*t));
+    }
+    assert_eq!("Solarized".parse::<Theme>(), Err(()));
+}
+
+#[test]
+fn test_log_level() {
+    assert_eq!(Config::default().log_level, LogLevel::Info);
+
+    for l in &[
+        LogLevel::Off,
+        LogLevel::Error,
+        LogLevel::Warn,
+        LogLevel::Info,
+        LogLevel::Debug,
+    ] {
+        assert_eq!(l.to_string().parse(), Ok(*l));
+    }
+    assert_eq!("Trace".parse::<LogLevel>(), Err(()));
+    assert_eq!(
+        log::LevelFilter::from(LogLevel::Warn),
+        log::LevelFilter::Warn
+    );
+}
+
+#[test]
+fn test_profiles() {
+    let mut profiles = ProfileFile::default();
+    let lzx = Config {
+        compression: Compression::Lzx,
+        ..Config::default()
+    };
+
+    profiles.insert("C:\\Archives", lzx);
+
+    assert!(profiles.get(Path::new("C:\\Games")).is_none());
+    assert_eq!(
+        profiles
+            .get(Path::new("C:\\Archives"))
+            .map(|c| c.compression),
+        Some(Compression::Lzx)
+    );
+    assert_eq!(
+        profiles
+            .get(Path::new("C:\\Archives\\Old"))
+            .map(|c| c.compression),
+        Some(Compression::Lzx)
+    );
+
+    assert!(profiles.remove("C:\\Archives").is_some());
+    assert!(profiles.get(Path::new("C:\\Archives")).is_none());
+}
+
+#[test]
+fn test_named_presets() {
+    let mut presets = PresetFile::default();
+    let lzx = Config {
+        compression: Compression::Lzx,
+        ..Config::default()
+    };
+
+    presets.insert("Games", Config::default());
+    presets.insert("Archives", lzx);
+
+    assert_eq!(presets.names(), vec!["Archives", "Games"]);
+    assert_eq!(
+        presets.get("Archives").map(|c| c.compression),
+        Some(Compression::Lzx)
+    );
+    assert!(presets.get("archives").is_none());
+}
+
+#[test]
+fn it_saves_and_loads_profiles() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("profiles.json");
+    let archives = dir.path().join("Archives");
+
+    let mut profiles = ProfileFile::new(&path);
+    profiles.insert(
+        &archives,
+        Config {
+            compression: Compression::Lzx,
+            ..Config::default()
+        },
+    );
+    profiles.save().unwrap();
+
+    let loaded = ProfileFile::new(&path);
+    assert_eq!(
+        loaded.get(&archives.join("Old")).map(|c| c.compression),
+        Some(Compression::Lzx)
+    );
+}
@@ -0,0 +1,86 @@
+// Errors worth telling apart.
+//
+// People get the `Display` message, as they always have.  Programs driving the
+// JSON protocol also get a short, stable `code` for each kind of error, so they
+// needn't pick apart messages that may be reworded.
+
+use std::io;
+use std::path::{Path, PathBuf};
+
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum CompactorError {
+    /// An exclude glob that doesn't parse, with lines numbered from 1.
+    #[error("Line {line}: {message}")]
+    Glob { line: usize, message: String },
+    /// A file extension that can't be made into a glob.
+    #[error("{extension}: {message}")]
+    Extension { extension: String, message: String },
+    /// A `%NAME%` in a path naming an environment variable which isn't set.
+    #[error("{}: environment variable %{name}% isn't set", path.display())]
+    UndefinedVariable { name: String, path: PathBuf },
+    /// Any other unusable setting.
+    #[error("{0}")]
+    Config(String),
+    /// Windows Overlay Filter refused to compress or decompress something.
+    #[error("{}: {source}", path.display())]
+    Wof { path: PathBuf, source: io::Error },
+    /// An IO error concerning a particular file or folder.
+    #[error("{}: {source}", path.display())]
+    Path { path: PathBuf, source: io::Error },
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, CompactorError>;
+
+impl CompactorError {
+    pub fn path<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        CompactorError::Path {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    pub fn wof<P: AsRef<Path>>(path: P, source: io::Error) -> Self {
+        CompactorError::Wof {
+            path: path.as_ref().to_path_buf(),
+            source,
+        }
+    }
+
+    /// A machine-readable name for the kind of error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            CompactorError::Glob { .. } => "invalid_glob",
+            CompactorError::Extension { .. } => "invalid_extension",
+            CompactorError::UndefinedVariable { .. } => "undefined_variable",
+            CompactorError::Config(_) => "invalid_config",
+            CompactorError::Wof { .. } => "wof",
+            CompactorError::Path { .. } => "path",
+            CompactorError::Io(_) => "io",
+        }
+    }
+}
+
+#[test]
+fn it_describes_errors() {
+    let e = CompactorError::Glob {
+        line: 2,
+        message: "unclosed alternate group".to_string(),
+    };
+    assert_eq!(e.to_string(), "Line 2: unclosed alternate group");
+    assert_eq!(e.code(), "invalid_glob");
+
+    let e = CompactorError::path(
+        "C:\\Games",
+        io::Error::new(io::ErrorKind::NotFound, "not found"),
+    );
+    assert_eq!(e.to_string(), "C:\\Games: not found");
+    assert_eq!(e.code(), "path");
+
+    let e = CompactorError::from(io::Error::new(io::ErrorKind::Other, "oops"));
+    assert_eq!(e.to_string(), "oops");
+    assert_eq!(e.code(), "io");
+}
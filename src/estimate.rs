@@ -0,0 +1,288 @@
+// Dry-run compression estimates.
+//
+// Nothing is compressed on disk.  Instead a sample of files are read into
+// memory and passed through the same compressibility check used prior to
+// compaction, using blocks the size of the target algorithm's chunks, and the
+// resulting ratios are projected across the rest of the folder by file type.
+//
+// Only one file's sample is held in memory at a time, and it never exceeds
+// `SAMPLE_BYTES_PER_FILE` or what's left of the overall budget, however large
+// the file is.
+
+use std::collections::HashMap;
+use std::io::{Cursor, Read};
+use std::iter::StepBy;
+use std::path::{Path, PathBuf};
+use std::slice;
+
+use compresstimator::Compresstimator;
+
+use crate::background::{Background, ControlToken};
+use crate::compression::COMPRESSIBLE_RATIO;
+use crate::folder::{extension_of, FileInfo};
+use crate::wof::Compression;
+
+/// Maximum bytes read from any single sampled file.
+const SAMPLE_BYTES_PER_FILE: u64 = 1024 * 1024;
+
+/// Maximum number of files to sample, spread evenly across the folder.
+const MAX_SAMPLE_FILES: usize = 1024;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Sample {
+    pub bytes: u64,
+    pub estimated: f64,
+}
+
+impl Sample {
+    pub fn ratio(&self) -> f64 {
+        if self.bytes == 0 {
+            1.0
+        } else {
+            self.estimated / self.bytes as f64
+        }
+    }
+}
+
+pub type Samples = HashMap<String, Sample>;
+
+/// The file being sampled, how many have been sampled, and the total.
+pub type SampleStatus = (PathBuf, u64, u64);
+
+/// Files to sample from a folder, spread evenly across it, stopping once a
+/// budget of bytes has been read from them.
+///
+/// Shared with benchmarks, so both are held to the same limits.
+#[derive(Debug)]
+pub struct SampleFiles<'a> {
+    files: StepBy<slice::Iter<'a, FileInfo>>,
+    bytes_per_file: u64,
+    max_bytes: u64,
+    read: u64,
+    started: u64,
+    total: u64,
+}
+
+/// A file to sample, and the most to read from it.
+#[derive(Debug)]
+pub struct SampleFile<'a> {
+    pub info: &'a FileInfo,
+    pub limit: u64,
+    pub status: SampleStatus,
+}
+
+impl<'a> SampleFiles<'a> {
+    /// Sample at most `max_files` of `files`, reading at most `bytes_per_file`
+    /// from each and `max_bytes` in all.
+    pub fn new(
+        files: &'a [FileInfo],
+        max_files: usize,
+        bytes_per_file: u64,
+        max_bytes: u64,
+    ) -> Self {
+        let stride = (files.len() / max_files).max(1);
+        Self {
+            files: files.iter().step_by(stride),
+            bytes_per_file,
+            max_bytes,
+            read: 0,
+            started: 0,
+            total: ((files.len() + stride - 1) / stride) as u64,
+        }
+    }
+
+    /// Count bytes read from the last file against the budget.
+    pub fn record(&mut self, bytes: u64) {
+        self.read += bytes;
+    }
+}
+
+impl<'a> Iterator for SampleFiles<'a> {
+    type Item = SampleFile<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.read >= self.max_bytes {
+            return None;
+        }
+
+        let info = self.files.next()?;
+        let status = (info.path.clone(), self.started, self.total);
+        self.started += 1;
+
+        Some(SampleFile {
+            info,
+            limit: self.bytes_per_file.min(self.max_bytes - self.read),
+            status,
+        })
+    }
+}
+
+#[derive(Debug)]
+pub struct Estimator {
+    root: PathBuf,
+    files: Vec<FileInfo>,
+    compression: Compression,
+    max_bytes: u64,
+}
+
+impl Estimator {
+    pub fn new<P: AsRef<Path>>(
+        root: P,
+        files: Vec<FileInfo>,
+        compression: Compression,
+        max_bytes: u64,
+    ) -> Self {
+        Self {
+            root: root.as_ref().to_path_buf(),
+            files,
+            compression,
+            max_bytes,
+        }
+    }
+}
+
+impl Background for Estimator {
+    type Output = Result<Samples, Samples>;
+    type Status = SampleStatus;
+
+    fn run(self, control: &ControlToken<Self::Status>) -> Self::Output {
+        let est = Compresstimator::with_block_size(self.compression.chunk_size());
+        let mut files = SampleFiles::new(
+            &self.files,
+            MAX_SAMPLE_FILES,
+            SAMPLE_BYTES_PER_FILE,
+            self.max_bytes,
+        );
+        let mut samples = Samples::new();
+        let mut buf = Vec::with_capacity(SAMPLE_BYTES_PER_FILE as usize);
+
+        while let Some(SampleFile {
+            info: fi,
+            limit,
+            status,
+        }) = files.next()
+        {
+            if control.is_cancelled_with_pause() {
+                break;
+            }
+
+            control.set_status(status);
+
+            buf.clear();
+            let len = match std::fs::File::open(self.root.join(&fi.path))
+                .and_then(|f| f.take(limit).read_to_end(&mut buf))
+            {
+                Ok(len) if len > 0 => len as u64,
+                _ => continue,
+            };
+
+            files.record(len);
+
+            if let Ok(ratio) = est.compresstimate(Cursor::new(&buf[..]), len) {
+                let sample = samples.entry(extension_of(&fi.path)).or_default();
+                sample.bytes += len;
+                sample.estimated += ratio * len as f64;
+            }
+        }
+
+        if control.is_cancelled() {
+            Err(samples)
+        } else {
+            Ok(samples)
+        }
+    }
+}
+
+/// Project the space saved by compressing a set of files, returning the bytes
+/// saved and the overall sampled ratio.
+///
+/// Extensions that weren't sampled fall back to the overall ratio, and files
+/// expected to be skipped as incompressible are assumed to save nothing.
+pub fn project<'a, I: IntoIterator<Item = &'a FileInfo>>(
+    samples: &Samples,
+    files: I,
+) -> (u64, f32) {
+    let total = samples.values().fold(Sample::default(), |a, s| Sample {
+        bytes: a.bytes + s.bytes,
+        estimated: a.estimated + s.estimated,
+    });
+
+    if total.bytes == 0 {
+        return (0, 1.0);
+    }
+
+    let overall = total.ratio();
+    let saved = files
+        .into_iter()
+        .map(|fi| {
+            let ratio = samples
+                .get(&extension_of(&fi.path))
+                .map(Sample::ratio)
+                .unwrap_or(overall);
+
+            if ratio < COMPRESSIBLE_RATIO {
+                (fi.physical_size as f64 * (1.0 - ratio)) as u64
+            } else {
+                0
+            }
+        })
+        .sum();
+
+    (saved, overall as f32)
+}
+
+#[test]
+fn it_samples_evenly_within_budget() {
+    use crate::testutil::file;
+
+    let files: Vec<FileInfo> = (0..10).map(|i| file(&format!("{}.bin", i), 100)).collect();
+
+    let picked: Vec<_> = SampleFiles::new(&files, 5, 100, 1000)
+        .map(|s| (s.info.path.clone(), s.status.1, s.status.2))
+        .collect();
+    assert_eq!(picked.len(), 5);
+    assert_eq!(picked[0], (PathBuf::from("0.bin"), 0, 5));
+    assert_eq!(picked[1], (PathBuf::from("2.bin"), 1, 5));
+
+    let mut sampler = SampleFiles::new(&files, 100, 64, 100);
+    let first = sampler.next().unwrap();
+    assert_eq!(first.limit, 64);
+    sampler.record(64);
+    assert_eq!(sampler.next().unwrap().limit, 36);
+    sampler.record(36);
+    assert!(sampler.next().is_none());
+}
+
+#[test]
+fn it_projects() {
+    use crate::testutil::file;
+
+    let mut samples = Samples::new();
+    assert_eq!(project(&samples, &[file("a.exe", 1000)]), (0, 1.0));
+
+    samples.insert(
+        "exe".to_string(),
+        Sample {
+            bytes: 100,
+            estimated: 50.0,
+        },
+    );
+    samples.insert(
+        "pak".to_string(),
+        Sample {
+            bytes: 300,
+            estimated: 300.0,
+        },
+    );
+
+    let files = vec![
+        file("a.exe", 1000),
+        file("b.pak", 1000),
+        file("c.dll", 1000),
+    ];
+    let (saved, ratio) = project(&samples, &files);
+
+    // exe: 50%, pak: incompressible, dll: overall 87.5%
+    assert_eq!(saved, 500 + 125);
+    assert!((ratio - 0.875).abs() < 0.001);
+}
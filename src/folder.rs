@@ -1,25 +1,36 @@
-use std::collections::VecDeque;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
 use std::os::windows::fs::MetadataExt;
 use std::path::{Path, PathBuf};
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime};
 
-use filesize::PathExt;
 use globset::GlobSet;
 use serde_derive::Serialize;
 use walkdir::WalkDir;
 use winapi::um::winnt::{
-    FILE_ATTRIBUTE_COMPRESSED, FILE_ATTRIBUTE_READONLY, FILE_ATTRIBUTE_SYSTEM,
+    FILE_ATTRIBUTE_COMPRESSED, FILE_ATTRIBUTE_HIDDEN, FILE_ATTRIBUTE_READONLY,
+    FILE_ATTRIBUTE_REPARSE_POINT, FILE_ATTRIBUTE_SPARSE_FILE, FILE_ATTRIBUTE_SYSTEM,
     FILE_ATTRIBUTE_TEMPORARY,
 };
 
 use crate::background::{Background, ControlToken};
+use crate::compact;
 use crate::persistence::pathdb;
+use crate::wof::{self, Compression};
 
 #[derive(Debug, Clone, Serialize)]
 pub struct FileInfo {
     pub path: PathBuf,
     pub logical_size: u64,
     pub physical_size: u64,
+    /// The WOF algorithm the file is compressed with, as of the scan or the
+    /// last time it was compressed or decompressed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<Compression>,
+    /// When the file was last modified, as of the scan, if that could be told.
+    #[serde(skip)]
+    pub modified: Option<SystemTime>,
 }
 
 #[derive(Debug, Clone, Serialize, Default)]
@@ -32,11 +43,24 @@ pub struct GroupInfo {
 #[derive(Debug, Clone, Serialize)]
 pub struct FolderInfo {
     pub path: PathBuf,
+    /// Whether `path` is a single file rather than a folder.
+    pub single_file: bool,
     pub logical_size: u64,
     pub physical_size: u64,
     pub compressible: GroupInfo,
     pub compressed: GroupInfo,
     pub skipped: GroupInfo,
+    pub size_skipped: GroupInfo,
+    /// Files and folders skipped for being hidden or system files.
+    pub attribute_skipped: usize,
+    /// Sparse files skipped, since filling in their holes could make them
+    /// bigger.
+    pub sparse_skipped: usize,
+    /// Junctions and symbolic links which weren't followed.
+    pub reparse_skipped: usize,
+    pub extensions: BTreeMap<String, GroupSummary>,
+    /// File count and physical size by `algorithm_of`.
+    pub by_algorithm: HashMap<String, (usize, u64)>,
 }
 
 #[derive(Debug, Clone, Serialize, Default)]
@@ -46,6 +70,19 @@ pub struct FolderSummary {
     pub compressible: GroupSummary,
     pub compressed: GroupSummary,
     pub skipped: GroupSummary,
+    pub size_skipped: GroupSummary,
+    pub attribute_skipped: usize,
+    pub sparse_skipped: usize,
+    pub reparse_skipped: usize,
+    /// Physical size divided by logical size, so lower is better.
+    pub ratio: f32,
+    /// The fraction of bytes worth compressing which already are, with the
+    /// target algorithm.  1.0 means there's nothing left to do.
+    pub optimal: f32,
+    pub extension_ratios: BTreeMap<String, f32>,
+    /// File count and physical size for each WOF algorithm, plus "Other" for
+    /// files smaller on disk some other way and "Uncompressed".
+    pub by_algorithm: HashMap<String, (usize, u64)>,
 }
 
 #[derive(Debug, Clone, Serialize, Default)]
@@ -55,25 +92,81 @@ pub struct GroupSummary {
     pub physical_size: u64,
 }
 
+/// A detailed, machine-readable description of an analysed folder.
+#[derive(Debug, Clone, Serialize)]
+pub struct FolderReport {
+    pub path: PathBuf,
+    pub summary: FolderSummary,
+    pub extensions: BTreeMap<String, GroupSummary>,
+    pub largest_skipped: Vec<FileInfo>,
+}
+
+/// What a set of excludes would skip in a folder.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ExcludePreview {
+    pub matched: usize,
+    pub unmatched: usize,
+    /// The first few matched files, relative to the folder.
+    pub sample: Vec<PathBuf>,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum FileKind {
     Compressed,
     Compressible,
     Skipped,
+    SizeSkipped,
+}
+
+/// The name of a file's algorithm in `FolderSummary::by_algorithm`.
+fn algorithm_of(fi: &FileInfo) -> String {
+    match fi.compression {
+        Some(compression) => compression.to_string(),
+        // NTFS compression, or a sparse file.
+        None if fi.physical_size < fi.logical_size => "Other".to_string(),
+        None => "Uncompressed".to_string(),
+    }
+}
+
+/// Lowercased file extension, or an empty string if there isn't one.
+pub fn extension_of(path: &Path) -> String {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
 }
 
 impl FolderInfo {
     pub fn new<P: AsRef<Path>>(path: P) -> Self {
         Self {
             path: path.as_ref().to_owned(),
+            single_file: false,
             logical_size: 0,
             physical_size: 0,
             compressible: GroupInfo::default(),
             compressed: GroupInfo::default(),
             skipped: GroupInfo::default(),
+            size_skipped: GroupInfo::default(),
+            attribute_skipped: 0,
+            sparse_skipped: 0,
+            reparse_skipped: 0,
+            extensions: BTreeMap::new(),
+            by_algorithm: HashMap::new(),
+        }
+    }
+
+    /// The folder file paths are relative to: either the folder itself, or the
+    /// one containing a single file.
+    pub fn base(&self) -> &Path {
+        match self.path.parent() {
+            Some(parent) if self.single_file => parent,
+            _ => &self.path,
         }
     }
 
+    pub fn full_path(&self, fi: &FileInfo) -> PathBuf {
+        self.base().join(&fi.path)
+    }
+
     pub fn summary(&self) -> FolderSummary {
         FolderSummary {
             logical_size: self.logical_size,
@@ -81,14 +174,165 @@ impl FolderInfo {
             compressible: self.compressible.summary(),
             compressed: self.compressed.summary(),
             skipped: self.skipped.summary(),
+            size_skipped: self.size_skipped.summary(),
+            attribute_skipped: self.attribute_skipped,
+            sparse_skipped: self.sparse_skipped,
+            reparse_skipped: self.reparse_skipped,
+            ratio: ratio(self.physical_size, self.logical_size),
+            optimal: optimal(self.compressed.logical_size, self.compressible.logical_size),
+            extension_ratios: self
+                .extensions
+                .iter()
+                .map(|(ext, group)| (ext.clone(), group.ratio()))
+                .collect(),
+            by_algorithm: self.by_algorithm.clone(),
         }
     }
 
+    pub fn report(&self, largest: usize) -> FolderReport {
+        let mut largest_skipped: Vec<FileInfo> = self.skipped.files.iter().cloned().collect();
+        largest_skipped.sort_by(|a, b| b.logical_size.cmp(&a.logical_size));
+        largest_skipped.truncate(largest);
+
+        FolderReport {
+            path: self.path.clone(),
+            summary: self.summary(),
+            extensions: self.extensions.clone(),
+            largest_skipped,
+        }
+    }
+
+    /// Write a CSV file listing every file, its sizes, the algorithm it's
+    /// compressed with if any, and whether it's worth compressing.
+    pub fn write_csv<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        self.write_csv_to(BufWriter::new(File::create(path)?))
+    }
+
+    fn write_csv_to<W: Write>(&self, out: W) -> io::Result<()> {
+        let mut csv = csv::Writer::from_writer(out);
+        csv.write_record(&[
+            "path",
+            "logical_size",
+            "physical_size",
+            "algorithm",
+            "compressible",
+        ])?;
+
+        let groups = [
+            (&self.compressible, true),
+            (&self.compressed, false),
+            (&self.skipped, false),
+            (&self.size_skipped, false),
+        ];
+        for (group, compressible) in groups.iter() {
+            for fi in group.files.iter() {
+                let path = self.full_path(fi);
+                // Only compressed files are worth asking about, and anything
+                // else using less space is compressed some other way.
+                let algorithm = if fi.physical_size < fi.logical_size {
+                    wof::query_compression(compact::long_path(&path))
+                        .ok()
+                        .flatten()
+                        .map(|c| c.to_string())
+                        .unwrap_or_default()
+                } else {
+                    String::new()
+                };
+
+                csv.write_record(&[
+                    path.to_string_lossy().into_owned(),
+                    fi.logical_size.to_string(),
+                    fi.physical_size.to_string(),
+                    algorithm,
+                    compressible.to_string(),
+                ])?;
+            }
+        }
+
+        csv.flush()
+    }
+
+    /// Write a JSON tree of every folder and file with its sizes and main
+    /// algorithm, for rendering as a treemap in another tool.
+    pub fn write_treemap<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer(&mut file, &self.treemap())?;
+        file.flush()
+    }
+
+    pub fn treemap(&self) -> TreeNode {
+        self.treemap_with(|fi| {
+            // As with the CSV, only files using less space can be compressed.
+            if fi.physical_size < fi.logical_size {
+                wof::query_compression(compact::long_path(self.full_path(fi)))
+                    .ok()
+                    .flatten()
+            } else {
+                None
+            }
+        })
+    }
+
+    fn treemap_with<F: FnMut(&FileInfo) -> Option<Compression>>(&self, mut detect: F) -> TreeNode {
+        let mut root = TreeBuilder::default();
+        let groups = [
+            &self.compressible,
+            &self.compressed,
+            &self.skipped,
+            &self.size_skipped,
+        ];
+        for group in groups.iter() {
+            for fi in group.files.iter() {
+                let components: Vec<String> = fi
+                    .path
+                    .components()
+                    .map(|c| c.as_os_str().to_string_lossy().into_owned())
+                    .collect();
+                root.add(&components, fi, detect(fi));
+            }
+        }
+
+        root.build(self.path.display().to_string())
+    }
+
+    /// Logical and physical sizes of each immediate subfolder, largest first.
+    ///
+    /// Files directly within the folder aren't included.
+    pub fn subfolders(&self) -> Vec<(PathBuf, u64, u64)> {
+        let mut sizes: BTreeMap<PathBuf, (u64, u64)> = BTreeMap::new();
+
+        for fi in self.files() {
+            let mut components = fi.path.components();
+            if let (Some(first), Some(_)) = (components.next(), components.next()) {
+                let entry = sizes.entry(PathBuf::from(first.as_os_str())).or_default();
+                entry.0 += fi.logical_size;
+                entry.1 += fi.physical_size;
+            }
+        }
+
+        let mut subfolders: Vec<_> = sizes
+            .into_iter()
+            .map(|(path, (logical, physical))| (path, logical, physical))
+            .collect();
+        subfolders.sort_by(|a, b| b.1.cmp(&a.1));
+        subfolders
+    }
+
+    pub fn files(&self) -> impl Iterator<Item = &FileInfo> {
+        self.compressible
+            .files
+            .iter()
+            .chain(self.compressed.files.iter())
+            .chain(self.skipped.files.iter())
+            .chain(self.size_skipped.files.iter())
+    }
+
     pub fn len(&mut self, kind: FileKind) -> usize {
         match kind {
             FileKind::Compressible => self.compressible.files.len(),
             FileKind::Compressed => self.compressed.files.len(),
             FileKind::Skipped => self.skipped.files.len(),
+            FileKind::SizeSkipped => self.size_skipped.files.len(),
         }
     }
 
@@ -97,12 +341,34 @@ impl FolderInfo {
             FileKind::Compressible => self.compressible.pop(),
             FileKind::Compressed => self.compressed.pop(),
             FileKind::Skipped => self.skipped.pop(),
+            FileKind::SizeSkipped => self.size_skipped.pop(),
         };
 
         if let Some(fi) = ret {
             self.logical_size -= fi.logical_size;
             self.physical_size -= fi.physical_size;
 
+            let ext = extension_of(&fi.path);
+            if let Some(group) = self.extensions.get_mut(&ext) {
+                group.count -= 1;
+                group.logical_size -= fi.logical_size;
+                group.physical_size -= fi.physical_size;
+
+                if group.count == 0 {
+                    self.extensions.remove(&ext);
+                }
+            }
+
+            let algorithm = algorithm_of(&fi);
+            if let Some((count, physical_size)) = self.by_algorithm.get_mut(&algorithm) {
+                *count -= 1;
+                *physical_size -= fi.physical_size;
+
+                if *count == 0 {
+                    self.by_algorithm.remove(&algorithm);
+                }
+            }
+
             Some(fi)
         } else {
             None
@@ -113,14 +379,121 @@ impl FolderInfo {
         self.logical_size += fi.logical_size;
         self.physical_size += fi.physical_size;
 
+        let group = self.extensions.entry(extension_of(&fi.path)).or_default();
+        group.count += 1;
+        group.logical_size += fi.logical_size;
+        group.physical_size += fi.physical_size;
+
+        let (count, physical_size) = self.by_algorithm.entry(algorithm_of(&fi)).or_default();
+        *count += 1;
+        *physical_size += fi.physical_size;
+
         match kind {
             FileKind::Compressible => self.compressible.push(fi),
             FileKind::Compressed => self.compressed.push(fi),
             FileKind::Skipped => self.skipped.push(fi),
+            FileKind::SizeSkipped => self.size_skipped.push(fi),
         };
     }
 }
 
+/// A folder or file in a treemap export.
+#[derive(Debug, Serialize)]
+pub struct TreeNode {
+    pub name: String,
+    pub logical_size: u64,
+    pub physical_size: u64,
+    /// The algorithm most of the data here is compressed with, by logical size,
+    /// or `None` if most of it isn't compressed.
+    pub compression: Option<Compression>,
+    /// Largest first, and empty for files.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<TreeNode>,
+}
+
+#[derive(Debug, Default)]
+struct TreeBuilder {
+    logical_size: u64,
+    physical_size: u64,
+    /// Logical bytes compressed with each algorithm, or `None` for not at all.
+    algorithms: Vec<(Option<Compression>, u64)>,
+    children: BTreeMap<String, TreeBuilder>,
+}
+
+impl TreeBuilder {
+    fn add(&mut self, components: &[String], fi: &FileInfo, compression: Option<Compression>) {
+        self.logical_size += fi.logical_size;
+        self.physical_size += fi.physical_size;
+
+        match self.algorithms.iter_mut().find(|(c, _)| *c == compression) {
+            Some((_, bytes)) => *bytes += fi.logical_size,
+            None => self.algorithms.push((compression, fi.logical_size)),
+        }
+
+        if let Some((first, rest)) = components.split_first() {
+            self.children
+                .entry(first.clone())
+                .or_default()
+                .add(rest, fi, compression);
+        }
+    }
+
+    fn build(self, name: String) -> TreeNode {
+        let compression = self
+            .algorithms
+            .iter()
+            .max_by_key(|(_, bytes)| *bytes)
+            .and_then(|(c, _)| *c);
+        let mut children: Vec<TreeNode> = self
+            .children
+            .into_iter()
+            .map(|(name, child)| child.build(name))
+            .collect();
+        children.sort_by(|a, b| b.logical_size.cmp(&a.logical_size));
+
+        TreeNode {
+            name,
+            logical_size: self.logical_size,
+            physical_size: self.physical_size,
+            compression,
+            children,
+        }
+    }
+}
+
+impl FolderReport {
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut file = BufWriter::new(File::create(path)?);
+        serde_json::to_writer_pretty(&mut file, self)?;
+        file.flush()
+    }
+}
+
+/// The fraction of compressed and compressible bytes which are compressed, or
+/// 1.0 if there aren't any.
+fn optimal(compressed: u64, compressible: u64) -> f32 {
+    let total = compressed + compressible;
+    if total == 0 {
+        1.0
+    } else {
+        compressed as f32 / total as f32
+    }
+}
+
+fn ratio(physical: u64, logical: u64) -> f32 {
+    if logical == 0 {
+        1.0
+    } else {
+        physical as f32 / logical as f32
+    }
+}
+
+impl GroupSummary {
+    pub fn ratio(&self) -> f32 {
+        ratio(self.physical_size, self.logical_size)
+    }
+}
+
 impl GroupInfo {
     pub fn summary(&self) -> GroupSummary {
         GroupSummary {
@@ -154,15 +527,132 @@ impl GroupInfo {
 pub struct FolderScan {
     path: PathBuf,
     excludes: GlobSet,
+    include_only: Option<GlobSet>,
+    compression: Compression,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    max_depth: Option<usize>,
+    follow_links: bool,
+    any_algorithm: bool,
+    include_readonly: bool,
+    compress_sparse: bool,
+    sorted: bool,
+    skip_attributes: u32,
 }
 
 impl FolderScan {
-    pub fn new<P: AsRef<Path>>(path: P, excludes: GlobSet) -> Self {
+    pub fn new<P: AsRef<Path>>(path: P, excludes: GlobSet, compression: Compression) -> Self {
         Self {
             path: path.as_ref().to_path_buf(),
             excludes,
+            include_only: None,
+            compression,
+            min_size: None,
+            max_size: None,
+            max_depth: None,
+            follow_links: false,
+            any_algorithm: false,
+            include_readonly: false,
+            compress_sparse: false,
+            sorted: false,
+            skip_attributes: 0,
         }
     }
+
+    /// Only count files matching these globs as compressible.  Excludes
+    /// still apply to any that match.
+    pub fn include_only(self, include_only: Option<GlobSet>) -> Self {
+        Self {
+            include_only,
+            ..self
+        }
+    }
+
+    /// Count read-only files as compressible, for a compactor which clears the
+    /// attribute while it works.
+    pub fn include_readonly(self, include_readonly: bool) -> Self {
+        Self {
+            include_readonly,
+            ..self
+        }
+    }
+
+    /// Count sparse files as compressible, rather than skipping them.
+    pub fn compress_sparse(self, compress_sparse: bool) -> Self {
+        Self {
+            compress_sparse,
+            ..self
+        }
+    }
+
+    /// Count files compressed with any algorithm as compressed, rather than
+    /// only those using the target one.
+    pub fn any_algorithm(self, any_algorithm: bool) -> Self {
+        Self {
+            any_algorithm,
+            ..self
+        }
+    }
+
+    /// Only walk this many folders deep, with files directly in the folder
+    /// being at depth 1.
+    pub fn max_depth(self, max_depth: Option<usize>) -> Self {
+        Self { max_depth, ..self }
+    }
+
+    /// Walk each folder's entries in order of name, rather than whatever order
+    /// the filesystem gives them in, so scans always find files in the same
+    /// order.  This costs a little on very large folders.
+    pub fn sorted(self, sorted: bool) -> Self {
+        Self { sorted, ..self }
+    }
+
+    /// Walk into junctions and symbolic links, instead of skipping them.
+    ///
+    /// These can lead anywhere, including back up to the folder itself.
+    pub fn follow_links(self, follow_links: bool) -> Self {
+        Self {
+            follow_links,
+            ..self
+        }
+    }
+
+    /// Leave hidden files and folders alone, without walking into them.
+    pub fn skip_hidden(self, skip: bool) -> Self {
+        self.skip_attribute(FILE_ATTRIBUTE_HIDDEN, skip)
+    }
+
+    /// Leave system files and folders alone, without walking into them.
+    pub fn skip_system(self, skip: bool) -> Self {
+        self.skip_attribute(FILE_ATTRIBUTE_SYSTEM, skip)
+    }
+
+    fn skip_attribute(self, attribute: u32, skip: bool) -> Self {
+        let skip_attributes = if skip {
+            self.skip_attributes | attribute
+        } else {
+            self.skip_attributes & !attribute
+        };
+
+        Self {
+            skip_attributes,
+            ..self
+        }
+    }
+
+    /// Only consider files within the given size range for compression.
+    pub fn size_range(self, min_size: Option<u64>, max_size: Option<u64>) -> Self {
+        Self {
+            min_size,
+            max_size,
+            ..self
+        }
+    }
+
+    fn in_size_range(&self, size: u64) -> bool {
+        self.min_size.map_or(true, |min| size >= min)
+            && self.max_size.map_or(true, |max| size <= max)
+    }
 }
 
 impl Background for FolderScan {
@@ -170,65 +660,145 @@ impl Background for FolderScan {
     type Status = (PathBuf, FolderSummary);
 
     fn run(self, control: &ControlToken<Self::Status>) -> Self::Output {
-        let FolderScan { path, excludes } = self;
-        let mut ds = FolderInfo::new(&path);
+        let path = &self.path;
+        let excludes = &self.excludes;
+        let include_only = self.include_only.as_ref();
+        let compression = self.compression;
+        let any_algorithm = self.any_algorithm;
+        let mut ds = FolderInfo::new(path);
+        ds.single_file = path.is_file();
+        let base = ds.base().to_path_buf();
         let incompressible = pathdb();
         let mut incompressible = incompressible.write().unwrap();
         let _ = incompressible.load();
 
         let mut last_status = Instant::now();
 
-        // 1. Handle excludes separately for directories to allow pruning, while
-        //    still recording accurate sizes for files.
+        // 1. Handle excludes and skipped attributes separately for directories
+        //    to allow pruning, while still recording accurate sizes for files.
+        //    Whatever was chosen is never skipped itself, since it was asked for.
+        //    Junctions and symlinks are skipped the same way unless followed.
         // 2. Ignore errors - consider recording them somewhere in future.
         // 3. Only process files.
         // 4. Grab metadata - should be infallible on Windows, it comes with the
         //    DirEntry.
         // 5. GetCompressedFileSizeW() or skip.
-        let walker = WalkDir::new(&path)
+        //
+        // Sparse files are smaller on disk without being compressed, and WOF
+        // would fill in their holes, so they're skipped unless asked for.  If
+        // they are, they're compressible like any other file, until WOF has
+        // them.
+        //
+        // Pauses and cancellation are checked every few entries of any kind, so
+        // walks through long runs of directories or excluded files still stop.
+        let skip_attributes = self.skip_attributes;
+        let follow_links = self.follow_links;
+        let mut unwanted =
+            FILE_ATTRIBUTE_SYSTEM | FILE_ATTRIBUTE_TEMPORARY | FILE_ATTRIBUTE_COMPRESSED;
+        if !self.include_readonly {
+            unwanted |= FILE_ATTRIBUTE_READONLY;
+        }
+        let mut skipped_dirs = 0_usize;
+        let mut skipped_links = 0_usize;
+        let mut entries = 0_usize;
+        let mut walker = WalkDir::new(path)
+            .max_depth(self.max_depth.unwrap_or(usize::max_value()))
+            .follow_links(follow_links);
+        if self.sorted {
+            walker = walker.sort_by(|a, b| a.file_name().cmp(b.file_name()));
+        }
+        let walker = walker
             .into_iter()
-            .filter_entry(|e| e.file_type().is_file() || !excludes.is_match(e.path()))
-            .filter_map(|e| e.map_err(|e| eprintln!("Error: {:?}", e)).ok())
+            .filter_entry(|e| {
+                if e.file_type().is_file() {
+                    return true;
+                }
+
+                if e.depth() > 0 {
+                    // Unless following links, this describes the link itself.
+                    let metadata = e.metadata().ok();
+                    let has = |attributes| {
+                        metadata
+                            .as_ref()
+                            .map_or(false, |md| has_attributes(md, attributes))
+                    };
+
+                    if !follow_links && has(FILE_ATTRIBUTE_REPARSE_POINT) {
+                        skipped_links += 1;
+                        return false;
+                    }
+
+                    if has(skip_attributes) {
+                        skipped_dirs += 1;
+                        return false;
+                    }
+                }
+
+                !excludes.is_match(e.path())
+            })
+            .take_while(|_| {
+                entries += 1;
+                entries % 8 != 0 || !control.is_cancelled_with_pause()
+            })
+            .filter_map(|e| e.map_err(|e| log::warn!("Error: {:?}", e)).ok())
             .filter(|e| e.file_type().is_file())
             .filter_map(|e| e.metadata().map(|md| (e, md)).ok())
-            .filter_map(|(e, md)| e.path().size_on_disk().map(|s| (e, md, s)).ok())
+            .filter_map(|(e, md)| {
+                compact::compressed_size(e.path())
+                    .map(|s| (e, md, s))
+                    .map_err(|err| log::warn!("Error: {}: {:?}", e.path().display(), err))
+                    .ok()
+            })
             .enumerate();
 
         for (count, (entry, metadata, physical)) in walker {
             let shortname = entry
                 .path()
-                .strip_prefix(&path)
+                .strip_prefix(&base)
                 .unwrap_or_else(|_e| entry.path())
                 .to_path_buf();
 
-            let fi = FileInfo {
+            let mut fi = FileInfo {
                 path: shortname,
                 logical_size: metadata.len().max(physical),
                 physical_size: physical,
+                compression: None,
+                modified: metadata.modified().ok(),
             };
 
-            if count % 8 == 0 {
-                if control.is_cancelled_with_pause() {
-                    return Err(ds);
-                }
-
-                if last_status.elapsed() >= Duration::from_millis(50) {
-                    last_status = Instant::now();
-                    control.set_status((fi.path.clone(), ds.summary()));
-                }
+            if count % 8 == 0 && last_status.elapsed() >= Duration::from_millis(50) {
+                last_status = Instant::now();
+                control.set_status((fi.path.clone(), ds.summary()));
             }
 
+            let sparse = has_attributes(&metadata, FILE_ATTRIBUTE_SPARSE_FILE);
             if fi.physical_size < fi.logical_size {
-                ds.push(FileKind::Compressed, fi);
+                fi.compression =
+                    wof::query_compression(compact::long_path(entry.path())).unwrap_or(None);
+            }
+
+            if entry.depth() > 0 && has_attributes(&metadata, skip_attributes) {
+                ds.attribute_skipped += 1;
+                ds.push(FileKind::Skipped, fi);
+            } else if sparse && !self.compress_sparse && fi.compression.is_none() {
+                ds.sparse_skipped += 1;
+                ds.push(FileKind::Skipped, fi);
+            } else if fi.compression.is_some() || (fi.physical_size < fi.logical_size && !sparse) {
+                // Files compressed with another WOF algorithm get recompressed
+                // with the target one, anything else is left alone.
+                match fi.compression {
+                    Some(current) if current != compression && !any_algorithm => {
+                        ds.push(FileKind::Compressible, fi)
+                    }
+                    _ => ds.push(FileKind::Compressed, fi),
+                }
+            } else if !self.in_size_range(fi.logical_size) {
+                ds.push(FileKind::SizeSkipped, fi);
             } else if fi.logical_size <= 4096
-                || metadata.file_attributes()
-                    & (FILE_ATTRIBUTE_READONLY
-                        | FILE_ATTRIBUTE_SYSTEM
-                        | FILE_ATTRIBUTE_TEMPORARY
-                        | FILE_ATTRIBUTE_COMPRESSED)
-                    != 0
+                || metadata.file_attributes() & unwanted != 0
                 || incompressible.contains(entry.path())
                 || excludes.is_match(entry.path())
+                || include_only.map_or(false, |includes| !includes.is_match(entry.path()))
             {
                 ds.push(FileKind::Skipped, fi);
             } else {
@@ -236,17 +806,191 @@ impl Background for FolderScan {
             }
         }
 
+        ds.attribute_skipped += skipped_dirs;
+        ds.reparse_skipped = skipped_links;
+
+        if control.is_cancelled() {
+            return Err(ds);
+        }
+
         Ok(ds)
     }
 }
 
+/// Check if an entry has any of the given file attributes.
+fn has_attributes(metadata: &std::fs::Metadata, attributes: u32) -> bool {
+    metadata.file_attributes() & attributes != 0
+}
+
+/// Count the files in a folder a set of excludes matches, without the rest of
+/// a scan, so globs can be tried out quickly.
+///
+/// As with a scan, every file in an excluded folder counts as excluded.
+pub fn preview_excludes<P: AsRef<Path>>(
+    path: P,
+    excludes: &GlobSet,
+    sample_size: usize,
+) -> ExcludePreview {
+    let base = path.as_ref();
+    let mut preview = ExcludePreview::default();
+
+    let files = WalkDir::new(base)
+        .into_iter()
+        .filter_map(|e| e.map_err(|e| log::warn!("Error: {:?}", e)).ok())
+        .filter(|e| e.file_type().is_file());
+
+    for entry in files {
+        let excluded = entry
+            .path()
+            .ancestors()
+            .take(entry.depth() + 1)
+            .any(|p| excludes.is_match(p));
+
+        if excluded {
+            preview.matched += 1;
+            if preview.sample.len() < sample_size {
+                let shortname = entry.path().strip_prefix(base).unwrap_or(entry.path());
+                preview.sample.push(shortname.to_path_buf());
+            }
+        } else {
+            preview.unmatched += 1;
+        }
+    }
+
+    preview
+}
+
+#[test]
+fn it_reports() {
+    use crate::testutil::file;
+
+    let mut folder = FolderInfo::new("C:\\Games");
+
+    folder.push(FileKind::Compressible, file("game.exe", 8192));
+    folder.push(FileKind::Compressible, file("data.PAK", 65536));
+    folder.push(FileKind::Skipped, file("movie.bk2", 1 << 20));
+    folder.push(FileKind::Skipped, file("intro.bk2", 1 << 21));
+    folder.push(FileKind::Skipped, file("readme", 100));
+
+    let report = folder.report(2);
+
+    assert_eq!(report.summary.skipped.count, 3);
+    assert_eq!(report.extensions["bk2"].count, 2);
+    assert_eq!(report.extensions["pak"].logical_size, 65536);
+    assert_eq!(report.extensions[""].count, 1);
+    assert_eq!(report.summary.extension_ratios["pak"], 1.0);
+
+    assert_eq!(
+        report
+            .largest_skipped
+            .iter()
+            .map(|fi| fi.path.to_str().unwrap())
+            .collect::<Vec<_>>(),
+        vec!["intro.bk2", "movie.bk2"]
+    );
+
+    let exe = folder.pop(FileKind::Compressible).unwrap();
+    folder.push(FileKind::Compressible, exe);
+    let mut pak = folder.pop(FileKind::Compressible).unwrap();
+    assert_eq!(pak.path, PathBuf::from("data.PAK"));
+    pak.physical_size = pak.logical_size / 4;
+    folder.push(FileKind::Compressed, pak);
+
+    let summary = folder.summary();
+    assert_eq!(summary.optimal, 65536.0 / (65536.0 + 8192.0));
+    assert_eq!(summary.extension_ratios["pak"], 0.25);
+    assert_eq!(summary.extension_ratios["exe"], 1.0);
+    assert!(summary.ratio < 1.0);
+    assert_eq!(folder.extensions["pak"].count, 1);
+}
+
+#[test]
+fn it_counts_files_by_algorithm() {
+    let mut folder = FolderInfo::new("C:\\Games");
+    let file = |path: &str, physical_size, compression| FileInfo {
+        physical_size,
+        compression,
+        ..crate::testutil::file(path, 8192)
+    };
+
+    folder.push(
+        FileKind::Compressed,
+        file("a.dll", 4096, Some(Compression::Lzx)),
+    );
+    folder.push(
+        FileKind::Compressed,
+        file("b.dll", 2048, Some(Compression::Lzx)),
+    );
+    folder.push(
+        FileKind::Compressed,
+        file("c.dll", 6144, Some(Compression::Xpress4k)),
+    );
+    folder.push(FileKind::Compressed, file("d.dll", 4096, None));
+    folder.push(FileKind::Compressible, file("game.exe", 8192, None));
+
+    let summary = folder.summary();
+    assert_eq!(summary.by_algorithm["LZX"], (2, 6144));
+    assert_eq!(summary.by_algorithm["XPRESS4K"], (1, 6144));
+    assert_eq!(summary.by_algorithm["Other"], (1, 4096));
+    assert_eq!(summary.by_algorithm["Uncompressed"], (1, 8192));
+
+    let mut exe = folder.pop(FileKind::Compressible).unwrap();
+    exe.physical_size = 2048;
+    exe.compression = Some(Compression::Lzx);
+    folder.push(FileKind::Compressed, exe);
+
+    let summary = folder.summary();
+    assert_eq!(summary.by_algorithm["LZX"], (3, 8192));
+    assert!(!summary.by_algorithm.contains_key("Uncompressed"));
+}
+
+#[test]
+fn it_breaks_down_subfolders() {
+    let mut folder = FolderInfo::new("C:\\Games");
+    for (path, size) in &[
+        ("readme.txt", 100),
+        ("Game A\\game.exe", 1000),
+        ("Game A\\data\\level.pak", 4000),
+        ("Game B\\game.exe", 2000),
+    ] {
+        folder.push(
+            FileKind::Compressible,
+            FileInfo {
+                physical_size: *size / 2,
+                ..crate::testutil::file(path, *size)
+            },
+        );
+    }
+
+    assert_eq!(
+        folder.subfolders(),
+        vec![
+            (PathBuf::from("Game A"), 5000, 2500),
+            (PathBuf::from("Game B"), 2000, 1000),
+        ]
+    );
+}
+
+#[test]
+fn it_filters_by_size() {
+    let scan = FolderScan::new("C:\\Games", GlobSet::empty(), Compression::default());
+    assert!(scan.in_size_range(0));
+    assert!(scan.in_size_range(u64::max_value()));
+
+    let scan = scan.size_range(Some(8192), Some(1 << 20));
+    assert!(!scan.in_size_range(4096));
+    assert!(scan.in_size_range(8192));
+    assert!(scan.in_size_range(1 << 20));
+    assert!(!scan.in_size_range((1 << 20) + 1));
+}
+
 #[test]
 fn it_walks() {
     use crate::background::BackgroundHandle;
     use crate::config::Config;
 
     let gs = Config::default().globset().unwrap();
-    let scanner = FolderScan::new("C:\\Games", gs);
+    let scanner = FolderScan::new("C:\\Games", gs, Compression::default());
 
     let task = BackgroundHandle::spawn(scanner);
 
@@ -267,3 +1011,315 @@ fn it_walks() {
         }
     }
 }
+
+#[test]
+fn it_scans_a_single_file() {
+    use crate::background::BackgroundHandle;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("disk.vhd");
+    std::fs::write(&path, crate::testutil::compressible_text()).unwrap();
+
+    let scanner = FolderScan::new(&path, GlobSet::empty(), Compression::default());
+    let mut folder = BackgroundHandle::spawn(scanner).wait().unwrap();
+
+    assert!(folder.single_file);
+    assert_eq!(folder.base(), dir.path());
+    assert_eq!(folder.len(FileKind::Compressible), 1);
+    assert_eq!(folder.summary().compressible.count, 1);
+
+    let fi = &folder.compressible.files[0];
+    assert_eq!(fi.path, PathBuf::from("disk.vhd"));
+    assert_eq!(folder.full_path(fi), path);
+}
+
+#[test]
+fn it_builds_treemaps() {
+    let mut folder = FolderInfo::new("C:\\Games");
+    let mut add = |kind, path: &str, logical_size, physical_size| {
+        folder.push(
+            kind,
+            FileInfo {
+                physical_size,
+                ..crate::testutil::file(path, logical_size)
+            },
+        )
+    };
+    add(FileKind::Compressed, "Data\\a.pak", 1000, 400);
+    add(FileKind::Compressed, "Data\\b.pak", 600, 300);
+    add(FileKind::Compressible, "Data\\c.pak", 500, 500);
+    add(FileKind::Skipped, "game.exe", 200, 200);
+
+    let tree = folder.treemap_with(|fi| {
+        if fi.path.ends_with("a.pak") {
+            Some(Compression::Lzx)
+        } else if fi.physical_size < fi.logical_size {
+            Some(Compression::Xpress4k)
+        } else {
+            None
+        }
+    });
+
+    assert_eq!(tree.name, "C:\\Games");
+    assert_eq!((tree.logical_size, tree.physical_size), (2300, 1400));
+    assert_eq!(tree.compression, Some(Compression::Lzx));
+
+    let names: Vec<&str> = tree.children.iter().map(|c| c.name.as_str()).collect();
+    assert_eq!(names, ["Data", "game.exe"]);
+
+    let data = &tree.children[0];
+    assert_eq!((data.logical_size, data.physical_size), (2100, 1200));
+    assert_eq!(data.children.len(), 3);
+    assert_eq!(tree.children[1].compression, None);
+    assert!(tree.children[1].children.is_empty());
+}
+
+#[test]
+fn it_writes_csv() {
+    let mut folder = FolderInfo::new("C:\\Games");
+    folder.push(
+        FileKind::Compressible,
+        crate::testutil::file("one, \"two\".txt", 8192),
+    );
+    folder.push(FileKind::Skipped, crate::testutil::file("game.exe", 100));
+
+    let mut out = Vec::new();
+    folder.write_csv_to(&mut out).unwrap();
+
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "path,logical_size,physical_size,algorithm,compressible\n\
+         \"C:\\Games\\one, \"\"two\"\".txt\",8192,8192,,true\n\
+         C:\\Games\\game.exe,100,100,,false\n"
+    );
+}
+
+#[test]
+fn it_measures_compressed_files() {
+    use crate::background::BackgroundHandle;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("test.txt");
+    std::fs::write(&path, crate::testutil::compressible_text()).unwrap();
+
+    if !wof::file_supports_compression(&path).unwrap_or(false) {
+        return;
+    }
+    assert!(wof::compress_file(&path, Compression::Xpress4k).unwrap());
+
+    let scanner = FolderScan::new(dir.path(), GlobSet::empty(), Compression::Xpress4k);
+    let folder = BackgroundHandle::spawn(scanner).wait().unwrap();
+    let summary = folder.summary();
+
+    // The allocation size would still be the full file.
+    assert_eq!(summary.compressed.count, 1);
+    assert_eq!(
+        summary.compressed.physical_size,
+        compact::compressed_size(&path).unwrap()
+    );
+    assert!(summary.compressed.physical_size < summary.compressed.logical_size);
+}
+
+#[test]
+fn it_only_includes_matching_files() {
+    use crate::background::BackgroundHandle;
+    use crate::config::Config;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    for name in &["data.pak", "data.bin", "game.exe"] {
+        std::fs::write(dir.path().join(name), crate::testutil::compressible_text()).unwrap();
+    }
+
+    let conf = Config {
+        excludes: vec!["*.bin".to_string()],
+        include_only: vec!["pak".to_string(), "bin".to_string()],
+        ..Config::default()
+    };
+    let scanner = FolderScan::new(dir.path(), conf.globset().unwrap(), Compression::default())
+        .include_only(conf.include_globset().unwrap());
+    let mut folder = BackgroundHandle::spawn(scanner).wait().unwrap();
+
+    // Excludes win over includes.
+    assert_eq!(folder.len(FileKind::Compressible), 1);
+    assert_eq!(folder.len(FileKind::Skipped), 2);
+    let fi = folder.pop(FileKind::Compressible).unwrap();
+    assert_eq!(fi.path, PathBuf::from("data.pak"));
+}
+
+#[test]
+fn it_previews_excludes() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    std::fs::create_dir(dir.path().join("cache")).unwrap();
+    for name in &[
+        "game.exe",
+        "data.pak",
+        "cache\\shaders.bin",
+        "cache\\log.txt",
+    ] {
+        std::fs::write(dir.path().join(name), "hello").unwrap();
+    }
+
+    let mut builder = globset::GlobSetBuilder::new();
+    builder.add(globset::Glob::new("*.pak").unwrap());
+    builder.add(globset::Glob::new("*\\cache").unwrap());
+    let excludes = builder.build().unwrap();
+
+    let preview = preview_excludes(dir.path(), &excludes, 2);
+    assert_eq!(preview.matched, 3);
+    assert_eq!(preview.unmatched, 1);
+    assert_eq!(preview.sample.len(), 2);
+
+    let all = preview_excludes(dir.path(), &excludes, 10);
+    assert!(all.sample.contains(&PathBuf::from("cache\\log.txt")));
+    assert!(!all.sample.contains(&PathBuf::from("game.exe")));
+}
+
+#[test]
+fn it_skips_hidden_files() {
+    use crate::testutil::scan;
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::fileapi::SetFileAttributesW;
+
+    let hide = |path: &Path| {
+        let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+        assert!(unsafe { SetFileAttributesW(path.as_ptr(), FILE_ATTRIBUTE_HIDDEN) } != 0);
+    };
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let data = crate::testutil::compressible_text();
+    std::fs::create_dir(dir.path().join("hidden")).unwrap();
+    std::fs::write(dir.path().join("hidden").join("inside.txt"), &data).unwrap();
+    std::fs::write(dir.path().join("hidden.txt"), &data).unwrap();
+    std::fs::write(dir.path().join("shown.txt"), &data).unwrap();
+    hide(&dir.path().join("hidden"));
+    hide(&dir.path().join("hidden.txt"));
+
+    let mut folder = scan(dir.path(), |s| s.skip_hidden(true));
+    assert_eq!(folder.len(FileKind::Compressible), 1);
+    assert_eq!(folder.len(FileKind::Skipped), 1);
+    assert_eq!(folder.summary().attribute_skipped, 2);
+
+    let mut folder = scan(dir.path(), |s| s.skip_hidden(false));
+    assert_eq!(folder.len(FileKind::Compressible), 3);
+    assert_eq!(folder.summary().attribute_skipped, 0);
+}
+
+#[test]
+fn it_skips_sparse_files() {
+    use crate::testutil::scan;
+    use std::os::windows::io::AsRawHandle;
+    use winapi::um::ioapiset::DeviceIoControl;
+    use winapi::um::winioctl::FSCTL_SET_SPARSE;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let data = crate::testutil::compressible_text();
+    std::fs::write(dir.path().join("dense.txt"), &data).unwrap();
+    std::fs::write(dir.path().join("sparse.vhd"), &data).unwrap();
+
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .open(dir.path().join("sparse.vhd"))
+        .unwrap();
+    let mut bytes = 0;
+    let ret = unsafe {
+        DeviceIoControl(
+            file.as_raw_handle() as _,
+            FSCTL_SET_SPARSE,
+            std::ptr::null_mut(),
+            0,
+            std::ptr::null_mut(),
+            0,
+            &mut bytes,
+            std::ptr::null_mut(),
+        )
+    };
+    assert!(ret != 0);
+    drop(file);
+
+    let mut folder = scan(dir.path(), |s| s.compress_sparse(false));
+    assert_eq!(folder.len(FileKind::Compressible), 1);
+    assert_eq!(folder.len(FileKind::Skipped), 1);
+    assert_eq!(folder.summary().sparse_skipped, 1);
+
+    let mut folder = scan(dir.path(), |s| s.compress_sparse(true));
+    assert_eq!(folder.len(FileKind::Compressible), 2);
+    assert_eq!(folder.summary().sparse_skipped, 0);
+}
+
+#[test]
+fn it_walks_in_order_of_name() {
+    use crate::testutil::scan;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    std::fs::create_dir(dir.path().join("b")).unwrap();
+    for name in &["c.txt", "b\\z.txt", "b\\a.txt", "a.txt"] {
+        std::fs::write(dir.path().join(name), crate::testutil::compressible_text()).unwrap();
+    }
+
+    let mut folder = scan(dir.path(), |s| s.sorted(true));
+
+    let mut found = vec![];
+    while let Some(fi) = folder.pop(FileKind::Compressible) {
+        found.push(fi.path);
+    }
+    assert_eq!(
+        found,
+        ["a.txt", "b\\a.txt", "b\\z.txt", "c.txt"]
+            .iter()
+            .map(PathBuf::from)
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn it_limits_depth() {
+    use crate::testutil::scan;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let data = crate::testutil::compressible_text();
+    let deep = dir.path().join("a").join("b");
+    std::fs::create_dir_all(&deep).unwrap();
+    std::fs::write(dir.path().join("top.txt"), &data).unwrap();
+    std::fs::write(dir.path().join("a").join("middle.txt"), &data).unwrap();
+    std::fs::write(deep.join("bottom.txt"), &data).unwrap();
+
+    let depth = |depth| scan(dir.path(), |s| s.max_depth(depth)).len(FileKind::Compressible);
+    assert_eq!(depth(Some(1)), 1);
+    assert_eq!(depth(Some(2)), 2);
+    assert_eq!(depth(None), 3);
+}
+
+#[test]
+fn it_skips_junctions() {
+    use crate::testutil::scan;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let target = dir.path().join("target");
+    std::fs::create_dir(&target).unwrap();
+    std::fs::write(
+        target.join("data.txt"),
+        crate::testutil::compressible_text(),
+    )
+    .unwrap();
+
+    let link = dir.path().join("link");
+    let made = std::process::Command::new("cmd")
+        .arg("/C")
+        .arg("mklink")
+        .arg("/J")
+        .arg(&link)
+        .arg(&target)
+        .output()
+        .map_or(false, |out| out.status.success());
+    if !made {
+        return;
+    }
+
+    let mut folder = scan(dir.path(), |s| s.follow_links(false));
+    assert_eq!(folder.len(FileKind::Compressible), 1);
+    assert_eq!(folder.summary().reparse_skipped, 1);
+
+    let mut folder = scan(dir.path(), |s| s.follow_links(true));
+    assert_eq!(folder.len(FileKind::Compressible), 2);
+    assert_eq!(folder.summary().reparse_skipped, 0);
+}
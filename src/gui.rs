@@ -1,3 +1,4 @@
+use std::cell::Cell;
 use std::path::{Path, PathBuf};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
@@ -7,11 +8,87 @@ use dirs_sys::known_folder;
 use serde_derive::{Deserialize, Serialize};
 use web_view::*;
 use winapi::um::knownfolders;
+use winapi::um::shobjidl::{FOS_ALLOWMULTISELECT, FOS_FORCEFILESYSTEM};
 
+use crate::auto;
 use crate::backend::Backend;
-use crate::config::Config;
-use crate::folder::FolderSummary;
-use crate::persistence::{self, config};
+use crate::compact;
+use crate::config::{
+    check_number, parse_exclude_file, parse_extensions, validate_excludes, Config, Preset,
+    INCOMPRESSIBLE_EXTENSIONS, MAX_DEPTH, MAX_FILE_SIZE, MAX_MIN_AGE_DAYS, MAX_MIN_SAVINGS_PCT,
+    MAX_RUNTIME_SECS, MAX_THREADS, MAX_THROUGHPUT_LIMIT,
+};
+use crate::error::{self, CompactorError};
+use crate::folder::{ExcludePreview, FolderSummary};
+use crate::logging;
+use crate::persistence::{self, config, config_for, named_presets, profiles};
+use crate::shell;
+use crate::state::Stats;
+use crate::tray::{Tray, TrayHandle};
+use crate::window::Window;
+use crate::wof;
+
+const WINDOW_TITLE: &str = "Compactor";
+const DEFAULT_SIZE: (i32, i32) = (750, 430);
+const MIN_SIZE: (i32, i32) = (600, 360);
+
+/// Everything on the settings page, as it sends it to be saved.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SettingsForm {
+    decimal: bool,
+    compression: String,
+    excludes: String,
+    #[serde(default)]
+    exclude_extensions: String,
+    #[serde(default)]
+    include_only: String,
+    #[serde(default)]
+    case_insensitive: bool,
+    #[serde(default)]
+    skip_incompressible: bool,
+    #[serde(default)]
+    follow_links: bool,
+    #[serde(default)]
+    skip_hidden: bool,
+    #[serde(default)]
+    skip_system: bool,
+    #[serde(default)]
+    verify: bool,
+    #[serde(default)]
+    verify_folder: bool,
+    #[serde(default)]
+    allow_protected: bool,
+    #[serde(default)]
+    backup_fallback: bool,
+    #[serde(default)]
+    clear_readonly: bool,
+    #[serde(default)]
+    compress_sparse: bool,
+    #[serde(default)]
+    revert_if_larger: bool,
+    #[serde(default)]
+    auto_analyse: bool,
+    #[serde(default)]
+    notify_on_complete: bool,
+    #[serde(default)]
+    pause_on_battery: bool,
+    #[serde(default)]
+    confirm_before_compress: bool,
+    #[serde(default)]
+    write_manifest: bool,
+    #[serde(default)]
+    read_only_mode: bool,
+    #[serde(default)]
+    priority: String,
+    #[serde(default)]
+    log_level: String,
+    #[serde(default)]
+    order: String,
+    #[serde(default)]
+    theme: String,
+    #[serde(flatten)]
+    numbers: NumberSettings,
+}
 
 // messages received from the GUI
 #[derive(Deserialize, Debug, Clone)]
@@ -20,18 +97,107 @@ pub enum GuiRequest {
     OpenUrl {
         url: String,
     },
-    SaveConfig {
-        decimal: bool,
-        compression: String,
+    SaveConfig(SettingsForm),
+    SaveProfile {
+        path: PathBuf,
+        #[serde(flatten)]
+        settings: SettingsForm,
+    },
+    ApplyPreset {
+        preset: String,
+    },
+    /// Save the global settings as a named preset.
+    SavePreset {
+        name: String,
+    },
+    /// Replace the global settings with a named preset.
+    LoadPreset {
+        name: String,
+    },
+    ValidateExcludes {
         excludes: String,
     },
+    ChooseExcludesFile,
+    /// Add the globs in a .gitignore-style file to the excludes.
+    ImportExcludes {
+        path: PathBuf,
+    },
+    /// Count what the current excludes would skip in the selected folder.
+    PreviewExcludes,
     ResetConfig,
+    OpenLogFolder,
+    /// Show a file selected in its folder in Explorer.
+    RevealFile {
+        path: PathBuf,
+    },
+    ResetStats,
     ChooseFolder,
-    Compress,
-    Decompress,
+    ChooseFile,
+    OpenFolder {
+        path: PathBuf,
+    },
+    /// A folder or file dragged onto the window.
+    DroppedPath {
+        path: PathBuf,
+    },
+    SelectRecent {
+        index: usize,
+    },
+    Compress {
+        #[serde(default)]
+        force: bool,
+        #[serde(default)]
+        incremental: bool,
+    },
+    Decompress {
+        #[serde(default)]
+        only: Option<String>,
+    },
+    /// Decompress the files compressed by the most recent run.
+    UndoLast,
+    /// Decompress every folder Compactor has ever compressed, before
+    /// uninstalling.
+    DecompressAllTracked,
+    /// Decompress exactly the files listed in a folder's manifest, given
+    /// either the manifest or the folder.
+    DecompressFromManifest {
+        path: PathBuf,
+    },
+    /// Put a summary of the last compression result on the clipboard.
+    CopySummary,
+    /// Leave these subfolders out of the next compression run.
+    SetExcludedSubfolders {
+        paths: Vec<PathBuf>,
+    },
     Pause,
     Resume,
+    /// Abandon the files currently being compressed, leaving them as they were.
+    SkipFile,
     Analyse,
+    EstimateCompression {
+        compression: String,
+    },
+    Benchmark,
+    /// Check the current settings and compress a scratch file with them.
+    TestConfig,
+    ChooseReportFile,
+    ExportReport {
+        path: PathBuf,
+    },
+    ChooseCsvFile,
+    /// Write a CSV listing every file in the analysed folder.
+    ExportCsv {
+        path: PathBuf,
+    },
+    ChooseTreemapFile,
+    /// Write a JSON tree of the analysed folder's sizes and algorithms.
+    ExportTreemap {
+        path: PathBuf,
+    },
+    ChooseQueueFolder,
+    QueueFolder {
+        path: PathBuf,
+    },
     Stop,
     Quit,
 }
@@ -48,112 +214,623 @@ pub enum GuiResponse {
         decimal: bool,
         compression: String,
         excludes: String,
+        exclude_extensions: String,
+        include_only: String,
+        case_insensitive: bool,
+        skip_incompressible: bool,
+        follow_links: bool,
+        skip_hidden: bool,
+        skip_system: bool,
+        min_size: Option<u64>,
+        max_size: Option<u64>,
+        min_age_days: Option<u64>,
+        max_depth: Option<usize>,
+        threads: usize,
+        verify: bool,
+        verify_folder: bool,
+        allow_protected: bool,
+        backup_fallback: bool,
+        clear_readonly: bool,
+        compress_sparse: bool,
+        revert_if_larger: bool,
+        auto_analyse: bool,
+        notify_on_complete: bool,
+        pause_on_battery: bool,
+        confirm_before_compress: bool,
+        write_manifest: bool,
+        read_only_mode: bool,
+        priority: String,
+        log_level: String,
+        order: String,
+        theme: String,
+        throughput_limit: Option<u64>,
+        max_runtime_secs: Option<u64>,
+        min_savings_pct: u32,
+        preset: Option<String>,
     },
     Folder {
         path: PathBuf,
     },
+    FolderCancelled,
+    /// Several folders chosen at once, queued to be worked on in turn.
+    Folders {
+        paths: Vec<PathBuf>,
+    },
+    ExcludeValidation {
+        ok: bool,
+        error: Option<String>,
+    },
+    ExcludePreview {
+        matched: usize,
+        unmatched: usize,
+        sample: Vec<PathBuf>,
+    },
+    DiskInfo {
+        free: u64,
+        total: u64,
+    },
+    Warning {
+        message: String,
+    },
+    /// How many new exclude globs an `ImportExcludes` added.
+    ExcludesImported {
+        path: PathBuf,
+        count: usize,
+    },
     Status {
         status: String,
         pct: Option<f32>,
+        eta_secs: Option<u64>,
+        /// MiB per second over the last few seconds, while compressing.
+        throughput_mbps: Option<f32>,
     },
     FolderSummary {
         info: FolderSummary,
     },
+    /// Each immediate subfolder's path, logical size and physical size.
+    SubfolderBreakdown {
+        entries: Vec<(PathBuf, u64, u64)>,
+    },
+    CompressionResult {
+        before: FolderSummary,
+        after: FolderSummary,
+        /// Whether the run was stopped early, so `after` reflects partial work.
+        stopped: bool,
+    },
     Paused,
     Resumed,
     Scanned,
     Stopped,
-    Compacting,
+    Compacting {
+        /// Whether the files underway can be skipped with `SkipFile`.
+        skippable: bool,
+    },
+    ReportExported {
+        path: PathBuf,
+    },
+    Estimate {
+        projected_saved: u64,
+        sample_ratio: f32,
+    },
+    /// Each algorithm's name, size on disk ratio, and throughput in MiB/s.
+    BenchmarkResult {
+        results: Vec<(String, f32, f32)>,
+    },
+    Errors {
+        count: usize,
+        sample: Vec<String>,
+        /// The full path of each file in `sample`, for `RevealFile`.
+        paths: Vec<PathBuf>,
+    },
+    Phase {
+        phase: String,
+        current: u64,
+        total: u64,
+    },
+    CurrentFile {
+        path: PathBuf,
+        index: u64,
+        total: u64,
+    },
+    QueueUpdated {
+        pending: Vec<PathBuf>,
+        active: Option<PathBuf>,
+    },
+    /// Something went wrong, with a machine-readable `code` from
+    /// `CompactorError::code`.
+    Error {
+        code: String,
+        message: String,
+    },
+    /// The WOF driver isn't available, so there's no point offering to compress.
+    CompressionUnavailable,
+    /// Which of the folders being decompressed by `DecompressAllTracked` is
+    /// underway, numbered from 1.
+    TrackedFolder {
+        path: PathBuf,
+        index: usize,
+        total: usize,
+    },
+    /// Which folder's last compression run can be undone, if any.
+    UndoAvailable {
+        folder: Option<PathBuf>,
+    },
+    RecentFolders {
+        paths: Vec<PathBuf>,
+    },
+    Presets {
+        names: Vec<String>,
+    },
+    LifetimeStats {
+        bytes_saved: u64,
+        files: u64,
+    },
+    EquivalentCommand {
+        cmd: String,
+    },
 }
 
-pub struct GuiWrapper<T>(Handle<T>);
-
-impl<T> GuiWrapper<T> {
-    pub fn new(handle: Handle<T>) -> Self {
-        let gui = Self(handle);
-        gui.version();
-        gui.config();
-        gui
+impl From<&Config> for GuiResponse {
+    fn from(s: &Config) -> Self {
+        GuiResponse::Config {
+            decimal: s.decimal,
+            compression: if s.auto_compression {
+                auto::NAME.to_string()
+            } else {
+                s.compression.to_string()
+            },
+            excludes: s.excludes.join("\n"),
+            exclude_extensions: s.exclude_extensions.join(", "),
+            include_only: s.include_only.join(", "),
+            case_insensitive: s.case_insensitive,
+            skip_incompressible: s.skip_incompressible,
+            follow_links: s.follow_links,
+            skip_hidden: s.skip_hidden,
+            skip_system: s.skip_system,
+            min_size: s.min_size,
+            max_size: s.max_size,
+            min_age_days: s.min_age_days,
+            max_depth: s.max_depth,
+            threads: s.threads,
+            verify: s.verify,
+            verify_folder: s.verify_folder,
+            allow_protected: s.allow_protected,
+            backup_fallback: s.backup_fallback,
+            clear_readonly: s.clear_readonly,
+            compress_sparse: s.compress_sparse,
+            revert_if_larger: s.revert_if_larger,
+            auto_analyse: s.auto_analyse,
+            notify_on_complete: s.notify_on_complete,
+            pause_on_battery: s.pause_on_battery,
+            confirm_before_compress: s.confirm_before_compress,
+            write_manifest: s.write_manifest,
+            read_only_mode: s.read_only_mode,
+            priority: s.priority.to_string(),
+            log_level: s.log_level.to_string(),
+            order: s.order.to_string(),
+            theme: s.theme.to_string(),
+            throughput_limit: s.throughput_limit,
+            max_runtime_secs: s.max_runtime_secs,
+            min_savings_pct: s.min_savings_pct,
+            preset: s.preset.map(|p| p.to_string()),
+        }
     }
+}
 
-    pub fn send(&self, msg: &GuiResponse) {
-        let js = format!(
-            "Response.dispatch(JSON.parse({}))",
-            serde_json::to_string(msg)
-                .and_then(|s| serde_json::to_string(&s))
-                .expect("serialize")
-        );
-        self.0.dispatch(move |wv| wv.eval(&js)).ok(); // let errors bubble through via messages
+impl From<Stats> for GuiResponse {
+    fn from(s: Stats) -> Self {
+        GuiResponse::LifetimeStats {
+            bytes_saved: s.bytes_saved,
+            files: s.files,
+        }
     }
+}
 
-    pub fn version(&self) {
-        let version = GuiResponse::Version {
-            date: env!("VERGEN_BUILD_DATE").to_string(),
-            version: format!("{}-{}", env!("CARGO_PKG_VERSION"), env!("VERGEN_SHA_SHORT")),
-        };
-        self.send(&version);
+/// Something the `Backend` can report its progress to.
+///
+/// The GUI implements this by forwarding messages to the WebView, while the
+/// command-line mode prints them to the console.
+pub trait Frontend {
+    fn send(&self, msg: &GuiResponse);
+
+    /// Ask for one or more folders to work on, receiving none if cancelled.
+    fn choose_folder(&self) -> Receiver<Vec<PathBuf>>;
+
+    /// Ask for a single file to work on, receiving none if cancelled.
+    fn choose_file(&self) -> Receiver<Vec<PathBuf>>;
+
+    /// Ask the user a yes or no question, answering `true` for yes.
+    fn confirm(&self, title: &str, message: &str) -> Receiver<bool>;
+
+    /// Whether there's someone to answer optional questions, rather than
+    /// just the ones `confirm` must be asked.
+    fn interactive(&self) -> bool {
+        false
     }
 
-    pub fn config(&self) {
-        let s = config().read().unwrap().current();
-        self.send(&GuiResponse::Config {
-            decimal: s.decimal,
-            compression: s.compression.to_string(),
-            excludes: s.excludes.join("\n"),
+    fn config(&self, s: Config) {
+        self.send(&GuiResponse::from(&s));
+        self.send(&GuiResponse::EquivalentCommand {
+            cmd: s.equivalent_command(),
         });
     }
 
-    pub fn summary(&self, info: FolderSummary) {
+    fn summary(&self, info: FolderSummary) {
         self.send(&GuiResponse::FolderSummary { info });
     }
 
-    pub fn status<S: AsRef<str>>(&self, msg: S, val: Option<f32>) {
+    fn subfolder_breakdown(&self, entries: Vec<(PathBuf, u64, u64)>) {
+        self.send(&GuiResponse::SubfolderBreakdown { entries });
+    }
+
+    /// The folder as it was before and after a compression run.
+    fn compression_result(&self, before: FolderSummary, after: FolderSummary, stopped: bool) {
+        self.send(&GuiResponse::CompressionResult {
+            before,
+            after,
+            stopped,
+        });
+    }
+
+    /// Draw the user's attention to a finished job, if they're elsewhere.
+    fn notify(&self, _title: &str, _message: &str) {}
+
+    fn status<S: AsRef<str>>(&self, msg: S, val: Option<f32>) {
+        self.status_progress(msg, val, None, None);
+    }
+
+    fn status_progress<S: AsRef<str>>(
+        &self,
+        msg: S,
+        val: Option<f32>,
+        eta_secs: Option<u64>,
+        throughput_mbps: Option<f32>,
+    ) {
         self.send(&GuiResponse::Status {
             status: msg.as_ref().to_owned(),
             pct: val,
+            eta_secs,
+            throughput_mbps,
         });
     }
 
-    pub fn folder<P: AsRef<Path>>(&self, path: P) {
+    fn folder<P: AsRef<Path>>(&self, path: P) {
         self.send(&GuiResponse::Folder {
             path: path.as_ref().to_path_buf(),
         });
     }
 
-    pub fn paused(&self) {
+    fn folder_cancelled(&self) {
+        self.send(&GuiResponse::FolderCancelled);
+    }
+
+    fn folders(&self, paths: &[PathBuf]) {
+        self.send(&GuiResponse::Folders {
+            paths: paths.to_vec(),
+        });
+    }
+
+    fn exclude_preview(&self, preview: ExcludePreview) {
+        self.send(&GuiResponse::ExcludePreview {
+            matched: preview.matched,
+            unmatched: preview.unmatched,
+            sample: preview.sample,
+        });
+    }
+
+    /// Free and total space on the chosen folder's volume, in bytes.
+    fn disk_info(&self, free: u64, total: u64) {
+        self.send(&GuiResponse::DiskInfo { free, total });
+    }
+
+    /// Something the user should know about, but which doesn't stop anything.
+    fn warning<S: AsRef<str>>(&self, message: S) {
+        self.send(&GuiResponse::Warning {
+            message: message.as_ref().to_string(),
+        });
+    }
+
+    /// Report progress through "scanning", "analysing" or "compacting".
+    ///
+    /// A `total` of 0 means it isn't known yet.
+    fn phase(&self, phase: &str, current: u64, total: u64) {
+        self.send(&GuiResponse::Phase {
+            phase: phase.to_owned(),
+            current,
+            total,
+        });
+    }
+
+    /// The file now being worked on, numbered from 1.
+    fn current_file<P: AsRef<Path>>(&self, path: P, index: u64, total: u64) {
+        self.send(&GuiResponse::CurrentFile {
+            path: path.as_ref().to_path_buf(),
+            index,
+            total,
+        });
+    }
+
+    fn paused(&self) {
         self.send(&GuiResponse::Paused);
     }
 
-    pub fn resumed(&self) {
+    fn resumed(&self) {
         self.send(&GuiResponse::Resumed);
     }
 
-    pub fn scanned(&self) {
+    fn scanned(&self) {
         self.send(&GuiResponse::Scanned);
     }
 
-    pub fn stopped(&self) {
+    fn stopped(&self) {
         self.send(&GuiResponse::Stopped);
     }
 
-    pub fn compacting(&self) {
-        self.send(&GuiResponse::Compacting);
+    fn compacting(&self, skippable: bool) {
+        self.send(&GuiResponse::Compacting { skippable });
+    }
+
+    fn estimate(&self, projected_saved: u64, sample_ratio: f32) {
+        self.send(&GuiResponse::Estimate {
+            projected_saved,
+            sample_ratio,
+        });
+    }
+
+    fn benchmark_result(&self, results: Vec<(String, f32, f32)>) {
+        self.send(&GuiResponse::BenchmarkResult { results });
+    }
+
+    fn errors(&self, count: usize, sample: Vec<String>, paths: Vec<PathBuf>) {
+        self.send(&GuiResponse::Errors {
+            count,
+            sample,
+            paths,
+        });
+    }
+
+    fn lifetime_stats(&self, stats: Stats) {
+        self.send(&GuiResponse::from(stats));
+    }
+
+    fn recent_folders(&self, paths: Vec<PathBuf>) {
+        self.send(&GuiResponse::RecentFolders { paths });
+    }
+
+    fn presets(&self, names: Vec<String>) {
+        self.send(&GuiResponse::Presets { names });
+    }
+
+    fn queue_updated(&self, pending: Vec<PathBuf>, active: Option<PathBuf>) {
+        self.send(&GuiResponse::QueueUpdated { pending, active });
+    }
+
+    fn tracked_folder<P: AsRef<Path>>(&self, path: P, index: usize, total: usize) {
+        self.send(&GuiResponse::TrackedFolder {
+            path: path.as_ref().to_path_buf(),
+            index,
+            total,
+        });
+    }
+
+    fn undo_available(&self, folder: Option<PathBuf>) {
+        self.send(&GuiResponse::UndoAvailable { folder });
+    }
+
+    fn report_exported<P: AsRef<Path>>(&self, path: P) {
+        self.send(&GuiResponse::ReportExported {
+            path: path.as_ref().to_path_buf(),
+        });
+    }
+
+    /// Report something going wrong, both as a status for people and as an
+    /// `Error` with a code for programs.
+    fn error(&self, context: &str, err: &CompactorError) {
+        self.status(format!("{}: {}", context, err), Some(1.0));
+        self.send(&GuiResponse::Error {
+            code: err.code().to_string(),
+            message: err.to_string(),
+        });
+    }
+}
+
+pub struct GuiWrapper<T> {
+    handle: Handle<T>,
+    tray: Option<TrayHandle>,
+    tray_pct: Cell<Option<u32>>,
+    taskbar_pct: Cell<Option<u32>>,
+}
+
+impl<T> GuiWrapper<T> {
+    pub fn new(handle: Handle<T>, tray: Option<TrayHandle>) -> Self {
+        let gui = Self {
+            handle,
+            tray,
+            tray_pct: Cell::new(None),
+            taskbar_pct: Cell::new(None),
+        };
+        gui.version();
+        gui.config(config().read().unwrap().current());
+        gui.recent_folders(persistence::recent_folders());
+        gui.presets(named_presets().read().unwrap().names());
+        gui.lifetime_stats(persistence::stats());
+        gui.undo_available(persistence::last_run().map(|run| run.folder));
+
+        if let Some(path) = persistence::last_folder() {
+            gui.folder(&path);
+            gui.scanned();
+        }
+
+        gui.check_compression_available();
+
+        gui
+    }
+
+    pub fn version(&self) {
+        let version = GuiResponse::Version {
+            date: env!("VERGEN_BUILD_DATE").to_string(),
+            version: format!("{}-{}", env!("CARGO_PKG_VERSION"), env!("VERGEN_SHA_SHORT")),
+        };
+        self.send(&version);
+    }
+
+    /// Check compression is possible at all, warning and disabling it if not.
+    fn check_compression_available(&self) {
+        match wof::driver_available() {
+            Ok(true) => (),
+            Ok(false) => {
+                log::warn!("WOF compression is unavailable");
+                self.status(
+                    "Warning: Windows file compression (the WOF driver) isn't available on this system, so nothing can be compressed",
+                    Some(1.0),
+                );
+                self.send(&GuiResponse::CompressionUnavailable);
+            }
+            Err(e) => log::warn!("Error checking for WOF compression: {}", e),
+        }
     }
 
-    pub fn choose_folder(&self) -> Receiver<Option<PathBuf>> {
-        let (tx, rx) = bounded::<Option<PathBuf>>(1);
-        let _ = self.0.dispatch(move |_| {
-            let folder = known_folder(&knownfolders::FOLDERID_ProgramFiles);
-            let folder = folder.and_then(|path| path.to_str().map(str::to_string)).unwrap_or_default();
+    /// Show a chooser for a folder or a single file to work on.
+    fn choose(&self, pick_folders: bool) -> Receiver<Vec<PathBuf>> {
+        let (tx, rx) = bounded::<Vec<PathBuf>>(1);
+        // If the WebView has gone this closure is dropped along with `tx`, so
+        // the receiver disconnects rather than waiting forever.
+        let _ = self.handle.dispatch(move |wv| {
+            let folder = persistence::last_folder()
+                .map(|path| match path.parent() {
+                    Some(parent) if path.is_file() => parent.to_path_buf(),
+                    _ => path,
+                })
+                .or_else(|| known_folder(&knownfolders::FOLDERID_ProgramFiles));
+            let folder = folder
+                .and_then(|path| path.to_str().map(str::to_string))
+                .unwrap_or_default();
+            // Without FOS_FORCEFILESYSTEM the dialog will happily return a
+            // bare server from the Network folder, which has no files to walk.
             let params = wfd::DialogParams {
-                options: wfd::FOS_PICKFOLDERS,
-                title: "Select a directory",
+                options: if pick_folders {
+                    wfd::FOS_PICKFOLDERS | FOS_ALLOWMULTISELECT | FOS_FORCEFILESYSTEM
+                } else {
+                    FOS_FORCEFILESYSTEM
+                },
+                title: if pick_folders {
+                    "Select one or more directories"
+                } else {
+                    "Select a file"
+                },
                 default_folder: &folder,
                 ..Default::default()
             };
-            let _ = tx.send(
-                wfd::open_dialog(params).map(|res| res.selected_file_path).ok()
+            // Cancelling or closing the dialog are both errors here.
+            let chosen: Vec<PathBuf> = wfd::open_dialog(params)
+                .map(|res| {
+                    res.selected_file_paths
+                        .into_iter()
+                        .map(compact::normal_path)
+                        .collect()
+                })
+                .unwrap_or_default();
+
+            if !chosen.is_empty() {
+                // Backwards, so the first chosen ends up the most recent.
+                for path in chosen.iter().rev() {
+                    if let Err(e) = persistence::set_last_folder(path) {
+                        log::warn!("Error saving last folder: {}", e);
+                    }
+                }
+
+                let paths = persistence::recent_folders();
+                message_dispatch(wv, &GuiResponse::RecentFolders { paths });
+            }
+
+            let _ = tx.send(chosen);
+            Ok(())
+        });
+
+        rx
+    }
+}
+
+impl<T> Frontend for GuiWrapper<T> {
+    fn send(&self, msg: &GuiResponse) {
+        let js = format!(
+            "Response.dispatch(JSON.parse({}))",
+            serde_json::to_string(msg)
+                .and_then(|s| serde_json::to_string(&s))
+                .expect("serialize")
+        );
+        self.handle.dispatch(move |wv| wv.eval(&js)).ok(); // let errors bubble through via messages
+
+        if let (Some(tray), GuiResponse::Status { pct, .. }) = (&self.tray, msg) {
+            let pct = pct.filter(|p| p.is_finite()).map(|p| (p * 100.0) as u32);
+            if self.tray_pct.replace(pct) != pct {
+                match pct {
+                    Some(pct) => tray.set_tooltip(&format!("{} - {}%", WINDOW_TITLE, pct)),
+                    None => tray.set_tooltip(WINDOW_TITLE),
+                }
+            }
+        }
+
+        // Finished, stopped and failed jobs all end on a full or missing pct,
+        // which clears the taskbar button rather than leaving it full.
+        let taskbar_pct = match msg {
+            GuiResponse::Status { pct, .. } => pct
+                .filter(|p| p.is_finite() && *p < 1.0)
+                .map(|p| (p.max(0.0) * 100.0) as u32),
+            GuiResponse::Stopped | GuiResponse::Scanned | GuiResponse::Error { .. } => None,
+            _ => return,
+        };
+
+        if self.taskbar_pct.replace(taskbar_pct) != taskbar_pct {
+            let _ = self.handle.dispatch(move |_wv| {
+                if let Some(window) = Window::find(WINDOW_TITLE) {
+                    window.set_progress(taskbar_pct);
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn choose_folder(&self) -> Receiver<Vec<PathBuf>> {
+        self.choose(true)
+    }
+
+    fn interactive(&self) -> bool {
+        true
+    }
+
+    // A tray balloon shows as a toast on Windows 10.  Without a tray icon,
+    // flashing the taskbar button is the best we can do.
+    fn notify(&self, title: &str, message: &str) {
+        if self.tray.map_or(false, |tray| tray.notify(title, message)) {
+            return;
+        }
+
+        let _ = self.handle.dispatch(|_wv| {
+            if let Some(window) = Window::find(WINDOW_TITLE) {
+                window.flash();
+            }
+            Ok(())
+        });
+    }
+
+    fn choose_file(&self) -> Receiver<Vec<PathBuf>> {
+        self.choose(false)
+    }
+
+    fn confirm(&self, title: &str, message: &str) -> Receiver<bool> {
+        let (tx, rx) = bounded::<bool>(1);
+        let title = title.to_owned();
+        let message = message.to_owned();
+        let _ = self.handle.dispatch(move |_wv| {
+            let answer = tinyfiledialogs::message_box_yes_no(
+                &title,
+                &message,
+                tinyfiledialogs::MessageBoxIcon::Warning,
+                tinyfiledialogs::YesNo::No,
             );
+
+            let _ = tx.send(answer == tinyfiledialogs::YesNo::Yes);
             Ok(())
         });
 
@@ -161,7 +838,11 @@ impl<T> GuiWrapper<T> {
     }
 }
 
-pub fn spawn_gui() {
+/// Run the GUI until the window is closed.
+///
+/// A folder given on the command line, as from the Explorer context menu, is
+/// chosen and analysed straight away.
+pub fn spawn_gui(folder: Option<PathBuf>) {
     let running = Arc::new(AtomicBool::new(true));
     let r = running.clone();
     ctrlc::set_handler(move || {
@@ -172,6 +853,7 @@ pub fn spawn_gui() {
     let html = format!(
         include_str!("ui/index.html"),
         style = include_str!("ui/style.css"),
+        incompressible = INCOMPRESSIBLE_EXTENSIONS.join(", "),
         script = format!(
             "{}\n{}",
             include_str!("ui/cash.min.js"),
@@ -181,10 +863,24 @@ pub fn spawn_gui() {
 
     let (from_gui, from_gui_rx) = bounded::<GuiRequest>(128);
 
+    // For requests from outside the WebView.  This must be dropped before the
+    // window closes, so the backend sees the channel close and exits.
+    let to_backend = from_gui.clone();
+
+    persistence::init();
+
+    let folder = folder.filter(|path| path.exists());
+    if let Some(path) = &folder {
+        if let Err(e) = persistence::set_last_folder(path) {
+            log::warn!("Error saving last folder: {}", e);
+        }
+    }
+
     let mut webview = web_view::builder()
-        .title("Compactor")
+        .title(WINDOW_TITLE)
         .content(Content::Html(html))
-        .size(750, 430)
+        .size(DEFAULT_SIZE.0, DEFAULT_SIZE.1)
+        .min_size(MIN_SIZE.0, MIN_SIZE.1)
         .resizable(true)
         .debug(true)
         .user_data(())
@@ -193,71 +889,238 @@ pub fn spawn_gui() {
                 Ok(GuiRequest::OpenUrl { url }) => {
                     let _ = open::that(url);
                 }
-                Ok(GuiRequest::SaveConfig {
-                    decimal,
-                    compression,
-                    excludes,
-                }) => {
-                    let s = Config {
-                        decimal,
-                        compression: compression.parse().unwrap_or_default(),
-                        excludes: excludes.split('\n').map(str::to_owned).collect(),
-                    };
-
-                    if let Err(msg) = s.globset() {
+                Ok(GuiRequest::RevealFile { path }) => {
+                    if let Err(e) = shell::reveal(&path) {
+                        message_dispatch(
+                            &mut webview,
+                            &GuiResponse::Warning {
+                                message: format!("Can't show file: {}", e),
+                            },
+                        );
+                    }
+                }
+                Ok(GuiRequest::OpenLogFolder) => {
+                    if let Some(dir) = persistence::log_dir() {
+                        let _ = std::fs::create_dir_all(&dir);
+                        if let Err(e) = open::that(&dir) {
+                            log::error!("Error opening {}: {}", dir.display(), e);
+                        }
+                    }
+                }
+                Ok(GuiRequest::SaveConfig(form)) => {
+                    let mut s = config().read().unwrap().current();
+                    if let Err(e) = apply_settings(&mut s, form) {
                         tinyfiledialogs::message_box_ok(
                             "Settings Error",
-                            &msg,
+                            &e.to_string(),
                             tinyfiledialogs::MessageBoxIcon::Error,
                         );
+                        // Nothing was saved, so don't leave the form saying otherwise.
+                        config_dispatch(&mut webview, &config().read().unwrap().current());
                     } else {
-                        message_dispatch(
-                            &mut webview,
-                            &GuiResponse::Config {
-                                decimal: s.decimal,
-                                compression: s.compression.to_string(),
-                                excludes: s.excludes.join("\n"),
-                            },
-                        );
+                        config_dispatch(&mut webview, &s);
+                        log::info!("Settings changed: {:?}", s);
+                        logging::set_level(s.log_level);
                         let c = config();
                         let mut c = c.write().unwrap();
                         c.replace(s);
                         if let Err(e) = c.save() {
                             tinyfiledialogs::message_box_ok(
                                 "Settings Error",
-                                &format!("Error saving settings: {:?}", e),
+                                &format!("Error saving settings: {}", e),
                                 tinyfiledialogs::MessageBoxIcon::Error,
                             );
                         }
                     }
                 }
-                Ok(GuiRequest::ResetConfig) => {
-                    let s = Config::default();
+                Ok(GuiRequest::SaveProfile { path, settings }) => {
+                    let mut s = persistence::config_for(&path);
+                    if let Err(e) = apply_settings(&mut s, settings) {
+                        tinyfiledialogs::message_box_ok(
+                            "Settings Error",
+                            &e.to_string(),
+                            tinyfiledialogs::MessageBoxIcon::Error,
+                        );
+                        config_dispatch(&mut webview, &persistence::config_for(&path));
+                    } else {
+                        config_dispatch(&mut webview, &s);
+                        log::info!("Settings for {} changed: {:?}", path.display(), s);
+                        let p = profiles();
+                        let mut p = p.write().unwrap();
+                        p.insert(path, s);
+                        if let Err(e) = p.save() {
+                            tinyfiledialogs::message_box_ok(
+                                "Settings Error",
+                                &format!("Error saving folder settings: {}", e),
+                                tinyfiledialogs::MessageBoxIcon::Error,
+                            );
+                        }
+                    }
+                }
+                Ok(GuiRequest::ApplyPreset { preset }) => match preset.parse::<Preset>() {
+                    Ok(preset) => {
+                        let c = config();
+                        let mut c = c.write().unwrap();
+                        let mut s = c.current();
+                        preset.apply(&mut s);
 
+                        config_dispatch(&mut webview, &s);
+                        c.replace(s);
+                        if let Err(e) = c.save() {
+                            tinyfiledialogs::message_box_ok(
+                                "Settings Error",
+                                &format!("Error saving settings: {}", e),
+                                tinyfiledialogs::MessageBoxIcon::Error,
+                            );
+                        }
+                    }
+                    Err(()) => {
+                        log::warn!("Unknown preset {:?}", preset);
+                    }
+                },
+                Ok(GuiRequest::SavePreset { name }) if !name.trim().is_empty() => {
+                    let p = named_presets();
+                    let mut p = p.write().unwrap();
+                    p.insert(name.trim(), config().read().unwrap().current());
+
+                    message_dispatch(&mut webview, &GuiResponse::Presets { names: p.names() });
+                    if let Err(e) = p.save() {
+                        tinyfiledialogs::message_box_ok(
+                            "Settings Error",
+                            &format!("Error saving presets: {}", e),
+                            tinyfiledialogs::MessageBoxIcon::Error,
+                        );
+                    }
+                }
+                Ok(GuiRequest::LoadPreset { name }) => {
+                    match named_presets().read().unwrap().get(&name) {
+                        Some(s) => {
+                            config_dispatch(&mut webview, &s);
+                            log::info!("Loaded preset {:?}: {:?}", name, s);
+                            logging::set_level(s.log_level);
+                            let c = config();
+                            let mut c = c.write().unwrap();
+                            c.replace(s);
+                            if let Err(e) = c.save() {
+                                tinyfiledialogs::message_box_ok(
+                                    "Settings Error",
+                                    &format!("Error saving settings: {}", e),
+                                    tinyfiledialogs::MessageBoxIcon::Error,
+                                );
+                            }
+                        }
+                        None => {
+                            log::warn!("Unknown preset {:?}", name);
+                        }
+                    }
+                }
+                Ok(GuiRequest::ValidateExcludes { excludes }) => {
+                    let error = validate_excludes(&excludes).err().map(|e| e.to_string());
                     message_dispatch(
                         &mut webview,
-                        &GuiResponse::Config {
-                            decimal: s.decimal,
-                            compression: s.compression.to_string(),
-                            excludes: s.excludes.join("\n"),
+                        &GuiResponse::ExcludeValidation {
+                            ok: error.is_none(),
+                            error,
                         },
                     );
+                }
+                Ok(GuiRequest::ChooseExcludesFile) => {
+                    if let Some(path) = tinyfiledialogs::open_file_dialog(
+                        "Import excludes",
+                        "",
+                        Some((&["*.gitignore", "*.txt", "*"], "Exclude lists")),
+                    ) {
+                        import_excludes(&mut webview, Path::new(&path));
+                    }
+                }
+                Ok(GuiRequest::ImportExcludes { path }) => {
+                    import_excludes(&mut webview, &path);
+                }
+                Ok(GuiRequest::ResetConfig) => {
+                    let s = Config::default();
+
+                    config_dispatch(&mut webview, &s);
+                    log::info!("Settings reset to defaults");
+                    logging::set_level(s.log_level);
                     let c = config();
                     let mut c = c.write().unwrap();
                     c.replace(s);
                     if let Err(e) = c.save() {
                         tinyfiledialogs::message_box_ok(
                             "Settings Error",
-                            &format!("Error saving settings: {:?}", e),
+                            &format!("Error saving settings: {}", e),
                             tinyfiledialogs::MessageBoxIcon::Error,
                         );
                     }
                 }
+                Ok(GuiRequest::ResetStats) => {
+                    if let Err(e) = persistence::reset_stats() {
+                        tinyfiledialogs::message_box_ok(
+                            "Settings Error",
+                            &format!("Error resetting statistics: {}", e),
+                            tinyfiledialogs::MessageBoxIcon::Error,
+                        );
+                    }
+
+                    message_dispatch(&mut webview, &GuiResponse::from(persistence::stats()));
+                }
+                Ok(GuiRequest::ChooseReportFile) => {
+                    if let Some(path) = tinyfiledialogs::save_file_dialog_with_filter(
+                        "Export report",
+                        "report.json",
+                        &["*.json"],
+                        "JSON files",
+                    ) {
+                        from_gui
+                            .send(GuiRequest::ExportReport {
+                                path: PathBuf::from(path),
+                            })
+                            .expect("GUI message queue");
+                    }
+                }
+                Ok(GuiRequest::ChooseCsvFile) => {
+                    if let Some(path) = tinyfiledialogs::save_file_dialog_with_filter(
+                        "Export file list",
+                        "files.csv",
+                        &["*.csv"],
+                        "CSV files",
+                    ) {
+                        from_gui
+                            .send(GuiRequest::ExportCsv {
+                                path: PathBuf::from(path),
+                            })
+                            .expect("GUI message queue");
+                    }
+                }
+                Ok(GuiRequest::ChooseTreemapFile) => {
+                    if let Some(path) = tinyfiledialogs::save_file_dialog_with_filter(
+                        "Export treemap",
+                        "treemap.json",
+                        &["*.json"],
+                        "JSON files",
+                    ) {
+                        from_gui
+                            .send(GuiRequest::ExportTreemap {
+                                path: PathBuf::from(path),
+                            })
+                            .expect("GUI message queue");
+                    }
+                }
+                Ok(GuiRequest::ChooseQueueFolder) => {
+                    if let Some(path) = tinyfiledialogs::select_folder_dialog("Queue a folder", "")
+                    {
+                        from_gui
+                            .send(GuiRequest::QueueFolder {
+                                path: PathBuf::from(path),
+                            })
+                            .expect("GUI message queue");
+                    }
+                }
                 Ok(msg) => {
                     from_gui.send(msg).expect("GUI message queue");
                 }
                 Err(err) => {
-                    eprintln!("Unhandled message {:?}: {:?}", arg, err);
+                    log::warn!("Unhandled message {:?}: {:?}", arg, err);
                 }
             }
 
@@ -266,19 +1129,48 @@ pub fn spawn_gui() {
         .build()
         .expect("WebView");
 
-    persistence::init();
+    let window = Window::find(WINDOW_TITLE);
+    if let Some(window) = &window {
+        window.restore(persistence::window_geometry(), DEFAULT_SIZE);
+    }
+    let mut geometry = window.as_ref().and_then(Window::geometry);
+
+    let tray = window.and_then(|window| Tray::new(window, to_backend.clone(), WINDOW_TITLE));
 
-    let gui = GuiWrapper::new(webview.handle());
+    let gui = GuiWrapper::new(webview.handle(), tray.as_ref().map(Tray::handle));
     let mut backend = Backend::new(gui, from_gui_rx);
+    let mut analyse = folder.is_some();
+    if let Some(path) = persistence::last_folder() {
+        analyse |= config_for(&path).auto_analyse;
+        backend.restore_folder(path);
+    }
+    if analyse {
+        to_backend
+            .send(GuiRequest::Analyse)
+            .expect("GUI message queue");
+    }
+    drop(to_backend);
     let bg = std::thread::spawn(move || {
         backend.run();
     });
 
     while running.load(Ordering::SeqCst) {
         match webview.step() {
-            Some(Ok(_)) => (),
+            Some(Ok(_)) => {
+                // Keep track while the window exists, for saving once closed.
+                if let Some(current) = window.as_ref().and_then(Window::geometry) {
+                    geometry = Some(current);
+                }
+
+                // Minimize to the tray rather than the taskbar, if we have one.
+                if let (Some(window), Some(_)) = (&window, &tray) {
+                    if window.is_minimized() && window.is_visible() {
+                        window.hide();
+                    }
+                }
+            }
             Some(e) => {
-                eprintln!("Error: {:?}", e);
+                log::error!("Error: {:?}", e);
             }
             None => {
                 break;
@@ -286,8 +1178,17 @@ pub fn spawn_gui() {
         }
     }
 
+    drop(tray);
     webview.into_inner();
 
+    if let Some(geometry) = geometry {
+        if persistence::window_geometry() != Some(geometry) {
+            if let Err(e) = persistence::set_window_geometry(geometry) {
+                log::warn!("Error saving window position: {}", e);
+            }
+        }
+    }
+
     bg.join().expect("background thread");
 }
 
@@ -299,3 +1200,151 @@ fn message_dispatch<T>(wv: &mut web_view::WebView<'_, T>, msg: &GuiResponse) {
 
     wv.eval(&js).ok();
 }
+
+/// Store everything from the settings page in a config, checking it all makes
+/// sense.  On error the config may be partly changed, so should be discarded.
+fn apply_settings(s: &mut Config, form: SettingsForm) -> error::Result<()> {
+    form.numbers.apply(s)?;
+    s.decimal = form.decimal;
+    s.auto_compression = form.compression == auto::NAME;
+    if !s.auto_compression {
+        s.compression = form.compression.parse().unwrap_or_default();
+    }
+    s.excludes = form.excludes.split('\n').map(str::to_owned).collect();
+    s.exclude_extensions = parse_extensions(&form.exclude_extensions);
+    s.include_only = parse_extensions(&form.include_only);
+    s.case_insensitive = form.case_insensitive;
+    s.skip_incompressible = form.skip_incompressible;
+    s.follow_links = form.follow_links;
+    s.skip_hidden = form.skip_hidden;
+    s.skip_system = form.skip_system;
+    s.verify = form.verify;
+    s.verify_folder = form.verify_folder;
+    s.allow_protected = form.allow_protected;
+    s.backup_fallback = form.backup_fallback;
+    s.clear_readonly = form.clear_readonly;
+    s.compress_sparse = form.compress_sparse;
+    s.revert_if_larger = form.revert_if_larger;
+    s.auto_analyse = form.auto_analyse;
+    s.notify_on_complete = form.notify_on_complete;
+    s.pause_on_battery = form.pause_on_battery;
+    s.confirm_before_compress = form.confirm_before_compress;
+    s.write_manifest = form.write_manifest;
+    s.read_only_mode = form.read_only_mode;
+    s.priority = form.priority.parse().unwrap_or_default();
+    s.log_level = form.log_level.parse().unwrap_or_default();
+    s.order = form.order.parse().unwrap_or_default();
+    s.theme = form.theme.parse().unwrap_or_default();
+    s.preset = s.preset.filter(|p| p.matches(s));
+
+    s.validate()?;
+    s.globset()?;
+    s.include_globset()?;
+    Ok(())
+}
+
+/// The numeric settings from a `SaveConfig` or `SaveProfile`, as the settings
+/// page sends them.
+#[derive(Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+struct NumberSettings {
+    min_size: Option<f64>,
+    max_size: Option<f64>,
+    min_age_days: Option<f64>,
+    max_depth: Option<f64>,
+    threads: f64,
+    throughput_limit: Option<f64>,
+    max_runtime_secs: Option<f64>,
+    min_savings_pct: f64,
+}
+
+impl NumberSettings {
+    /// Check every number, then store them all, treating zero as none where
+    /// that's what the settings page means by it.  If any is unusable, none
+    /// are stored.
+    fn apply(self, s: &mut Config) -> error::Result<()> {
+        let check =
+            |name, value: Option<f64>, max| value.map(|v| check_number(name, v, max)).transpose();
+
+        let min_size = check("Minimum file size", self.min_size, MAX_FILE_SIZE)?;
+        let max_size = check("Maximum file size", self.max_size, MAX_FILE_SIZE)?;
+        let min_age_days = check("Minimum age", self.min_age_days, MAX_MIN_AGE_DAYS)?;
+        let max_depth = check("Maximum depth", self.max_depth, MAX_DEPTH)?;
+        let threads = check_number("Threads", self.threads, MAX_THREADS)?;
+        let throughput_limit = check(
+            "Throughput limit",
+            self.throughput_limit,
+            MAX_THROUGHPUT_LIMIT,
+        )?;
+        let max_runtime_secs = check("Time limit", self.max_runtime_secs, MAX_RUNTIME_SECS)?;
+        let min_savings_pct =
+            check_number("Minimum savings", self.min_savings_pct, MAX_MIN_SAVINGS_PCT)?;
+
+        s.min_size = min_size;
+        s.max_size = max_size;
+        s.min_age_days = min_age_days.filter(|&days| days > 0);
+        s.max_depth = max_depth
+            .filter(|&depth| depth > 0)
+            .map(|depth| depth as usize);
+        s.threads = threads as usize;
+        s.throughput_limit = throughput_limit.filter(|&limit| limit > 0);
+        s.max_runtime_secs = max_runtime_secs.filter(|&secs| secs > 0);
+        s.min_savings_pct = min_savings_pct as u32;
+
+        Ok(())
+    }
+}
+
+/// Merge the exclude globs in a file into the global settings.
+fn import_excludes<T>(wv: &mut web_view::WebView<'_, T>, path: &Path) {
+    let globs = match std::fs::read_to_string(path)
+        .map_err(|e| CompactorError::path(path, e))
+        .and_then(|text| parse_exclude_file(&text))
+    {
+        Ok(globs) => globs,
+        Err(e) => {
+            tinyfiledialogs::message_box_ok(
+                "Settings Error",
+                &format!("Error importing excludes from {}: {}", path.display(), e),
+                tinyfiledialogs::MessageBoxIcon::Error,
+            );
+            return;
+        }
+    };
+
+    let c = config();
+    let mut c = c.write().unwrap();
+    let mut s = c.current();
+    let count = s.merge_excludes(globs);
+    s.preset = s.preset.filter(|p| p.matches(&s));
+
+    log::info!("Imported {} excludes from {}", count, path.display());
+    config_dispatch(wv, &s);
+    message_dispatch(
+        wv,
+        &GuiResponse::ExcludesImported {
+            path: path.to_path_buf(),
+            count,
+        },
+    );
+
+    c.replace(s);
+    if let Err(e) = c.save() {
+        tinyfiledialogs::message_box_ok(
+            "Settings Error",
+            &format!("Error saving settings: {}", e),
+            tinyfiledialogs::MessageBoxIcon::Error,
+        );
+    }
+}
+
+/// Send a config to the settings page, with the command line it amounts to.
+fn config_dispatch<T>(wv: &mut web_view::WebView<'_, T>, s: &Config) {
+    message_dispatch(wv, &GuiResponse::from(s));
+    message_dispatch(
+        wv,
+        &GuiResponse::EquivalentCommand {
+            cmd: s.equivalent_command(),
+        },
+    );
+}
@@ -0,0 +1,100 @@
+// Settings and state kept as pretty-printed JSON, loaded once at startup and
+// written back whenever they change.
+//
+// A file that's missing or can't be parsed just gives the default, so a
+// damaged config never stops Compactor starting.
+
+use std::io;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::{self, CompactorError};
+
+#[derive(Debug, Default)]
+pub struct JsonFile<T> {
+    backing: Option<PathBuf>,
+    value: T,
+}
+
+impl<T: Default + DeserializeOwned + Serialize> JsonFile<T> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Self {
+        Self {
+            backing: Some(path.as_ref().to_owned()),
+            value: std::fs::read(path)
+                .and_then(|data| {
+                    serde_json::from_slice::<T>(&data)
+                        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// A file to be saved with `value`, whatever it has in it now.
+    pub fn create<P: AsRef<Path>>(path: P, value: T) -> Self {
+        Self {
+            backing: Some(path.as_ref().to_owned()),
+            value,
+        }
+    }
+
+    pub fn save(&self) -> error::Result<()> {
+        match &self.backing {
+            Some(path) => {
+                if let Some(dir) = path.parent() {
+                    std::fs::create_dir_all(dir).map_err(|e| CompactorError::path(dir, e))?;
+                }
+
+                let data = serde_json::to_string_pretty(&self.value).expect("Serialize");
+                std::fs::write(path, &data).map_err(|e| CompactorError::path(path, e))
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Clone> JsonFile<T> {
+    pub fn current(&self) -> T {
+        self.value.clone()
+    }
+
+    pub fn replace(&mut self, value: T) {
+        self.value = value;
+    }
+}
+
+impl<T> Deref for JsonFile<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.value
+    }
+}
+
+impl<T> DerefMut for JsonFile<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.value
+    }
+}
+
+#[test]
+fn it_saves_and_loads_json() {
+    use std::collections::HashMap;
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("settings").join("test.json");
+
+    let mut file = JsonFile::<HashMap<String, u64>>::new(&path);
+    assert!(file.is_empty());
+
+    file.insert("files".to_string(), 42);
+    file.save().unwrap();
+
+    let loaded = JsonFile::<HashMap<String, u64>>::new(&path);
+    assert_eq!(loaded.get("files"), Some(&42));
+
+    std::fs::write(&path, "{ not json").unwrap();
+    assert!(JsonFile::<HashMap<String, u64>>::new(&path).is_empty());
+}
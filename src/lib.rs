@@ -0,0 +1,4 @@
+// The parts of Compactor with no knowledge of the rest of it, for use by other
+// programs.  The Compactor binary uses them from here too.
+
+pub mod wof;
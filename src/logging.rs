@@ -0,0 +1,153 @@
+// A diagnostic log, written to a small set of rotating files.
+//
+// Compactor mostly runs without a console, so anything printed to stderr is
+// lost.  Instead, state changes and errors go through the `log` macros to
+// `compactor.log` in the local data folder.  Once that grows past
+// `MAX_LOG_BYTES` it's renamed to `compactor.1.log`, shuffling older logs
+// along and dropping the oldest, so the whole lot stays a few MiB at most.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use log::{LevelFilter, Log, Metadata, Record};
+
+use crate::config::LogLevel;
+
+/// Size at which the current log is rotated out.
+const MAX_LOG_BYTES: u64 = 1024 * 1024;
+
+/// How many rotated logs to keep alongside the current one.
+const KEEP_LOGS: usize = 3;
+
+const LOG_NAME: &str = "compactor";
+
+struct RotatingFile {
+    dir: PathBuf,
+    file: Option<File>,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn new<P: AsRef<Path>>(dir: P) -> Self {
+        Self {
+            dir: dir.as_ref().to_path_buf(),
+            file: None,
+            written: 0,
+        }
+    }
+
+    fn path(&self, n: usize) -> PathBuf {
+        if n == 0 {
+            self.dir.join(format!("{}.log", LOG_NAME))
+        } else {
+            self.dir.join(format!("{}.{}.log", LOG_NAME, n))
+        }
+    }
+
+    fn open(&mut self) -> io::Result<&mut File> {
+        if self.file.is_none() {
+            fs::create_dir_all(&self.dir)?;
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.path(0))?;
+            self.written = file.metadata()?.len();
+            self.file = Some(file);
+        }
+
+        Ok(self.file.as_mut().unwrap())
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file = None;
+        for n in (0..KEEP_LOGS).rev() {
+            let from = self.path(n);
+            if from.exists() {
+                fs::rename(&from, self.path(n + 1))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn write_line(&mut self, line: &str) -> io::Result<()> {
+        self.open()?;
+        if self.written >= MAX_LOG_BYTES {
+            self.rotate()?;
+        }
+
+        let file = self.open()?;
+        file.write_all(line.as_bytes())?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+}
+
+struct Logger(Mutex<RotatingFile>);
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+
+        let secs = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default();
+        let line = format!(
+            "{} {:<5} {}: {}\n",
+            secs,
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        // Nowhere left to report a failure to write the log.
+        let _ = self.0.lock().unwrap().write_line(&line);
+    }
+
+    fn flush(&self) {
+        if let Some(file) = self.0.lock().unwrap().file.as_mut() {
+            let _ = file.flush();
+        }
+    }
+}
+
+/// Start logging to the given folder at the given level.
+///
+/// Only the first call installs the logger; later ones just change the level.
+pub fn init<P: AsRef<Path>>(dir: P, level: LogLevel) {
+    let logger = Logger(Mutex::new(RotatingFile::new(dir)));
+    let _ = log::set_boxed_logger(Box::new(logger));
+    set_level(level);
+}
+
+/// Change how much is logged, for when the config changes.
+pub fn set_level(level: LogLevel) {
+    log::set_max_level(LevelFilter::from(level));
+}
+
+#[test]
+fn it_rotates_logs() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let mut log = RotatingFile::new(dir.path());
+    let line = format!("{}\n", "x".repeat(1023));
+
+    for _ in 0..(MAX_LOG_BYTES / 1024) * (KEEP_LOGS as u64 + 2) + 1 {
+        log.write_line(&line).unwrap();
+    }
+
+    for n in 0..=KEEP_LOGS {
+        assert!(log.path(n).exists(), "missing log {}", n);
+    }
+    assert!(!log.path(KEEP_LOGS + 1).exists());
+    assert!(fs::metadata(log.path(1)).unwrap().len() >= MAX_LOG_BYTES);
+    assert!(fs::metadata(log.path(0)).unwrap().len() < MAX_LOG_BYTES);
+}
@@ -2,18 +2,47 @@
 #![cfg_attr(test, windows_subsystem = "console")]
 #![allow(non_snake_case)]
 
+mod auto;
 mod backend;
 mod background;
+mod benchmark;
+mod cli;
 mod compact;
 mod compression;
 mod config;
 mod console;
+mod error;
+mod estimate;
 mod folder;
 mod gui;
+mod jsonfile;
+mod logging;
+mod manifest;
 mod persistence;
+mod power;
+mod priority;
+mod privilege;
+mod protected;
+mod protocol;
+mod schedule;
+mod shell;
+mod snapshot;
+mod state;
+#[cfg(test)]
+mod testutil;
+mod tray;
+mod window;
+
+use compactor::wof;
 
 fn setup_panic() {
     std::panic::set_hook(Box::new(|e| {
+        // A bad file is reported like any other error, and the run goes on.
+        if compression::catching_panics() {
+            log::error!("Caught {}", e);
+            return;
+        }
+
         if !console::alloc() {
             // No point trying to print without a console...
             return;
@@ -61,10 +90,33 @@ App: {app}, Version: {ver}, Build Date: {date}, Hash: {hash}
 fn main() {
     setup_panic();
     console::attach();
-    let ret = std::panic::catch_unwind(gui::spawn_gui);
+
+    let (portable, args) = cli::take_portable(std::env::args().skip(1));
+    if portable {
+        persistence::set_portable();
+    }
+
+    let ret = match cli::parse_args(args) {
+        Ok(cli::Command::Run(args)) => std::panic::catch_unwind(|| cli::run_cli(args)),
+        Ok(cli::Command::Gui(path)) => {
+            std::panic::catch_unwind(|| gui::spawn_gui(path)).map(|_| cli::EXIT_SUCCESS)
+        }
+        Ok(cli::Command::Protocol) => std::panic::catch_unwind(protocol::run_protocol),
+        Ok(cli::Command::Register) => Ok(cli::run_shell(true)),
+        Ok(cli::Command::Unregister) => Ok(cli::run_shell(false)),
+        Ok(cli::Command::Schedule { args, at }) => Ok(cli::run_schedule(args, &at)),
+        Ok(cli::Command::Unschedule(path)) => Ok(cli::run_unschedule(path)),
+        Err(msg) => {
+            eprintln!("{}\n\n{}", msg, cli::USAGE);
+            Ok(cli::EXIT_USAGE)
+        }
+    };
+
     console::free();
 
-    if ret.is_err() {
-        std::process::exit(1);
+    match ret {
+        Ok(cli::EXIT_SUCCESS) => (),
+        Ok(code) => std::process::exit(code),
+        Err(_) => std::process::exit(1),
     }
 }
@@ -0,0 +1,120 @@
+// A record, kept in the folder itself, of every file Compactor compressed and
+// with what, so exactly those can be decompressed again later.
+//
+// Unlike the undo state this survives later runs and reinstalls, and moves
+// with the folder, since paths are relative to it.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::error::{self, CompactorError};
+use crate::wof::Compression;
+
+pub const MANIFEST_NAME: &str = ".compactor-manifest.json";
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub files: Vec<ManifestEntry>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestEntry {
+    /// Relative to the folder the manifest is in.
+    pub path: PathBuf,
+    /// `None` if the algorithm couldn't be determined, in which case the file
+    /// is decompressed whatever it's compressed with.
+    pub compression: Option<Compression>,
+}
+
+/// Where the manifest for a folder lives.
+pub fn path_in<P: AsRef<Path>>(folder: P) -> PathBuf {
+    folder.as_ref().join(MANIFEST_NAME)
+}
+
+impl Manifest {
+    /// Read a manifest, or start a new one if there isn't one yet.
+    pub fn read_or_new<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        match Self::read(&path) {
+            Err(CompactorError::Path { source, .. })
+                if source.kind() == io::ErrorKind::NotFound =>
+            {
+                Ok(Self::default())
+            }
+            ret => ret,
+        }
+    }
+
+    pub fn read<P: AsRef<Path>>(path: P) -> error::Result<Self> {
+        let path = path.as_ref();
+        let data = std::fs::read(path).map_err(|e| CompactorError::path(path, e))?;
+
+        serde_json::from_slice(&data)
+            .map_err(|e| CompactorError::path(path, io::Error::new(io::ErrorKind::InvalidData, e)))
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> error::Result<()> {
+        let path = path.as_ref();
+        let write = || -> io::Result<()> {
+            let mut file = BufWriter::new(File::create(path)?);
+            serde_json::to_writer_pretty(&mut file, self)?;
+            file.flush()
+        };
+
+        write().map_err(|e| CompactorError::path(path, e))
+    }
+
+    /// Record files as compressed, replacing any earlier entries for them.
+    pub fn add<I: IntoIterator<Item = ManifestEntry>>(&mut self, entries: I) {
+        let entries: Vec<ManifestEntry> = entries.into_iter().collect();
+        let paths: HashSet<&Path> = entries.iter().map(|e| e.path.as_path()).collect();
+
+        self.files.retain(|e| !paths.contains(e.path.as_path()));
+        self.files.extend(entries.iter().cloned());
+    }
+
+    /// Forget files, once they've been decompressed.
+    pub fn remove<F: FnMut(&ManifestEntry) -> bool>(&mut self, mut done: F) {
+        self.files.retain(|e| !done(e));
+    }
+}
+
+#[test]
+fn it_records_compressed_files() {
+    let entry = |path: &str, compression| ManifestEntry {
+        path: PathBuf::from(path),
+        compression,
+    };
+
+    let mut manifest = Manifest::default();
+    manifest.add(vec![
+        entry("game.exe", Some(Compression::Xpress8k)),
+        entry("data\\level1.pak", Some(Compression::Lzx)),
+    ]);
+    manifest.add(vec![
+        entry("game.exe", Some(Compression::Lzx)),
+        entry("readme.txt", None),
+    ]);
+
+    assert_eq!(manifest.files.len(), 3);
+    assert!(manifest
+        .files
+        .contains(&entry("game.exe", Some(Compression::Lzx))));
+
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = path_in(dir.path());
+    assert_eq!(Manifest::read_or_new(&path).unwrap(), Manifest::default());
+
+    manifest.write(&path).unwrap();
+    let mut read = Manifest::read(&path).unwrap();
+    assert_eq!(read, manifest);
+
+    read.remove(|e| e.compression == Some(Compression::Lzx));
+    assert_eq!(read.files, vec![entry("readme.txt", None)]);
+
+    std::fs::write(&path, "not json").unwrap();
+    assert_eq!(Manifest::read_or_new(&path).unwrap_err().code(), "path");
+}
@@ -1,29 +1,332 @@
 use directories::ProjectDirs;
 use hashfilter::HashFilter;
 use lazy_static::lazy_static;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::RwLock;
+use std::time::SystemTime;
 
-use crate::config::ConfigFile;
+use crate::config::{Config, ConfigFile, PresetFile, ProfileFile};
+use crate::error;
+use crate::jsonfile::JsonFile;
+use crate::logging;
+use crate::state::{LastRun, StateFile, Stats, WindowGeometry};
 
 lazy_static! {
     static ref PATHDB: RwLock<HashFilter> = RwLock::new(HashFilter::default());
     static ref CONFIG: RwLock<ConfigFile> = RwLock::new(ConfigFile::default());
+    static ref PROFILES: RwLock<ProfileFile> = RwLock::new(ProfileFile::default());
+    static ref PRESETS: RwLock<PresetFile> = RwLock::new(PresetFile::default());
+    static ref STATE: RwLock<StateFile> = RwLock::new(StateFile::default());
+}
+
+static PORTABLE: AtomicBool = AtomicBool::new(false);
+
+/// Its presence beside the executable means portable mode without `--portable`.
+const PORTABLE_MARKER: &str = "portable.txt";
+
+/// Keep everything beside the executable, rather than in the user's profile.
+pub fn set_portable() {
+    PORTABLE.store(true, Ordering::Relaxed);
+}
+
+/// The executable's folder, if that's where everything's kept.
+pub fn portable_dir() -> Option<PathBuf> {
+    let exe = std::env::current_exe().ok()?;
+    let dir = exe.parent()?;
+
+    if PORTABLE.load(Ordering::Relaxed) || dir.join(PORTABLE_MARKER).is_file() {
+        Some(dir.to_path_buf())
+    } else {
+        None
+    }
+}
+
+/// Where settings, state and the incompressible file cache are kept.
+struct Dirs {
+    config: PathBuf,
+    data: PathBuf,
+    cache: PathBuf,
+}
+
+fn dirs() -> Option<Dirs> {
+    match portable_dir() {
+        Some(dir) => Some(Dirs {
+            config: dir.clone(),
+            data: dir.clone(),
+            cache: dir,
+        }),
+        None => ProjectDirs::from("", "Freaky", "Compactor").map(|dirs| Dirs {
+            config: dirs.config_dir().to_path_buf(),
+            data: dirs.data_local_dir().to_path_buf(),
+            cache: dirs.cache_dir().to_path_buf(),
+        }),
+    }
 }
 
 pub fn init() {
-    if let Some(dirs) = ProjectDirs::from("", "Freaky", "Compactor") {
+    if let Some(dirs) = dirs() {
         pathdb()
             .write()
             .unwrap()
-            .set_backing(dirs.cache_dir().join("incompressible.dat"));
-        *config().write().unwrap() = ConfigFile::new(dirs.config_dir().join("config.json"));
+            .set_backing(dirs.cache.join("incompressible.dat"));
+        *config().write().unwrap() = ConfigFile::new(dirs.config.join("config.json"));
+        *profiles().write().unwrap() = ProfileFile::new(dirs.config.join("profiles.json"));
+        *named_presets().write().unwrap() = PresetFile::new(dirs.config.join("presets.json"));
+        *state().write().unwrap() = StateFile::new(dirs.data.join("state.json"));
+        logging::init(
+            dirs.data.join("logs"),
+            config().read().unwrap().current().log_level,
+        );
+        log::info!("Compactor {} started", env!("CARGO_PKG_VERSION"));
+        if portable_dir().is_some() {
+            log::info!(
+                "Portable mode, keeping settings in {}",
+                dirs.config.display()
+            );
+        }
     }
 }
 
+/// Where diagnostic logs are written.
+pub fn log_dir() -> Option<PathBuf> {
+    dirs().map(|dirs| dirs.data.join("logs"))
+}
+
 pub fn config() -> &'static RwLock<ConfigFile> {
     &CONFIG
 }
 
+pub fn profiles() -> &'static RwLock<ProfileFile> {
+    &PROFILES
+}
+
+pub fn named_presets() -> &'static RwLock<PresetFile> {
+    &PRESETS
+}
+
+/// The configuration to use for a given folder - its profile if it has one,
+/// otherwise the global config.
+pub fn config_for(path: &Path) -> Config {
+    let profile = profiles().read().unwrap().get(path);
+    profile.unwrap_or_else(|| config().read().unwrap().current())
+}
+
+pub fn state() -> &'static RwLock<StateFile> {
+    &STATE
+}
+
+/// The last folder chosen in the GUI, if it still exists.
+pub fn last_folder() -> Option<PathBuf> {
+    state()
+        .read()
+        .unwrap()
+        .current()
+        .last_folder
+        .filter(|path| path.exists())
+}
+
+/// Recently chosen folders and files, most recent first, skipping any since
+/// removed.
+pub fn recent_folders() -> Vec<PathBuf> {
+    state()
+        .read()
+        .unwrap()
+        .current()
+        .recent_folders
+        .into_iter()
+        .filter(|path| path.exists())
+        .collect()
+}
+
+/// Save a chosen folder as the last one, and add it to the recent list.
+pub fn set_last_folder<P: AsRef<Path>>(path: P) -> error::Result<()> {
+    let s = state();
+    let mut s = s.write().unwrap();
+    let mut current = s.current();
+    current.remember_folder(path);
+    s.replace(current);
+    s.save()
+}
+
+pub fn stats() -> Stats {
+    state().read().unwrap().current().stats
+}
+
+/// Add the results of a compression run to the lifetime totals.
+pub fn record_stats(bytes_saved: u64, files: u64) -> error::Result<Stats> {
+    let s = state();
+    let mut s = s.write().unwrap();
+    let mut current = s.current();
+    current.stats.bytes_saved += bytes_saved;
+    current.stats.files += files;
+    let stats = current.stats;
+    s.replace(current);
+    s.save().map(|_| stats)
+}
+
+pub fn reset_stats() -> error::Result<()> {
+    let s = state();
+    let mut s = s.write().unwrap();
+    let mut current = s.current();
+    current.stats = Stats::default();
+    s.replace(current);
+    s.save()
+}
+
+/// When a folder last finished compressing without errors, if ever.
+pub fn last_compressed(path: &Path) -> Option<SystemTime> {
+    state()
+        .read()
+        .unwrap()
+        .current()
+        .last_compressed
+        .get(path)
+        .copied()
+}
+
+pub fn set_last_compressed<P: AsRef<Path>>(path: P, time: SystemTime) -> error::Result<()> {
+    let s = state();
+    let mut s = s.write().unwrap();
+    let mut current = s.current();
+    current
+        .last_compressed
+        .insert(path.as_ref().to_owned(), time);
+    s.replace(current);
+    s.save()
+}
+
+/// Folders Compactor has compressed files in, and might not have since
+/// decompressed.
+pub fn compressed_folders() -> Vec<PathBuf> {
+    state()
+        .read()
+        .unwrap()
+        .current()
+        .compressed_folders
+        .into_iter()
+        .collect()
+}
+
+/// Remember a folder as having been compressed, or forget it once it's been
+/// decompressed again.
+pub fn set_compressed_folder<P: AsRef<Path>>(path: P, compressed: bool) -> error::Result<()> {
+    let s = state();
+    let mut s = s.write().unwrap();
+    let mut current = s.current();
+    let path = path.as_ref().to_owned();
+    let changed = if compressed {
+        current.compressed_folders.insert(path)
+    } else {
+        current.compressed_folders.remove(&path)
+    };
+
+    if !changed {
+        return Ok(());
+    }
+    s.replace(current);
+    s.save()
+}
+
+/// Where the files compressed by the most recent run are listed.
+fn last_run_list() -> Option<PathBuf> {
+    dirs().map(|dirs| dirs.data.join("last_run.json"))
+}
+
+/// The most recent compression run, if it made any changes not since undone.
+pub fn last_run() -> Option<LastRun> {
+    load_last_run(&mut state().write().unwrap(), last_run_list())
+}
+
+fn load_last_run(s: &mut StateFile, list: Option<PathBuf>) -> Option<LastRun> {
+    let mut run = s.current().last_run?;
+    match &run.file_list {
+        Some(path) => run.files = JsonFile::<Vec<PathBuf>>::new(path).current(),
+        // Older versions listed them in the state itself, where its next save
+        // would lose them.
+        None if list.is_some() && !run.files.is_empty() => {
+            if let Err(e) = save_last_run(s, list, Some(run.clone())) {
+                log::warn!("Error moving the last run's files: {}", e);
+            }
+        }
+        None => (),
+    }
+    Some(run).filter(|run| !run.files.is_empty())
+}
+
+/// Save the most recent compression run, keeping its files in a list of their
+/// own.
+pub fn set_last_run(run: Option<LastRun>) -> error::Result<()> {
+    save_last_run(&mut state().write().unwrap(), last_run_list(), run)
+}
+
+fn save_last_run(
+    s: &mut StateFile,
+    list: Option<PathBuf>,
+    run: Option<LastRun>,
+) -> error::Result<()> {
+    let run = match (run, list) {
+        (Some(mut run), Some(list)) => {
+            JsonFile::create(&list, std::mem::take(&mut run.files)).save()?;
+            run.file_list = Some(list);
+            Some(run)
+        }
+        (None, Some(list)) => {
+            let _ = std::fs::remove_file(list);
+            None
+        }
+        (run, None) => run,
+    };
+
+    let mut current = s.current();
+    current.last_run = run;
+    s.replace(current);
+    s.save()
+}
+
+pub fn window_geometry() -> Option<WindowGeometry> {
+    state().read().unwrap().current().window
+}
+
+pub fn set_window_geometry(geometry: WindowGeometry) -> error::Result<()> {
+    let s = state();
+    let mut s = s.write().unwrap();
+    let mut current = s.current();
+    current.window = Some(geometry);
+    s.replace(current);
+    s.save()
+}
+
 pub fn pathdb() -> &'static RwLock<HashFilter> {
     &PATHDB
 }
+
+#[test]
+fn it_moves_inline_run_files_into_their_own_list() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let path = dir.path().join("state.json");
+    let list = dir.path().join("last_run.json");
+    std::fs::write(
+        &path,
+        r#"{"last_run":{"folder":"C:\\Games","compression":"Lzx",
+            "finished":{"secs_since_epoch":0,"nanos_since_epoch":0},
+            "files":["C:\\Games\\game.exe"]}}"#,
+    )
+    .unwrap();
+    let files = vec![PathBuf::from("C:\\Games\\game.exe")];
+
+    let mut s = StateFile::new(&path);
+    let run = load_last_run(&mut s, Some(list.clone())).unwrap();
+    assert_eq!(run.files, files);
+
+    // Saving something else mustn't lose them.
+    let mut current = s.current();
+    current.remember_folder("C:\\Apps");
+    s.replace(current);
+    s.save().unwrap();
+
+    let mut s = StateFile::new(&path);
+    let run = load_last_run(&mut s, Some(list.clone())).unwrap();
+    assert_eq!(run.files, files);
+    assert_eq!(run.file_list, Some(list));
+}
@@ -0,0 +1,79 @@
+// Power status, so compression can wait for mains power on laptops.
+
+use std::io;
+use std::time::{Duration, Instant};
+
+use winapi::um::winbase::{GetSystemPowerStatus, SYSTEM_POWER_STATUS};
+
+/// How often to ask Windows whether we're on battery.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// `ACLineStatus` when running on battery.  1 is on mains, 255 unknown.
+const AC_LINE_OFFLINE: u8 = 0;
+
+/// Check if the system is running on battery power.
+///
+/// Desktops, and systems that don't know, are assumed to be on mains power.
+pub fn on_battery() -> io::Result<bool> {
+    let mut status: SYSTEM_POWER_STATUS = unsafe { std::mem::zeroed() };
+
+    if unsafe { GetSystemPowerStatus(&mut status) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(status.ACLineStatus == AC_LINE_OFFLINE)
+}
+
+/// Watches for the system switching between battery and mains power.
+#[derive(Debug)]
+pub struct PowerWatch {
+    enabled: bool,
+    on_battery: bool,
+    last_check: Option<Instant>,
+}
+
+impl PowerWatch {
+    pub fn new(enabled: bool) -> Self {
+        Self {
+            enabled,
+            on_battery: false,
+            last_check: None,
+        }
+    }
+
+    /// Returns `Some(true)` when the system has just gone onto battery, and
+    /// `Some(false)` when it's just come back to mains power.
+    ///
+    /// Starting out on battery counts as a switch to it.
+    pub fn changed(&mut self) -> Option<bool> {
+        if !self.enabled
+            || self
+                .last_check
+                .map_or(false, |t| t.elapsed() < POLL_INTERVAL)
+        {
+            return None;
+        }
+
+        self.last_check = Some(Instant::now());
+
+        match on_battery() {
+            Ok(battery) if battery != self.on_battery => {
+                self.on_battery = battery;
+                Some(battery)
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("Error checking power status: {}", e);
+                self.enabled = false;
+                None
+            }
+        }
+    }
+}
+
+#[test]
+fn disabled_watch_never_changes() {
+    let mut watch = PowerWatch::new(false);
+    assert_eq!(watch.changed(), None);
+    assert_eq!(watch.last_check, None);
+}
@@ -0,0 +1,49 @@
+// Process priority, so compression can run in the background without getting
+// in the way of games and the like.
+//
+// This is set for the whole process rather than just the backend thread, since
+// the actual work is spread over the compactor's worker threads.
+
+use std::io;
+
+use winapi::um::processthreadsapi::{GetCurrentProcess, SetPriorityClass};
+use winapi::um::winbase::{
+    BELOW_NORMAL_PRIORITY_CLASS, IDLE_PRIORITY_CLASS, NORMAL_PRIORITY_CLASS,
+};
+
+use crate::config::Priority;
+
+/// Set the priority class of the current process.
+pub fn set_priority(priority: Priority) -> io::Result<()> {
+    let class = match priority {
+        Priority::Normal => NORMAL_PRIORITY_CLASS,
+        Priority::BelowNormal => BELOW_NORMAL_PRIORITY_CLASS,
+        Priority::Idle => IDLE_PRIORITY_CLASS,
+    };
+
+    if unsafe { SetPriorityClass(GetCurrentProcess(), class) } == 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(())
+}
+
+/// Keeps the process at a given priority, going back to normal when dropped.
+#[must_use]
+pub struct PriorityGuard(());
+
+impl PriorityGuard {
+    /// Set the priority class of the current process until the guard is dropped.
+    pub fn set(priority: Priority) -> io::Result<Self> {
+        set_priority(priority)?;
+        Ok(Self(()))
+    }
+}
+
+impl Drop for PriorityGuard {
+    fn drop(&mut self) {
+        if let Err(e) = set_priority(Priority::Normal) {
+            log::warn!("Error restoring priority: {}", e);
+        }
+    }
+}
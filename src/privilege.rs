@@ -0,0 +1,69 @@
+// Process privileges, for the few operations which need more than the usual
+// file permissions.
+//
+// Administrators hold SeBackupPrivilege and SeRestorePrivilege, but they're
+// disabled until asked for.  With them enabled, files opened with
+// FILE_FLAG_BACKUP_SEMANTICS bypass the usual access checks.
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+
+use winapi::shared::winerror::ERROR_NOT_ALL_ASSIGNED;
+use winapi::um::errhandlingapi::GetLastError;
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::processthreadsapi::{GetCurrentProcess, OpenProcessToken};
+use winapi::um::securitybaseapi::AdjustTokenPrivileges;
+use winapi::um::winbase::LookupPrivilegeValueW;
+use winapi::um::winnt::{
+    HANDLE, SE_BACKUP_NAME, SE_PRIVILEGE_ENABLED, SE_RESTORE_NAME, TOKEN_ADJUST_PRIVILEGES,
+    TOKEN_PRIVILEGES,
+};
+
+fn enable(name: &str) -> io::Result<()> {
+    let name: Vec<u16> = OsStr::new(name).encode_wide().chain(Some(0)).collect();
+    let mut token: HANDLE = std::ptr::null_mut();
+
+    unsafe {
+        if OpenProcessToken(GetCurrentProcess(), TOKEN_ADJUST_PRIVILEGES, &mut token) == 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let mut privileges: TOKEN_PRIVILEGES = std::mem::zeroed();
+        privileges.PrivilegeCount = 1;
+        privileges.Privileges[0].Attributes = SE_PRIVILEGE_ENABLED;
+
+        let ret = if LookupPrivilegeValueW(
+            std::ptr::null(),
+            name.as_ptr(),
+            &mut privileges.Privileges[0].Luid,
+        ) == 0
+            || AdjustTokenPrivileges(
+                token,
+                0,
+                &mut privileges,
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+            ) == 0
+        {
+            Err(io::Error::last_os_error())
+        } else if GetLastError() == ERROR_NOT_ALL_ASSIGNED {
+            // "Succeeds" without doing anything if we don't hold it at all.
+            Err(io::Error::from_raw_os_error(ERROR_NOT_ALL_ASSIGNED as i32))
+        } else {
+            Ok(())
+        };
+
+        CloseHandle(token);
+        ret
+    }
+}
+
+/// Enable the backup and restore privileges for the current process.
+///
+/// This fails unless running as administrator.
+pub fn enable_backup() -> io::Result<()> {
+    enable(SE_BACKUP_NAME)?;
+    enable(SE_RESTORE_NAME)
+}
@@ -0,0 +1,103 @@
+// Paths Windows needs to boot and service itself, which are never compressed
+// unless the user explicitly allows it.
+//
+// Compressing these can leave a system unbootable or break updates, and it's
+// all too easy to point Compactor at C:\ and click Compress.
+
+use std::path::{Component, Path};
+
+/// Protected paths, relative to the root of each volume.
+const PROTECTED: &[&str] = &[
+    "Windows",
+    "Boot",
+    "EFI",
+    "Recovery",
+    "System Volume Information",
+    "bootmgr",
+    "BOOTNXT",
+    "pagefile.sys",
+    "hiberfil.sys",
+    "swapfile.sys",
+    "Program Files\\WindowsApps",
+    "Program Files\\Windows Defender",
+    "Program Files\\Windows Defender Advanced Threat Protection",
+    "Program Files (x86)\\Windows Defender",
+    "ProgramData\\Microsoft\\Windows Defender",
+];
+
+/// The protected paths on a given volume.
+#[derive(Debug, Clone)]
+pub struct Protected {
+    paths: Vec<String>,
+}
+
+/// Lowercase a path with consistent separators and no trailing separator, for
+/// case-insensitive prefix checks.
+fn normalize(path: &Path) -> String {
+    path.to_string_lossy()
+        .replace('/', "\\")
+        .trim_end_matches('\\')
+        .to_lowercase()
+}
+
+/// Check if `path` is `parent` or somewhere beneath it, both normalized.
+fn is_within(path: &str, parent: &str) -> bool {
+    path.strip_prefix(parent)
+        .map_or(false, |rest| rest.is_empty() || rest.starts_with('\\'))
+}
+
+impl Protected {
+    /// Protected paths on the volume containing `path`.
+    ///
+    /// Relative paths have no volume to check, so nothing is protected.
+    pub fn for_volume<P: AsRef<Path>>(path: P) -> Self {
+        let paths = match path.as_ref().components().next() {
+            Some(Component::Prefix(prefix)) => {
+                let root = normalize(Path::new(prefix.as_os_str()));
+                PROTECTED
+                    .iter()
+                    .map(|p| format!("{}\\{}", root, p.to_lowercase()))
+                    .collect()
+            }
+            _ => vec![],
+        };
+
+        Self { paths }
+    }
+
+    /// Check if a file or folder is protected itself, or inside one that is.
+    pub fn contains<P: AsRef<Path>>(&self, path: P) -> bool {
+        let path = normalize(path.as_ref());
+        self.paths.iter().any(|p| is_within(&path, p))
+    }
+
+    /// Check if a folder is protected, or has protected paths inside it.
+    pub fn overlaps<P: AsRef<Path>>(&self, root: P) -> bool {
+        let root = normalize(root.as_ref());
+        self.paths
+            .iter()
+            .any(|p| is_within(&root, p) || is_within(p, &root))
+    }
+}
+
+#[test]
+fn it_protects_system_paths() {
+    let protected = Protected::for_volume("C:\\Games");
+
+    assert!(protected.contains("C:\\Windows"));
+    assert!(protected.contains("c:\\WINDOWS\\System32\\ntdll.dll"));
+    assert!(protected.contains("C:\\pagefile.sys"));
+    assert!(protected.contains("C:/Program Files/WindowsApps/Foo/foo.exe"));
+    assert!(!protected.contains("C:\\Windows.old\\foo.dll"));
+    assert!(!protected.contains("C:\\Games\\Windows\\foo.dll"));
+    assert!(!protected.contains("C:\\Program Files\\Steam\\steam.exe"));
+
+    assert!(protected.overlaps("C:\\"));
+    assert!(protected.overlaps("C:\\Program Files"));
+    assert!(protected.overlaps("C:\\Windows\\WinSxS"));
+    assert!(!protected.overlaps("C:\\Games"));
+    assert!(!protected.overlaps("C:\\Program Files\\Steam"));
+
+    assert!(Protected::for_volume("D:\\").contains("D:\\Windows\\explorer.exe"));
+    assert!(!Protected::for_volume("Games").contains("Windows\\explorer.exe"));
+}
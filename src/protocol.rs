@@ -0,0 +1,94 @@
+// A line-based JSON protocol, for driving the backend from scripts and
+// integration tests without a WebView.
+//
+// Each line on stdin is a `GuiRequest`, and each `GuiResponse` the backend
+// sends is written as a line on stdout, using the same tagged JSON as the GUI.
+// There's no one to show folder choosers to, so `OpenFolder` takes a path
+// instead, and confirmations are always answered no.  Failures come with an
+// `Error` response carrying a stable `code`, for scripts to act on.
+//
+// The backend stops when stdin closes or a `Quit` arrives, cancelling any job
+// still running, just as closing the window would.
+
+use std::io::{self, BufRead, Write};
+use std::path::PathBuf;
+use std::thread;
+
+use crossbeam_channel::{bounded, Receiver};
+
+use crate::backend::Backend;
+use crate::cli::{EXIT_FAILURE, EXIT_SUCCESS};
+use crate::gui::{Frontend, GuiRequest, GuiResponse};
+use crate::persistence;
+
+/// Writes backend responses to stdout, one JSON object per line.
+struct JsonLines;
+
+impl Frontend for JsonLines {
+    fn send(&self, msg: &GuiResponse) {
+        let json = serde_json::to_string(msg).expect("serialize");
+        let stdout = io::stdout();
+        let mut out = stdout.lock();
+        // Nobody is listening if stdout has gone, and stdin will close soon.
+        let _ = writeln!(out, "{}", json).and_then(|_| out.flush());
+    }
+
+    fn choose_folder(&self) -> Receiver<Vec<PathBuf>> {
+        let (tx, rx) = bounded::<Vec<PathBuf>>(1);
+        let _ = tx.send(Vec::new());
+        rx
+    }
+
+    fn choose_file(&self) -> Receiver<Vec<PathBuf>> {
+        self.choose_folder()
+    }
+
+    fn confirm(&self, _title: &str, _message: &str) -> Receiver<bool> {
+        let (tx, rx) = bounded::<bool>(1);
+        let _ = tx.send(false);
+        rx
+    }
+}
+
+pub fn run_protocol() -> i32 {
+    persistence::init();
+
+    let (to_backend, from_stdin) = bounded::<GuiRequest>(128);
+
+    let backend = thread::spawn(move || {
+        let mut backend = Backend::new(JsonLines, from_stdin);
+        backend.run();
+    });
+
+    let stdin = io::stdin();
+    for line in stdin.lock().lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                log::error!("Error reading stdin: {}", e);
+                break;
+            }
+        };
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match serde_json::from_str::<GuiRequest>(&line) {
+            Ok(GuiRequest::Quit) => break,
+            Ok(msg) => {
+                if to_backend.send(msg).is_err() {
+                    break;
+                }
+            }
+            Err(e) => log::warn!("Invalid request {:?}: {}", line, e),
+        }
+    }
+
+    drop(to_backend);
+
+    match backend.join() {
+        Ok(()) => EXIT_SUCCESS,
+        Err(_) => EXIT_FAILURE,
+    }
+}
@@ -0,0 +1,219 @@
+// Scheduled compression, via the Windows Task Scheduler.
+//
+// Each scheduled folder gets its own daily task under a "Compactor" folder in
+// the Task Scheduler library, running the command-line mode at idle priority,
+// and only while the machine is otherwise idle.  Tasks are registered by
+// handing schtasks.exe an XML definition, since the command-line options alone
+// can't express the idle conditions.
+
+use std::io;
+use std::path::Path;
+use std::process::Command;
+
+/// The Task Scheduler folder tasks are created in.
+const TASK_FOLDER: &str = "Compactor";
+
+/// Task priorities run from 0 to 10, with 10 the lowest, mapping to an idle
+/// priority class.
+const TASK_PRIORITY: u32 = 10;
+
+/// The task name for a folder, with the characters Task Scheduler won't allow
+/// in a name replaced.
+pub fn task_name(folder: &Path) -> String {
+    let name: String = folder
+        .display()
+        .to_string()
+        .chars()
+        .map(|c| match c {
+            '\\' | '/' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c => c,
+        })
+        .collect();
+
+    format!(r"{}\{}", TASK_FOLDER, name.trim_matches('_'))
+}
+
+/// Check a time of day is in the HH:MM form schedules use.
+pub fn valid_time(at: &str) -> bool {
+    let mut parts = at.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(h), Some(m)) if h.len() == 2 && m.len() == 2 => {
+            match (h.parse::<u8>(), m.parse::<u8>()) {
+                (Ok(h), Ok(m)) => h < 24 && m < 60,
+                _ => false,
+            }
+        }
+        _ => false,
+    }
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Quote an argument the way the Microsoft C runtime will split it back out.
+fn quote_arg(arg: &str) -> String {
+    if !arg.is_empty() && !arg.contains(|c: char| c == ' ' || c == '\t' || c == '"') {
+        return arg.to_string();
+    }
+
+    // Backslashes are only special when they come before a quote.
+    let mut quoted = String::from("\"");
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        match c {
+            '\\' => {
+                backslashes += 1;
+                continue;
+            }
+            '"' => quoted.push_str(&"\\".repeat(backslashes * 2 + 1)),
+            _ => quoted.push_str(&"\\".repeat(backslashes)),
+        }
+        quoted.push(c);
+        backslashes = 0;
+    }
+    quoted.push_str(&"\\".repeat(backslashes * 2));
+    quoted.push('"');
+    quoted
+}
+
+/// The Task Scheduler XML definition of a daily, idle-only task.
+pub fn task_xml(exe: &Path, args: &[String], at: &str) -> String {
+    let args: Vec<String> = args.iter().map(|a| quote_arg(a)).collect();
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>Compress a folder with Compactor</Description>
+  </RegistrationInfo>
+  <Triggers>
+    <CalendarTrigger>
+      <StartBoundary>2020-01-01T{at}:00</StartBoundary>
+      <ScheduleByDay>
+        <DaysInterval>1</DaysInterval>
+      </ScheduleByDay>
+    </CalendarTrigger>
+  </Triggers>
+  <Principals>
+    <Principal id="Author">
+      <LogonType>InteractiveToken</LogonType>
+      <RunLevel>LeastPrivilege</RunLevel>
+    </Principal>
+  </Principals>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <DisallowStartIfOnBatteries>true</DisallowStartIfOnBatteries>
+    <StopIfGoingOnBatteries>true</StopIfGoingOnBatteries>
+    <StartWhenAvailable>true</StartWhenAvailable>
+    <RunOnlyIfIdle>true</RunOnlyIfIdle>
+    <IdleSettings>
+      <StopOnIdleEnd>true</StopOnIdleEnd>
+      <RestartOnIdle>true</RestartOnIdle>
+    </IdleSettings>
+    <ExecutionTimeLimit>PT0S</ExecutionTimeLimit>
+    <Priority>{priority}</Priority>
+  </Settings>
+  <Actions Context="Author">
+    <Exec>
+      <Command>{command}</Command>
+      <Arguments>{arguments}</Arguments>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        at = at,
+        priority = TASK_PRIORITY,
+        command = escape_xml(&exe.display().to_string()),
+        arguments = escape_xml(&args.join(" "))
+    )
+}
+
+fn schtasks(args: &[&str]) -> io::Result<()> {
+    let output = Command::new("schtasks.exe").args(args).output()?;
+
+    if output.status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ))
+    }
+}
+
+/// Register a daily task running Compactor with the given arguments, replacing
+/// any existing one for the folder.
+pub fn schedule(folder: &Path, args: &[String], at: &str) -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let xml = task_xml(&exe, args, at);
+    let file = std::env::temp_dir().join(format!("compactor-task-{}.xml", std::process::id()));
+
+    // schtasks wants the UTF-16 the XML declares, with a byte order mark.
+    let bytes: Vec<u8> = std::iter::once(0xfeff)
+        .chain(xml.encode_utf16())
+        .flat_map(|u| u.to_le_bytes().to_vec())
+        .collect();
+    std::fs::write(&file, bytes)?;
+
+    let name = task_name(folder);
+    let ret = schtasks(&[
+        "/Create",
+        "/TN",
+        &name,
+        "/XML",
+        &file.to_string_lossy(),
+        "/F",
+    ]);
+    let _ = std::fs::remove_file(&file);
+    ret
+}
+
+/// Remove the scheduled task for a folder.
+pub fn unschedule(folder: &Path) -> io::Result<()> {
+    schtasks(&["/Delete", "/TN", &task_name(folder), "/F"])
+}
+
+#[test]
+fn it_names_tasks() {
+    assert_eq!(
+        task_name(Path::new("C:\\Users\\Me\\Downloads")),
+        "Compactor\\C__Users_Me_Downloads"
+    );
+}
+
+#[test]
+fn it_validates_times() {
+    assert!(valid_time("03:00"));
+    assert!(valid_time("23:59"));
+    assert!(!valid_time("24:00"));
+    assert!(!valid_time("3:00"));
+    assert!(!valid_time("03-00"));
+    assert!(!valid_time("noon"));
+}
+
+#[test]
+fn it_quotes_arguments() {
+    assert_eq!(quote_arg("--force"), "--force");
+    assert_eq!(quote_arg(""), "\"\"");
+    assert_eq!(quote_arg("C:\\My Games\\"), "\"C:\\My Games\\\\\"");
+    assert_eq!(quote_arg("say \"hi\""), "\"say \\\"hi\\\"\"");
+}
+
+#[test]
+fn it_writes_task_xml() {
+    let xml = task_xml(
+        Path::new("C:\\Program Files\\Compactor.exe"),
+        &["--excludes".to_string(), "*.a;<b>".to_string()],
+        "02:30",
+    );
+
+    assert!(xml.contains("<StartBoundary>2020-01-01T02:30:00</StartBoundary>"));
+    assert!(xml.contains("<Command>C:\\Program Files\\Compactor.exe</Command>"));
+    assert!(xml.contains("<Arguments>--excludes *.a;&lt;b&gt;</Arguments>"));
+    assert!(xml.contains("<RunOnlyIfIdle>true</RunOnlyIfIdle>"));
+    assert!(xml.contains("<Priority>10</Priority>"));
+}
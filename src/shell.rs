@@ -0,0 +1,122 @@
+// Explorer integration, adding "Compress with Compactor" to the context menu
+// for folders.
+//
+// The keys are written under HKEY_CURRENT_USER\Software\Classes, which Windows
+// merges into HKEY_CLASSES_ROOT for the current user, so no elevation is
+// needed to register or unregister.
+
+use std::ffi::OsStr;
+use std::io;
+use std::os::windows::ffi::OsStrExt;
+use std::path::Path;
+use std::process::Command;
+
+use winapi::shared::minwindef::{DWORD, HKEY};
+use winapi::shared::winerror::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+use winapi::um::winnt::{KEY_WRITE, REG_OPTION_NON_VOLATILE, REG_SZ};
+use winapi::um::winreg::{
+    RegCloseKey, RegCreateKeyExW, RegDeleteTreeW, RegSetValueExW, HKEY_CURRENT_USER,
+};
+
+use crate::persistence;
+
+const MENU_KEY: &str = r"Software\Classes\Directory\shell\Compactor";
+const MENU_TEXT: &str = "Compress with Compactor";
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+fn check(status: i32) -> io::Result<()> {
+    if status == ERROR_SUCCESS as i32 {
+        Ok(())
+    } else {
+        Err(io::Error::from_raw_os_error(status))
+    }
+}
+
+/// Set a string value on a key under HKEY_CURRENT_USER, creating it if needed.
+///
+/// A `name` of `None` sets the key's default value.
+fn set_value(key: &str, name: Option<&str>, value: &str) -> io::Result<()> {
+    let key = wide(key);
+    let name = name.map(wide);
+    let value = wide(value);
+    let mut hkey: HKEY = std::ptr::null_mut();
+
+    unsafe {
+        check(RegCreateKeyExW(
+            HKEY_CURRENT_USER,
+            key.as_ptr(),
+            0,
+            std::ptr::null_mut(),
+            REG_OPTION_NON_VOLATILE,
+            KEY_WRITE,
+            std::ptr::null_mut(),
+            &mut hkey,
+            std::ptr::null_mut(),
+        ))?;
+
+        let ret = check(RegSetValueExW(
+            hkey,
+            name.as_ref().map_or(std::ptr::null(), |n| n.as_ptr()),
+            0,
+            REG_SZ,
+            value.as_ptr() as *const u8,
+            (value.len() * std::mem::size_of::<u16>()) as DWORD,
+        ));
+
+        RegCloseKey(hkey);
+        ret
+    }
+}
+
+/// Add the folder context menu entry, launching the GUI on the chosen folder.
+pub fn register() -> io::Result<()> {
+    let exe = std::env::current_exe()?;
+    let exe = exe.display();
+    let portable = if persistence::portable_dir().is_some() {
+        " --portable"
+    } else {
+        ""
+    };
+
+    set_value(MENU_KEY, None, MENU_TEXT)?;
+    set_value(MENU_KEY, Some("Icon"), &format!("\"{}\"", exe))?;
+    set_value(
+        &format!(r"{}\command", MENU_KEY),
+        None,
+        &format!("\"{}\"{} --gui \"%1\"", exe, portable),
+    )
+}
+
+/// Remove the folder context menu entry, if it exists.
+pub fn unregister() -> io::Result<()> {
+    let key = wide(MENU_KEY);
+
+    match unsafe { RegDeleteTreeW(HKEY_CURRENT_USER, key.as_ptr()) } {
+        status if status == ERROR_FILE_NOT_FOUND as i32 => Ok(()),
+        status => check(status),
+    }
+}
+
+/// Open an Explorer window on the folder containing a file, with the file
+/// selected.
+///
+/// Explorer opens a default window rather than failing if the path doesn't
+/// exist, so check first.
+pub fn reveal(path: &Path) -> io::Result<()> {
+    if !path.is_absolute() || !path.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} no longer exists", path.display()),
+        ));
+    }
+
+    // Explorer's exit status is meaningless, so only failure to launch counts.
+    Command::new("explorer.exe")
+        .arg("/select,")
+        .arg(path)
+        .spawn()
+        .map(|_| ())
+}
@@ -0,0 +1,137 @@
+// Folder-wide checks that compression leaves what files read as alone.
+//
+// Before a run each file about to be compressed has its logical size and a
+// quick checksum of its start and end recorded, and once the run's over
+// they're all checked again.  WOF should never change a file's contents, so
+// any difference is a critical error.
+//
+// This is coarser than `verify`, which hashes whole files as they're
+// compressed, but it's checked after everything's done, however each file was
+// handled.
+
+use std::hash::Hasher;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use siphasher::sip128::{Hasher128, SipHasher};
+
+use crate::compact;
+
+/// Bytes checksummed from each end of a file.
+const SAMPLE_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Fingerprint {
+    pub len: u64,
+    pub checksum: u128,
+}
+
+/// The logical size of a file, and a checksum of its first and last blocks.
+pub fn fingerprint<P: AsRef<Path>>(path: P) -> io::Result<Fingerprint> {
+    let mut file = std::fs::File::open(compact::long_path(path))?;
+    let len = file.metadata()?.len();
+    let mut hash = SipHasher::new();
+    let mut buf = Vec::with_capacity(SAMPLE_BYTES as usize);
+
+    hash.write_u64(len);
+    (&mut file).take(SAMPLE_BYTES).read_to_end(&mut buf)?;
+    hash.write(&buf);
+
+    if len > SAMPLE_BYTES {
+        buf.clear();
+        file.seek(SeekFrom::Start(SAMPLE_BYTES.max(len - SAMPLE_BYTES)))?;
+        file.take(SAMPLE_BYTES).read_to_end(&mut buf)?;
+        hash.write(&buf);
+    }
+
+    let h = hash.finish128();
+    Ok(Fingerprint {
+        len,
+        checksum: (u128::from(h.h1) << 64) | u128::from(h.h2),
+    })
+}
+
+#[derive(Debug, Default)]
+pub struct Snapshot {
+    /// Relative to the folder the snapshot was taken of.
+    files: Vec<(PathBuf, Fingerprint)>,
+}
+
+impl Snapshot {
+    /// Fingerprint a file by its path relative to `base`.
+    pub fn record(&mut self, base: &Path, path: &Path) -> io::Result<()> {
+        let fp = fingerprint(base.join(path))?;
+        self.files.push((path.to_path_buf(), fp));
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+
+    /// Check every recorded file again, returning those which read differently.
+    ///
+    /// Files which have gone, or can no longer be read, can't be checked, so
+    /// are only logged.
+    pub fn changed(&self, base: &Path) -> Vec<(PathBuf, io::Error)> {
+        let mut changed = vec![];
+
+        for (path, before) in &self.files {
+            match fingerprint(base.join(path)) {
+                Ok(after) if after == *before => (),
+                Ok(after) => {
+                    let msg = if after.len != before.len {
+                        format!(
+                            "Contents changed after compression: {} bytes before, {} after",
+                            before.len, after.len
+                        )
+                    } else {
+                        "Contents changed after compression".to_string()
+                    };
+                    changed.push((
+                        path.clone(),
+                        io::Error::new(io::ErrorKind::InvalidData, msg),
+                    ));
+                }
+                Err(e) => {
+                    log::warn!("Unable to check {}: {}", path.display(), e);
+                }
+            }
+        }
+
+        changed
+    }
+}
+
+#[test]
+fn it_notices_changed_contents() {
+    let dir = tempdir::TempDir::new("compactor-test").unwrap();
+    let big = "hello world ".repeat(65536);
+    std::fs::write(dir.path().join("big.txt"), &big).unwrap();
+    std::fs::write(dir.path().join("small.txt"), "hello").unwrap();
+    std::fs::write(dir.path().join("gone.txt"), "hello").unwrap();
+
+    let mut snapshot = Snapshot::default();
+    for name in &["big.txt", "small.txt", "gone.txt"] {
+        snapshot.record(dir.path(), Path::new(name)).unwrap();
+    }
+    assert_eq!(snapshot.len(), 3);
+    assert!(snapshot.changed(dir.path()).is_empty());
+
+    // The last block changes, but not the size.
+    let mut altered = big.into_bytes();
+    *altered.last_mut().unwrap() = b'!';
+    std::fs::write(dir.path().join("big.txt"), &altered).unwrap();
+    std::fs::write(dir.path().join("small.txt"), "hello!").unwrap();
+    std::fs::remove_file(dir.path().join("gone.txt")).unwrap();
+
+    let changed: Vec<PathBuf> = snapshot
+        .changed(dir.path())
+        .into_iter()
+        .map(|(path, _)| path)
+        .collect();
+    assert_eq!(
+        changed,
+        vec![PathBuf::from("big.txt"), PathBuf::from("small.txt")]
+    );
+}
@@ -0,0 +1,144 @@
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::jsonfile::JsonFile;
+use crate::wof::Compression;
+
+/// How many recently chosen folders to remember.
+pub const RECENT_FOLDERS: usize = 10;
+
+/// Things remembered between runs which aren't user settings, and so survive
+/// resetting the config.
+pub type StateFile = JsonFile<State>;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    pub last_folder: Option<PathBuf>,
+    pub recent_folders: Vec<PathBuf>,
+    pub window: Option<WindowGeometry>,
+    pub stats: Stats,
+    /// When each folder last finished compressing, for incremental runs.
+    pub last_compressed: HashMap<PathBuf, SystemTime>,
+    /// What the most recent compression run did, so it can be undone.
+    pub last_run: Option<LastRun>,
+    /// Every folder Compactor has compressed anything in, so it can all be
+    /// put back before uninstalling.
+    pub compressed_folders: BTreeSet<PathBuf>,
+}
+
+/// The files compressed by a run, and how.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LastRun {
+    pub folder: PathBuf,
+    /// `None` when automatic compression chose per file.
+    pub compression: Option<Compression>,
+    pub finished: SystemTime,
+    /// Full paths of every file the run compressed.
+    ///
+    /// There can be hundreds of thousands, so they're saved in a file of their
+    /// own rather than the state.  Older states listed them here.
+    #[serde(default, skip_serializing)]
+    pub files: Vec<PathBuf>,
+    /// Where `files` are saved, once they have been.
+    #[serde(default)]
+    pub file_list: Option<PathBuf>,
+}
+
+/// Running totals across every compression run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Stats {
+    pub bytes_saved: u64,
+    pub files: u64,
+}
+
+/// The outer position and size of the GUI window, in screen coordinates.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct WindowGeometry {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl State {
+    /// Make a folder the last chosen, moving it to the top of the recent list.
+    pub fn remember_folder<P: AsRef<Path>>(&mut self, path: P) {
+        let path = path.as_ref().to_owned();
+        self.recent_folders.retain(|p| p != &path);
+        self.recent_folders.insert(0, path.clone());
+        self.recent_folders.truncate(RECENT_FOLDERS);
+        self.last_folder = Some(path);
+    }
+}
+
+#[test]
+fn it_remembers_recent_folders() {
+    let mut state = State::default();
+
+    for i in 0..RECENT_FOLDERS + 2 {
+        state.remember_folder(format!("C:\\Games\\{}", i));
+    }
+    state.remember_folder("C:\\Games\\5");
+
+    assert_eq!(state.last_folder, Some(PathBuf::from("C:\\Games\\5")));
+    assert_eq!(state.recent_folders.len(), RECENT_FOLDERS);
+    assert_eq!(state.recent_folders[0], PathBuf::from("C:\\Games\\5"));
+    assert_eq!(state.recent_folders[1], PathBuf::from("C:\\Games\\11"));
+    assert_eq!(
+        state
+            .recent_folders
+            .iter()
+            .filter(|p| p.ends_with("5"))
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn it_remembers_compressed_folders() {
+    let mut state = State::default();
+    state.compressed_folders.insert(PathBuf::from("C:\\Games"));
+    state.compressed_folders.insert(PathBuf::from("C:\\Games"));
+    state.compressed_folders.insert(PathBuf::from("C:\\Apps"));
+
+    let data = serde_json::to_string(&state).unwrap();
+    let state: State = serde_json::from_str(&data).unwrap();
+    assert_eq!(
+        state.compressed_folders.into_iter().collect::<Vec<_>>(),
+        vec![PathBuf::from("C:\\Apps"), PathBuf::from("C:\\Games")]
+    );
+
+    // State saved before folders were tracked still loads.
+    let state: State = serde_json::from_str("{}").unwrap();
+    assert!(state.compressed_folders.is_empty());
+}
+
+#[test]
+fn it_keeps_run_files_out_of_the_state() {
+    let run = LastRun {
+        folder: PathBuf::from("C:\\Games"),
+        compression: Some(Compression::Lzx),
+        finished: SystemTime::UNIX_EPOCH,
+        files: vec![PathBuf::from("C:\\Games\\game.exe")],
+        file_list: Some(PathBuf::from("last_run.json")),
+    };
+
+    let data = serde_json::to_string(&run).unwrap();
+    assert!(!data.contains("game.exe"));
+    let saved: LastRun = serde_json::from_str(&data).unwrap();
+    assert!(saved.files.is_empty());
+    assert_eq!(saved.file_list, run.file_list);
+
+    // Runs saved with their files listed inline still load them.
+    let old = r#"{"folder":"C:\\Games","compression":"Lzx",
+        "finished":{"secs_since_epoch":0,"nanos_since_epoch":0},
+        "files":["C:\\Games\\game.exe"]}"#;
+    let saved: LastRun = serde_json::from_str(old).unwrap();
+    assert_eq!(saved.files, run.files);
+    assert!(saved.file_list.is_none());
+}
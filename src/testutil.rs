@@ -0,0 +1,36 @@
+// Fixtures shared between tests in different modules.
+
+use std::path::{Path, PathBuf};
+
+use globset::GlobSet;
+
+use crate::background::BackgroundHandle;
+use crate::folder::{FileInfo, FolderInfo, FolderScan};
+use crate::wof::Compression;
+
+/// Text which compresses well, and is big enough for WOF to bother with.
+pub fn compressible_text() -> String {
+    "hello world ".repeat(16384)
+}
+
+/// An uncompressed file of the given size, as if found by a scan.
+pub fn file(path: &str, size: u64) -> FileInfo {
+    FileInfo {
+        path: PathBuf::from(path),
+        logical_size: size,
+        physical_size: size,
+        compression: None,
+        modified: None,
+    }
+}
+
+/// Scan a folder with no excludes and the default algorithm, after `options`
+/// has set anything else.
+pub fn scan<F: FnOnce(FolderScan) -> FolderScan>(dir: &Path, options: F) -> FolderInfo {
+    let scanner = options(FolderScan::new(
+        dir,
+        GlobSet::empty(),
+        Compression::default(),
+    ));
+    BackgroundHandle::spawn(scanner).wait().unwrap()
+}
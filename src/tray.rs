@@ -0,0 +1,249 @@
+// A notification area icon, so the window can be minimized out of the way
+// during long-running jobs.
+//
+// The icon belongs to a hidden window created on the GUI thread, so its
+// messages are pumped by web-view's own event loop.  Menu commands are sent to
+// the backend over the same channel as requests from the WebView.
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+
+use crossbeam_channel::Sender;
+use winapi::shared::minwindef::{LPARAM, LRESULT, UINT, WPARAM};
+use winapi::shared::windef::{HWND, POINT};
+use winapi::um::libloaderapi::GetModuleHandleW;
+use winapi::um::shellapi::{
+    Shell_NotifyIconW, NIF_ICON, NIF_INFO, NIF_MESSAGE, NIF_TIP, NIIF_INFO, NIM_ADD, NIM_DELETE,
+    NIM_MODIFY, NOTIFYICONDATAW,
+};
+use winapi::um::winuser::{
+    AppendMenuW, CreatePopupMenu, CreateWindowExW, DefWindowProcW, DestroyMenu, DestroyWindow,
+    GetCursorPos, GetWindowLongPtrW, LoadIconW, RegisterClassW, SetForegroundWindow,
+    SetWindowLongPtrW, TrackPopupMenu, GWLP_USERDATA, IDI_APPLICATION, MAKEINTRESOURCEW,
+    MF_SEPARATOR, MF_STRING, TPM_NONOTIFY, TPM_RETURNCMD, TPM_RIGHTBUTTON, WM_APP,
+    WM_LBUTTONDBLCLK, WM_LBUTTONUP, WM_RBUTTONUP, WNDCLASSW,
+};
+
+use crate::gui::GuiRequest;
+use crate::window::Window;
+
+const CLASS_NAME: &str = "CompactorTray";
+const TRAY_ID: UINT = 1;
+const WM_TRAY: UINT = WM_APP + 1;
+
+const MENU_SHOW: usize = 1;
+const MENU_PAUSE: usize = 2;
+const MENU_RESUME: usize = 3;
+const MENU_STOP: usize = 4;
+const MENU_QUIT: usize = 5;
+
+struct TrayState {
+    window: Window,
+    gui: Sender<GuiRequest>,
+}
+
+pub struct Tray {
+    hwnd: HWND,
+    state: *mut TrayState,
+}
+
+/// A handle for updating the tray tooltip, which may be used from any thread.
+#[derive(Clone, Copy)]
+pub struct TrayHandle(usize);
+
+fn wide(s: &str) -> Vec<u16> {
+    OsStr::new(s).encode_wide().chain(Some(0)).collect()
+}
+
+fn notify_data(hwnd: HWND) -> NOTIFYICONDATAW {
+    let mut data: NOTIFYICONDATAW = unsafe { std::mem::zeroed() };
+    data.cbSize = std::mem::size_of::<NOTIFYICONDATAW>() as u32;
+    data.hWnd = hwnd;
+    data.uID = TRAY_ID;
+    data
+}
+
+/// Copy a string into a fixed-size buffer, truncating to fit.
+fn copy_wide(buf: &mut [u16], s: &str) {
+    let s: Vec<u16> = OsStr::new(s).encode_wide().collect();
+    let len = s.len().min(buf.len() - 1);
+    buf[..len].copy_from_slice(&s[..len]);
+    buf[len] = 0;
+}
+
+fn set_tip(data: &mut NOTIFYICONDATAW, tip: &str) {
+    copy_wide(&mut data.szTip, tip);
+}
+
+impl Tray {
+    /// Add a tray icon for the given window, sending menu commands to `gui`.
+    pub fn new(window: Window, gui: Sender<GuiRequest>, tip: &str) -> Option<Self> {
+        let class_name = wide(CLASS_NAME);
+
+        unsafe {
+            let instance = GetModuleHandleW(std::ptr::null());
+
+            let mut class: WNDCLASSW = std::mem::zeroed();
+            class.lpfnWndProc = Some(tray_proc);
+            class.hInstance = instance;
+            class.lpszClassName = class_name.as_ptr();
+            RegisterClassW(&class);
+
+            let hwnd = CreateWindowExW(
+                0,
+                class_name.as_ptr(),
+                class_name.as_ptr(),
+                0,
+                0,
+                0,
+                0,
+                0,
+                std::ptr::null_mut(),
+                std::ptr::null_mut(),
+                instance,
+                std::ptr::null_mut(),
+            );
+
+            if hwnd.is_null() {
+                return None;
+            }
+
+            let state = Box::into_raw(Box::new(TrayState { window, gui }));
+            SetWindowLongPtrW(hwnd, GWLP_USERDATA, state as isize);
+
+            // The application icon is embedded by winres as resource 1.
+            let mut icon = LoadIconW(instance, MAKEINTRESOURCEW(1));
+            if icon.is_null() {
+                icon = LoadIconW(std::ptr::null_mut(), IDI_APPLICATION);
+            }
+
+            let mut data = notify_data(hwnd);
+            data.uFlags = NIF_ICON | NIF_MESSAGE | NIF_TIP;
+            data.uCallbackMessage = WM_TRAY;
+            data.hIcon = icon;
+            set_tip(&mut data, tip);
+
+            if Shell_NotifyIconW(NIM_ADD, &mut data) == 0 {
+                DestroyWindow(hwnd);
+                drop(Box::from_raw(state));
+                return None;
+            }
+
+            Some(Self { hwnd, state })
+        }
+    }
+
+    pub fn handle(&self) -> TrayHandle {
+        TrayHandle(self.hwnd as usize)
+    }
+}
+
+impl Drop for Tray {
+    fn drop(&mut self) {
+        unsafe {
+            let mut data = notify_data(self.hwnd);
+            Shell_NotifyIconW(NIM_DELETE, &mut data);
+            SetWindowLongPtrW(self.hwnd, GWLP_USERDATA, 0);
+            DestroyWindow(self.hwnd);
+            drop(Box::from_raw(self.state));
+        }
+    }
+}
+
+impl TrayHandle {
+    pub fn set_tooltip(&self, tip: &str) {
+        let mut data = notify_data(self.0 as HWND);
+        data.uFlags = NIF_TIP;
+        set_tip(&mut data, tip);
+
+        unsafe { Shell_NotifyIconW(NIM_MODIFY, &mut data) };
+    }
+
+    /// Show a balloon notification from the icon, returning `false` if it
+    /// couldn't be shown.
+    pub fn notify(&self, title: &str, message: &str) -> bool {
+        let mut data = notify_data(self.0 as HWND);
+        data.uFlags = NIF_INFO;
+        data.dwInfoFlags = NIIF_INFO;
+        copy_wide(&mut data.szInfoTitle, title);
+        copy_wide(&mut data.szInfo, message);
+
+        unsafe { Shell_NotifyIconW(NIM_MODIFY, &mut data) != 0 }
+    }
+}
+
+impl TrayState {
+    fn menu(&self, hwnd: HWND) {
+        let items = [
+            (MENU_SHOW, "Show Compactor"),
+            (MENU_PAUSE, "Pause"),
+            (MENU_RESUME, "Resume"),
+            (MENU_STOP, "Stop"),
+        ];
+
+        let cmd = unsafe {
+            let menu = CreatePopupMenu();
+            for (id, label) in items.iter() {
+                AppendMenuW(menu, MF_STRING, *id, wide(label).as_ptr());
+            }
+            AppendMenuW(menu, MF_SEPARATOR, 0, std::ptr::null());
+            AppendMenuW(menu, MF_STRING, MENU_QUIT, wide("Quit").as_ptr());
+
+            // Without this the menu won't close when clicking elsewhere.
+            SetForegroundWindow(hwnd);
+
+            let mut pt: POINT = std::mem::zeroed();
+            GetCursorPos(&mut pt);
+            let cmd = TrackPopupMenu(
+                menu,
+                TPM_RETURNCMD | TPM_NONOTIFY | TPM_RIGHTBUTTON,
+                pt.x,
+                pt.y,
+                0,
+                hwnd,
+                std::ptr::null(),
+            );
+            DestroyMenu(menu);
+            cmd as usize
+        };
+
+        let msg = match cmd {
+            MENU_SHOW => {
+                self.window.show();
+                None
+            }
+            MENU_PAUSE => Some(GuiRequest::Pause),
+            MENU_RESUME => Some(GuiRequest::Resume),
+            MENU_STOP => Some(GuiRequest::Stop),
+            MENU_QUIT => {
+                self.window.close();
+                Some(GuiRequest::Quit)
+            }
+            _ => None,
+        };
+
+        if let Some(msg) = msg {
+            let _ = self.gui.send(msg);
+        }
+    }
+}
+
+unsafe extern "system" fn tray_proc(
+    hwnd: HWND,
+    msg: UINT,
+    wparam: WPARAM,
+    lparam: LPARAM,
+) -> LRESULT {
+    let state = GetWindowLongPtrW(hwnd, GWLP_USERDATA) as *const TrayState;
+
+    if msg == WM_TRAY && !state.is_null() {
+        match lparam as UINT {
+            WM_LBUTTONUP | WM_LBUTTONDBLCLK => (*state).window.show(),
+            WM_RBUTTONUP => (*state).menu(hwnd),
+            _ => (),
+        }
+        return 0;
+    }
+
+    DefWindowProcW(hwnd, msg, wparam, lparam)
+}
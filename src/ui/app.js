@@ -124,6 +124,23 @@ var Util = (function() {
 			return num;
 		},
 
+		format_duration: function(secs) {
+			var h = Math.floor(secs / 3600);
+			var m = Math.floor((secs % 3600) / 60);
+			var s = secs % 60;
+
+			if (h > 0) return h + "h " + m + "m";
+			if (m > 0) return m + "m " + s + "s";
+			return s + "s";
+		},
+
+		// Parse a size in KiB from a form field, or null if it's empty.
+		kib_to_bytes: function(val) {
+			var kib = parseFloat(val);
+			if (isNaN(kib) || kib < 0) return null;
+			return Math.round(kib * 1024);
+		},
+
 		sformat: function() {
 			var args = arguments;
 			return args[0].replace(/\{(\d+)\}/g, function (m, n) { return args[parseInt(n, 10) + 1]; });
@@ -136,6 +153,21 @@ var Util = (function() {
 				arr.push(i);
 			}
 			return arr;
+		},
+
+		// The Windows path for a file: URL.  Anything without a drive letter is
+		// on a server, so file://server/share/... is \\server\share\...
+		file_url_to_path: function(url) {
+			var path = decodeURIComponent(url.replace(/^file:/i, ""))
+				.replace(/^\/\/localhost(?=\/)/i, "");
+
+			if (/^\/*[A-Za-z]:/.test(path)) {
+				path = path.replace(/^\/+/, "");
+			} else {
+				path = "\\\\" + path.replace(/^\/+/, "");
+			}
+
+			return path.replace(/\//g, "\\");
 		}
 	};
 })();
@@ -151,25 +183,79 @@ var Action = (function() {
 			external.invoke(JSON.stringify({ type: 'OpenUrl', url: url }));
 		},
 
+		dropped_path: function(path) {
+			external.invoke(JSON.stringify({ type: 'DroppedPath', path: path }));
+		},
+
+		reveal_file: function(path) {
+			external.invoke(JSON.stringify({ type: 'RevealFile', path: path }));
+		},
+
+		open_log_folder: function() {
+			external.invoke(JSON.stringify({ type: 'OpenLogFolder' }));
+		},
+
+		validate_excludes: function(excludes) {
+			external.invoke(JSON.stringify({ type: 'ValidateExcludes', excludes: excludes }));
+		},
+
+		apply_preset: function(preset) {
+			external.invoke(JSON.stringify({ type: 'ApplyPreset', preset: preset }));
+		},
+
+		save_preset: function(name) {
+			external.invoke(JSON.stringify({ type: 'SavePreset', name: name }));
+		},
+
+		load_preset: function(name) {
+			external.invoke(JSON.stringify({ type: 'LoadPreset', name: name }));
+		},
+
 		reset_config: function() {
 			external.invoke(JSON.stringify({ type: 'ResetConfig' }));
 		},
 
+		reset_stats: function() {
+			external.invoke(JSON.stringify({ type: 'ResetStats' }));
+		},
+
 		save_config: function(config) {
 			config.type = 'SaveConfig';
 			external.invoke(JSON.stringify(config));
 		},
 
+		save_profile: function(path, config) {
+			config.type = 'SaveProfile';
+			config.path = path;
+			external.invoke(JSON.stringify(config));
+		},
+
 		choose_folder: function() {
+			Gui.choosing_folder();
 			external.invoke(JSON.stringify({ type: 'ChooseFolder' }));
 		},
 
-		compress: function() {
-			external.invoke(JSON.stringify({ type: 'Compress' }));
+		choose_file: function() {
+			Gui.choosing_folder();
+			external.invoke(JSON.stringify({ type: 'ChooseFile' }));
 		},
 
-		decompress: function() {
-			external.invoke(JSON.stringify({ type: 'Decompress' }));
+		select_recent: function(index) {
+			if (index !== "") {
+				external.invoke(JSON.stringify({ type: 'SelectRecent', index: parseInt(index, 10) }));
+			}
+		},
+
+		choose_queue_folder: function() {
+			external.invoke(JSON.stringify({ type: 'ChooseQueueFolder' }));
+		},
+
+		compress: function(force, incremental) {
+			external.invoke(JSON.stringify({ type: 'Compress', force: force, incremental: incremental }));
+		},
+
+		decompress: function(only) {
+			external.invoke(JSON.stringify({ type: 'Decompress', only: only || null }));
 		},
 
 		pause: function() {
@@ -188,6 +274,62 @@ var Action = (function() {
 			external.invoke(JSON.stringify({ type: 'Stop' }));
 		},
 
+		skip_file: function() {
+			external.invoke(JSON.stringify({ type: 'SkipFile' }));
+		},
+
+		estimate: function(compression) {
+			external.invoke(JSON.stringify({ type: 'EstimateCompression', compression: compression }));
+		},
+
+		benchmark: function() {
+			external.invoke(JSON.stringify({ type: 'Benchmark' }));
+		},
+
+		import_excludes: function() {
+			external.invoke(JSON.stringify({ type: 'ChooseExcludesFile' }));
+		},
+
+		preview_excludes: function() {
+			external.invoke(JSON.stringify({ type: 'PreviewExcludes' }));
+		},
+
+		test_config: function() {
+			external.invoke(JSON.stringify({ type: 'TestConfig' }));
+		},
+
+		undo_last: function() {
+			external.invoke(JSON.stringify({ type: 'UndoLast' }));
+		},
+
+		decompress_from_manifest: function(path) {
+			external.invoke(JSON.stringify({ type: 'DecompressFromManifest', path: path }));
+		},
+
+		decompress_all_tracked: function() {
+			external.invoke(JSON.stringify({ type: 'DecompressAllTracked' }));
+		},
+
+		set_excluded_subfolders: function(paths) {
+			external.invoke(JSON.stringify({ type: 'SetExcludedSubfolders', paths: paths }));
+		},
+
+		copy_summary: function() {
+			external.invoke(JSON.stringify({ type: 'CopySummary' }));
+		},
+
+		export_report: function() {
+			external.invoke(JSON.stringify({ type: 'ChooseReportFile' }));
+		},
+
+		export_csv: function() {
+			external.invoke(JSON.stringify({ type: 'ChooseCsvFile' }));
+		},
+
+		export_treemap: function() {
+			external.invoke(JSON.stringify({ type: 'ChooseTreemapFile' }));
+		},
+
 		quit: function() {
 			external.invoke(JSON.stringify({ type: 'Quit' }));
 		}
@@ -205,31 +347,158 @@ var Response = (function() {
 					Gui.set_decimal(msg.decimal);
 					Gui.set_compression(msg.compression);
 					Gui.set_excludes(msg.excludes);
+					Gui.set_exclude_extensions(msg.exclude_extensions);
+					Gui.set_include_only(msg.include_only);
+					Gui.set_case_insensitive(msg.case_insensitive);
+					Gui.set_skip_incompressible(msg.skip_incompressible);
+					Gui.set_skip_attributes(msg.skip_hidden, msg.skip_system);
+					Gui.set_follow_links(msg.follow_links);
+					Gui.set_size_range(msg.min_size, msg.max_size);
+					Gui.set_min_age(msg.min_age_days);
+					Gui.set_max_depth(msg.max_depth);
+					Gui.set_threads(msg.threads);
+					Gui.set_verify(msg.verify);
+					Gui.set_verify_folder(msg.verify_folder);
+					Gui.set_allow_protected(msg.allow_protected);
+					Gui.set_backup_fallback(msg.backup_fallback);
+					Gui.set_clear_readonly(msg.clear_readonly);
+					Gui.set_compress_sparse(msg.compress_sparse);
+					Gui.set_revert_if_larger(msg.revert_if_larger);
+					Gui.set_auto_analyse(msg.auto_analyse);
+					Gui.set_notify_on_complete(msg.notify_on_complete);
+					Gui.set_pause_on_battery(msg.pause_on_battery);
+					Gui.set_confirm_before_compress(msg.confirm_before_compress);
+					Gui.set_write_manifest(msg.write_manifest);
+					Gui.set_read_only_mode(msg.read_only_mode);
+					Gui.set_priority(msg.priority, msg.throughput_limit);
+					Gui.set_max_runtime(msg.max_runtime_secs);
+					Gui.set_min_savings(msg.min_savings_pct);
+					Gui.set_log_level(msg.log_level);
+					Gui.set_order(msg.order);
+					Gui.set_theme(msg.theme);
+					Gui.set_preset(msg.preset);
 					break;
 
 				case "Folder":
 					Gui.set_folder(msg.path);
 					break;
 
+				case "FolderCancelled":
+					Gui.folder_chosen();
+					break;
+
+				case "Folders":
+					Gui.folder_chosen();
+					Gui.toast("Queued " + msg.paths.length + " folders: " + msg.paths.join(", "));
+					break;
+
 				case "Version":
 					Gui.version(msg.date, msg.version);
 					break;
 
 				case "Status":
-					Gui.set_status(msg.status, msg.pct);
+					Gui.set_status(msg.status, msg.pct, msg.eta_secs, msg.throughput_mbps);
 					break;
 
 				case "Paused":
 				case "Resumed":
 				case "Stopped":
 				case "Scanned":
-				case "Compacting":
 					Gui[msg.type.toLowerCase()]();
 					break;
 
+				case "Compacting":
+					Gui.compacting(msg.skippable);
+					break;
+
 				case "FolderSummary":
 					Gui.set_folder_summary(msg.info);
 					break;
+
+				case "CompressionResult":
+					Gui.set_compression_result(msg.before, msg.after, msg.stopped);
+					break;
+
+				case "Estimate":
+					Gui.set_estimate(msg.projected_saved, msg.sample_ratio);
+					break;
+
+				case "SubfolderBreakdown":
+					Gui.set_subfolders(msg.entries);
+					break;
+
+				case "BenchmarkResult":
+					Gui.set_benchmark(msg.results);
+					break;
+
+				case "Phase":
+					Gui.set_phase(msg.phase, msg.current, msg.total);
+					break;
+
+				case "CurrentFile":
+					Gui.set_current_file(msg.path, msg.index, msg.total);
+					break;
+
+				case "EquivalentCommand":
+					Gui.set_equivalent_command(msg.cmd);
+					break;
+
+				case "LifetimeStats":
+					Gui.set_lifetime_stats(msg.bytes_saved, msg.files);
+					break;
+
+				case "RecentFolders":
+					Gui.set_recent_folders(msg.paths);
+					break;
+
+				case "Presets":
+					Gui.set_named_presets(msg.names);
+					break;
+
+				case "QueueUpdated":
+					Gui.set_queue(msg.pending, msg.active);
+					break;
+
+				case "CompressionUnavailable":
+					Gui.compression_unavailable();
+					break;
+
+				case "TrackedFolder":
+					Gui.page("Compress");
+					Gui.toast("Decompressing folder " + msg.index + " of " + msg.total + ": " + msg.path);
+					break;
+
+				case "ExcludePreview":
+					Gui.exclude_preview(msg.matched, msg.unmatched, msg.sample);
+					break;
+
+				case "UndoAvailable":
+					Gui.set_undo_available(msg.folder);
+					break;
+
+				case "Errors":
+					Gui.set_errors(msg.count, msg.sample, msg.paths);
+					break;
+
+				case "ExcludeValidation":
+					Gui.set_exclude_validation(msg.ok, msg.error);
+					break;
+
+				case "DiskInfo":
+					Gui.set_disk_info(msg.free, msg.total);
+					break;
+
+				case "Warning":
+					Gui.toast(msg.message);
+					break;
+
+				case "ExcludesImported":
+					Gui.toast("Imported " + Util.format_number(msg.count, 0) + " new exclude patterns from " + msg.path);
+					break;
+
+				case "ReportExported":
+					Gui.toast("Report saved to " + msg.path);
+					break;
 			}
 		}
 	};
@@ -239,6 +508,62 @@ var Response = (function() {
 var Gui = (function() {
 	"use strict";
 
+	var current_folder = null;
+	var toast_timeout;
+	var validate_timeout;
+	var subfolders = [];
+	var deselected_subfolders = {};
+	var fully_compressed = false;
+	// The settings page has no status line, so the test result is toasted.
+	var testing_config = false;
+	var undo_folder = null;
+	var read_only = false;
+	var subfolder_sort = 1;
+
+	var is_shown = function(id) {
+		return document.getElementById(id).offsetParent !== null;
+	};
+
+	var settings = function() {
+		return {
+			decimal: $("#SI_Units").val() == "D",
+			compression: $("#Compression_Mode").val(),
+			excludes: $("#Excludes").val(),
+			exclude_extensions: $("#Exclude_Extensions").val(),
+			include_only: $("#Include_Only").val(),
+			case_insensitive: $("#Case_Insensitive").is(":checked"),
+			skip_incompressible: $("#Skip_Incompressible").is(":checked"),
+			skip_hidden: $("#Skip_Hidden").is(":checked"),
+			skip_system: $("#Skip_System").is(":checked"),
+			follow_links: $("#Follow_Links").is(":checked"),
+			min_size: Util.kib_to_bytes($("#Min_Size").val()),
+			max_size: Util.kib_to_bytes($("#Max_Size").val()),
+			min_age_days: parseInt($("#Min_Age").val(), 10) || null,
+			max_depth: parseInt($("#Max_Depth").val(), 10) || null,
+			threads: parseInt($("#Threads").val(), 10) || 0,
+			verify: $("#Verify").is(":checked"),
+			verify_folder: $("#Verify_Folder").is(":checked"),
+			allow_protected: $("#Allow_Protected").is(":checked"),
+			backup_fallback: $("#Backup_Fallback").is(":checked"),
+			clear_readonly: $("#Clear_Readonly").is(":checked"),
+			compress_sparse: $("#Compress_Sparse").is(":checked"),
+			revert_if_larger: $("#Revert_If_Larger").is(":checked"),
+			auto_analyse: $("#Auto_Analyse").is(":checked"),
+			notify_on_complete: $("#Notify_On_Complete").is(":checked"),
+			pause_on_battery: $("#Pause_On_Battery").is(":checked"),
+			confirm_before_compress: $("#Confirm_Before_Compress").is(":checked"),
+			write_manifest: $("#Write_Manifest").is(":checked"),
+			read_only_mode: $("#Read_Only_Mode").is(":checked"),
+			priority: $("#Priority").val(),
+			log_level: $("#Log_Level").val(),
+			order: $("#Order").val(),
+			theme: $("#Theme").val(),
+			throughput_limit: parseInt($("#Throughput_Limit").val(), 10) || null,
+			max_runtime_secs: (parseInt($("#Max_Runtime").val(), 10) * 60) || null,
+			min_savings_pct: parseInt($("#Min_Savings").val(), 10) || 0
+		};
+	};
+
 	return {
 		boot: function() {
 			$("a[href]").on("click", function(e) {
@@ -248,16 +573,140 @@ var Gui = (function() {
 			});
 
 			$("#Button_Save").on("click", function() {
-				Action.save_config({
-				  decimal: $("#SI_Units").val() == "D",
-					compression: $("#Compression_Mode").val(),
-					excludes: $("#Excludes").val()
-				});
+				Action.save_config(settings());
+			});
+
+			// Check patterns once typing pauses, rather than on every key.
+			$("#Excludes").on("input", function() {
+				var excludes = $(this).val();
+				clearTimeout(validate_timeout);
+				validate_timeout = setTimeout(function() { Action.validate_excludes(excludes); }, 300);
+			});
+
+			$("#Skip_Incompressible").on("change", function() {
+				$("#Incompressible_Extensions").toggle($(this).is(":checked"));
+			});
+
+			$("#Force_Compress").on("change", function() {
+				if (is_shown("Force_Compress_Label")) {
+					Gui.scanned();
+				}
+			});
+
+			$("#Subfolders th").on("click", function() {
+				Gui.sort_subfolders(parseInt($(this).attr("data-sort"), 10));
+			});
+
+			$("#Errors_Toggle").on("click", function() {
+				$("#Error_List").toggle();
+			});
+
+			$("#Button_Save_Profile").on("click", function() {
+				if (current_folder !== null) {
+					Action.save_profile(current_folder, settings());
+				}
+			});
+
+			$("#Preset_Mode").on("change", function() {
+				if ($(this).val() !== "") {
+					Action.apply_preset($(this).val());
+				}
 			});
 
 			$("#Button_Reset").on("click", function() {
 				Action.reset_config();
 			});
+
+			$("#Button_Rollback").on("click", function() {
+				Action.decompress_from_manifest(current_folder);
+			});
+
+			// Save first, so the preview uses what's on screen.
+			$("#Button_Preview_Excludes").on("click", function() {
+				Action.save_config(settings());
+				Action.preview_excludes();
+			});
+
+			// Save first, so imports add to what's on screen.
+			$("#Button_Import_Excludes").on("click", function() {
+				Action.save_config(settings());
+				Action.import_excludes();
+			});
+
+			// Save first, so the test uses what's on screen.
+			$("#Button_Test_Config").on("click", function() {
+				Action.save_config(settings());
+				Gui.testing_config();
+				Action.test_config();
+			});
+
+			// Save first, so the preset has what's on screen.
+			$("#Button_Save_Preset").on("click", function() {
+				var name = $("#Preset_Name").val().trim();
+				if (name !== "") {
+					Action.save_config(settings());
+					Action.save_preset(name);
+					$("#Preset_Name").val("");
+				}
+			});
+
+			$("#Named_Presets").on("change", function() {
+				if ($(this).val() !== "") {
+					Action.load_preset($(this).val());
+				}
+				$(this).val("");
+			});
+
+			// Dropped files come through as file: URLs; only take them when a
+			// folder could be chosen by hand.
+			$(document).on("dragover", function(e) {
+				e.preventDefault();
+			});
+
+			$(document).on("drop", function(e) {
+				e.preventDefault();
+
+				var data = e.dataTransfer || (e.originalEvent && e.originalEvent.dataTransfer);
+				if (!data || $("#Button_Folder").prop("disabled")) {
+					return;
+				}
+
+				var path = null;
+				if (data.files && data.files.length > 0 && data.files[0].path) {
+					path = data.files[0].path;
+				} else {
+					var url = data.getData("URL") || data.getData("Text");
+					if (url && /^file:/i.test(url)) {
+						path = Util.file_url_to_path(url);
+					}
+				}
+
+				if (path) {
+					Action.dropped_path(path);
+				} else {
+					Gui.toast("Drop a folder or file from this computer to open it");
+				}
+			});
+
+			// Shortcuts only act when their button is on screen, so they can't
+			// start anything the GUI wouldn't otherwise offer.
+			$(document).on("keydown", function(e) {
+				var key = e.key.toLowerCase();
+				var button = null;
+
+				if (e.ctrlKey && key == "o") {
+					button = "Button_Folder";
+				} else if (e.ctrlKey && key == "enter") {
+					button = "Button_Compress";
+				} else if (key == "escape" || key == "esc") {
+					button = "Button_Stop";
+				}
+
+				if (button !== null && is_shown(button)) {
+					e.preventDefault();
+					document.getElementById(button).click();
+				}
+			});
 		},
 
 		page: function(page) {
@@ -289,9 +738,186 @@ var Gui = (function() {
 
 		set_excludes: function(excludes) {
 			$("#Excludes").val(excludes);
+			Gui.set_exclude_validation(true, null);
+		},
+
+		set_exclude_validation: function(ok, error) {
+			$("#Excludes").toggleClass("invalid", !ok);
+			$("#Excludes_Error").text(error || "").toggle(!ok);
+		},
+
+		set_case_insensitive: function(insensitive) {
+			$("#Case_Insensitive").prop("checked", insensitive);
+		},
+
+		set_skip_incompressible: function(enabled) {
+			$("#Skip_Incompressible").prop("checked", enabled);
+			$("#Incompressible_Extensions").toggle(enabled);
+		},
+
+		set_skip_attributes: function(hidden, system) {
+			$("#Skip_Hidden").prop("checked", hidden);
+			$("#Skip_System").prop("checked", system);
+		},
+
+		set_follow_links: function(follow) {
+			$("#Follow_Links").prop("checked", follow);
+		},
+
+		set_exclude_extensions: function(extensions) {
+			$("#Exclude_Extensions").val(extensions);
+		},
+
+		set_include_only: function(extensions) {
+			$("#Include_Only").val(extensions);
+		},
+
+		set_verify: function(verify) {
+			$("#Verify").prop("checked", verify);
+		},
+
+		set_verify_folder: function(enabled) {
+			$("#Verify_Folder").prop("checked", enabled);
+		},
+
+		set_allow_protected: function(allow) {
+			$("#Allow_Protected").prop("checked", allow);
+		},
+
+		set_backup_fallback: function(enabled) {
+			$("#Backup_Fallback").prop("checked", enabled);
+		},
+
+		set_clear_readonly: function(enabled) {
+			$("#Clear_Readonly").prop("checked", enabled);
+		},
+
+		set_compress_sparse: function(enabled) {
+			$("#Compress_Sparse").prop("checked", enabled);
+		},
+
+		set_revert_if_larger: function(enabled) {
+			$("#Revert_If_Larger").prop("checked", enabled);
+		},
+
+		set_auto_analyse: function(auto) {
+			$("#Auto_Analyse").prop("checked", auto);
+		},
+
+		set_notify_on_complete: function(notify) {
+			$("#Notify_On_Complete").prop("checked", notify);
+		},
+
+		set_pause_on_battery: function(enabled) {
+			$("#Pause_On_Battery").prop("checked", enabled);
+		},
+
+		set_confirm_before_compress: function(enabled) {
+			$("#Confirm_Before_Compress").prop("checked", enabled);
+		},
+
+		set_write_manifest: function(enabled) {
+			$("#Write_Manifest").prop("checked", enabled);
+		},
+
+		compression_unavailable: function() {
+			$("#Button_Compress")
+				.prop("disabled", true)
+				.attr("title", "Windows file compression isn't available on this system");
+		},
+
+		set_read_only_mode: function(enabled) {
+			$("#Read_Only_Mode").prop("checked", enabled);
+			read_only = enabled;
+		},
+
+		set_priority: function(priority, throughput_limit) {
+			$("#Priority").val(priority);
+			$("#Throughput_Limit").val(throughput_limit || "");
+		},
+
+		set_max_runtime: function(secs) {
+			$("#Max_Runtime").val(secs ? Math.max(Math.round(secs / 60), 1) : "");
+		},
+
+		set_min_savings: function(pct) {
+			$("#Min_Savings").val(pct || "");
+		},
+
+		set_log_level: function(level) {
+			$("#Log_Level").val(level);
+		},
+
+		set_order: function(order) {
+			$("#Order").val(order);
+		},
+
+		set_theme: function(theme) {
+			$("#Theme").val(theme);
+
+			// The system theme is whichever the Windows colour settings ask for.
+			if (theme === "System") {
+				var matches = function(query) {
+					return window.matchMedia && window.matchMedia(query).matches;
+				};
+
+				if (matches("(-ms-high-contrast: active)") || matches("(forced-colors: active)")) {
+					theme = "HighContrast";
+				} else if (matches("(prefers-color-scheme: light)")) {
+					theme = "Light";
+				} else {
+					theme = "Dark";
+				}
+			}
+
+			$("body")
+				.removeClass("theme-light theme-dark theme-high-contrast")
+				.addClass({
+					Light: "theme-light",
+					Dark: "theme-dark",
+					HighContrast: "theme-high-contrast"
+				}[theme] || "theme-dark");
+		},
+
+		set_max_depth: function(depth) {
+			$("#Max_Depth").val(depth || "");
+		},
+
+		set_threads: function(threads) {
+			$("#Threads").val(threads || "");
+		},
+
+		set_size_range: function(min_size, max_size) {
+			$("#Min_Size").val(min_size == null ? "" : min_size / 1024);
+			$("#Max_Size").val(max_size == null ? "" : max_size / 1024);
+		},
+
+		set_min_age: function(days) {
+			$("#Min_Age").val(days || "");
+		},
+
+		set_preset: function(preset) {
+			$("#Preset_Mode").val(preset || "");
+		},
+
+		// Stop the chooser being opened twice while the first is still up.
+		choosing_folder: function() {
+			$("#Button_Folder").prop("disabled", true);
+			$("#Button_File").prop("disabled", true);
+		},
+
+		folder_chosen: function() {
+			$("#Button_Folder").prop("disabled", false);
+			$("#Button_File").prop("disabled", false);
 		},
 
 		set_folder: function(folder) {
+			current_folder = folder;
+			Gui.folder_chosen();
+			$("#Button_Save_Profile").show();
+			$("#Button_Preview_Excludes").show();
+			$("#Exclude_Preview").hide();
+
 			var bits = folder.split(/:\\|\\/).map(function(x) { return document.createTextNode(x); });
 			var end = bits.pop();
 
@@ -306,8 +932,20 @@ var Gui = (function() {
 			Gui.scanning();
 		},
 
-		set_status: function(status, pct) {
+		set_status: function(status, pct, eta, mbps) {
 			$("#Activity_Text").text(status);
+			if (testing_config && pct === 1) {
+				testing_config = false;
+				Gui.toast(status);
+			}
+			var eta_text = [];
+			if (eta != null) {
+				eta_text.push("About " + Util.format_duration(eta) + " remaining");
+			}
+			if (mbps != null) {
+				eta_text.push(Util.format_number(mbps, 1) + " MiB/s");
+			}
+			$("#Activity_ETA").text(eta_text.join(", "));
 			if (pct != null) {
 				$("#Activity_Progress").val(pct);
 			} else {
@@ -315,26 +953,82 @@ var Gui = (function() {
 			}
 		},
 
+		set_phase: function(phase, current, total) {
+			var name = phase.charAt(0).toUpperCase() + phase.slice(1);
+
+			if (total > 0) {
+				$("#Phase_Text").text(name + ": " + Util.format_number(current, 0) + " of " + Util.format_number(total, 0) + " files");
+				$("#Phase_Progress").val(current / total);
+			} else {
+				$("#Phase_Text").text(name + ": " + Util.format_number(current, 0) + " files");
+				$("#Phase_Progress").removeAttr("value");
+			}
+		},
+
+		set_current_file: function(path, index, total) {
+			$("#Current_File").text(Util.format_number(index, 0) + "/" + Util.format_number(total, 0) + ": " + path);
+		},
+
 		scanning: function() {
 			Gui.reset_folder_summary();
+			$("#Current_File").text("");
 			$("#Activity").show();
 			$("#Analysis").show();
 			$("#Button_Pause").show();
 			$("#Button_Resume").hide();
+			$("#Button_Skip").hide();
 			$("#Button_Stop").show();
 			$("#Button_Analyse").hide();
 			$("#Button_Compress").hide();
 			$("#Button_Decompress").hide();
+			$("#Decompress_Only").hide();
+			$("#Force_Compress_Label").hide();
+			$("#Incremental_Label").hide();
+			$("#Button_Export").hide();
+			$("#Button_Export_Csv").hide();
+			$("#Button_Export_Treemap").hide();
+			$("#Button_Estimate").hide();
+			$("#Button_Benchmark").hide();
+			$("#Button_Undo").hide();
+			$("#Button_Rollback").hide();
+			$("#Estimate").hide();
+			$("#Benchmark").hide();
+			$("#Subfolders").hide();
+			$("#Result").hide();
+			$("#Errors").hide();
+			$("#Error_List").hide();
 			$("#Command").show();
 		},
 
-		compacting: function() {
+		compacting: function(skippable) {
+			if (skippable) {
+				$("#Button_Skip").show();
+			} else {
+				$("#Button_Skip").hide();
+			}
+
+			// Only compression runs are skippable, and they use up the selection.
+			if (skippable && Object.keys(deselected_subfolders).length > 0) {
+				deselected_subfolders = {};
+				Gui.sort_subfolders(subfolder_sort);
+			}
 			$("#Button_Pause").show();
 			$("#Button_Resume").hide();
 			$("#Button_Stop").show();
 			$("#Button_Analyse").hide();
 			$("#Button_Compress").hide();
 			$("#Button_Decompress").hide();
+			$("#Decompress_Only").hide();
+			$("#Force_Compress_Label").hide();
+			$("#Incremental_Label").hide();
+			$("#Button_Export").hide();
+			$("#Button_Export_Csv").hide();
+			$("#Button_Export_Treemap").hide();
+			$("#Button_Estimate").hide();
+			$("#Button_Benchmark").hide();
+			$("#Button_Undo").hide();
+			$("#Button_Rollback").hide();
+			$("#Result").hide();
 		},
 
 		paused: function() {
@@ -352,21 +1046,69 @@ var Gui = (function() {
 		},
 
 		scanned: function() {
+			// Requests made mid-run are dropped, so don't wait for an answer.
+			Gui.folder_chosen();
+			$("#Current_File").text("");
 			$("#Button_Pause").hide();
 			$("#Button_Resume").hide();
+			$("#Button_Skip").hide();
 			$("#Button_Stop").hide();
 			$("#Button_Analyse").show();
+			$("#Button_Export").show();
+			$("#Button_Export_Csv").show();
+			$("#Button_Export_Treemap").show();
+
+			if (undo_folder) {
+				$("#Button_Undo").show();
+			} else {
+				$("#Button_Undo").hide();
+			}
+
+			if ($("#Write_Manifest").is(":checked")) {
+				$("#Button_Rollback").show();
+			} else {
+				$("#Button_Rollback").hide();
+			}
+
+			if ($("#File_Count_Compressible").text() != "0") {
+				$("#Button_Estimate").show();
+				$("#Button_Benchmark").show();
+			} else {
+				$("#Button_Estimate").hide();
+				$("#Button_Benchmark").hide();
+			}
 
 			if ($("#File_Count_Compressible").text() != "0") {
 				$("#Button_Compress").show();
+				$("#Incremental_Label").show();
 			} else {
 				$("#Button_Compress").hide();
+				$("#Incremental_Label").hide();
 			}
 
+			// Once everything's compressed only offer to recompress on request.
 			if ($("#File_Count_Compressed").text() != "0") {
 				$("#Button_Decompress").show();
+				$("#Decompress_Only").show();
+				$("#Force_Compress_Label").show();
+				if (!fully_compressed || $("#Force_Compress").is(":checked")) {
+					$("#Incremental_Label").show();
+					$("#Button_Compress").show();
+				}
 			} else {
 				$("#Button_Decompress").hide();
+				$("#Decompress_Only").hide();
+				$("#Force_Compress_Label").hide();
+			}
+
+			if (read_only) {
+				$("#Button_Compress").hide();
+				$("#Incremental_Label").hide();
+				$("#Force_Compress_Label").hide();
+				$("#Button_Decompress").hide();
+				$("#Decompress_Only").hide();
+				$("#Button_Undo").hide();
+				$("#Button_Rollback").hide();
 			}
 		},
 
@@ -376,7 +1118,15 @@ var Gui = (function() {
 				physical_size: 0,
 				compressed: {count: 0, logical_size: 0, physical_size: 0},
 				compressible: {count: 0, logical_size: 0, physical_size: 0},
-				skipped: {count: 0, logical_size: 0, physical_size: 0}
+				skipped: {count: 0, logical_size: 0, physical_size: 0},
+				size_skipped: {count: 0, logical_size: 0, physical_size: 0},
+				attribute_skipped: 0,
+				sparse_skipped: 0,
+				reparse_skipped: 0,
+				ratio: 1.0,
+				optimal: 1.0,
+				extension_ratios: {},
+				by_algorithm: {}
 			});
 		},
 
@@ -384,28 +1134,283 @@ var Gui = (function() {
 			$("#Size_Logical").text(Util.bytes_to_human(data.logical_size));
 			$("#Size_Physical").text(Util.bytes_to_human(data.physical_size));
 
-			if (data.logical_size > 0) {
-				var ratio = (data.physical_size / data.logical_size);
-				$("#Compress_Ratio").text(Util.format_number(ratio, 2));
+			$("#Compress_Ratio").text(Util.format_number(data.ratio, 2));
+
+			// Allow for a few stragglers too small to matter.
+			fully_compressed = data.compressed.count > 0 && data.optimal >= 0.995;
+			if (fully_compressed) {
+				$("#Fully_Compressed").show();
 			} else {
-				$("#Compress_Ratio").text("1.00");
+				$("#Fully_Compressed").hide();
 			}
+			Gui.set_extension_ratios(data.extension_ratios);
+			Gui.set_algorithm_breakdown(data.by_algorithm);
+
+			var skipped_size = data.skipped.physical_size + data.size_skipped.physical_size;
 
 			if (data.logical_size > 0) {
 				var total = data.logical_size;
 				$("#Compressed_Size").text(Util.bytes_to_human(data.compressed.physical_size));
 				$("#Compressible_Size").text(Util.bytes_to_human(data.compressible.physical_size));
-				$("#Skipped_Size").text(Util.bytes_to_human(data.skipped.physical_size));
+				$("#Skipped_Size").text(Util.bytes_to_human(skipped_size));
 				document.getElementById("Breakdown_Compressed").style.width = "" + 100 * (data.compressed.physical_size / total).toFixed(2) + "%";
 				document.getElementById("Breakdown_Compressible").style.width = "" + 100 * (data.compressible.physical_size / total).toFixed(2) + "%";
-				document.getElementById("Breakdown_Skipped").style.width = "" + 100 * (data.skipped.physical_size / total).toFixed(2) + "%";
+				document.getElementById("Breakdown_Skipped").style.width = "" + 100 * (skipped_size / total).toFixed(2) + "%";
 			}
 
 			$("#Space_Saved").text(Util.bytes_to_human(data.compressed.logical_size - data.compressed.physical_size));
 
 			$("#File_Count_Compressed").text(Util.format_number(data.compressed.count, 0));
 			$("#File_Count_Compressible").text(Util.format_number(data.compressible.count, 0));
-			$("#File_Count_Skipped").text(Util.format_number(data.skipped.count, 0));
+			$("#File_Count_Skipped").text(Util.format_number(data.skipped.count + data.size_skipped.count, 0));
+			$("#File_Count_Size_Skipped").text(Util.format_number(data.size_skipped.count, 0));
+
+			if (data.size_skipped.count > 0) {
+				$("#Size_Skipped").show();
+			} else {
+				$("#Size_Skipped").hide();
+			}
+
+			$("#File_Count_Attribute_Skipped").text(Util.format_number(data.attribute_skipped, 0));
+			if (data.attribute_skipped > 0) {
+				$("#Attribute_Skipped").show();
+			} else {
+				$("#Attribute_Skipped").hide();
+			}
+
+			$("#File_Count_Sparse_Skipped").text(Util.format_number(data.sparse_skipped, 0));
+			if (data.sparse_skipped > 0) {
+				$("#Sparse_Skipped").show();
+			} else {
+				$("#Sparse_Skipped").hide();
+			}
+
+			$("#File_Count_Reparse_Skipped").text(Util.format_number(data.reparse_skipped, 0));
+			if (data.reparse_skipped > 0) {
+				$("#Reparse_Skipped").show();
+			} else {
+				$("#Reparse_Skipped").hide();
+			}
+		},
+
+		// Rows are [path, logical, physical].  Sort by column, largest first
+		// except for names.
+		set_subfolders: function(entries) {
+			subfolders = entries;
+			deselected_subfolders = {};
+			Gui.sort_subfolders(subfolder_sort);
+		},
+
+		sort_subfolders: function(column) {
+			subfolder_sort = column;
+			var key = function(e) { return column == 3 ? e[1] - e[2] : e[column]; };
+			var rows = subfolders.slice().sort(function(a, b) {
+				if (column == 0) {
+					return a[0].localeCompare(b[0]);
+				}
+				return key(b) - key(a);
+			});
+
+			var body = $("#Subfolders tbody").empty();
+			rows.forEach(function(e) {
+				var checkbox = $("<input type='checkbox'>")
+					.prop("checked", !deselected_subfolders[e[0]])
+					.attr("title", "Compress this subfolder")
+					.on("change", function() {
+						if (this.checked) {
+							delete deselected_subfolders[e[0]];
+						} else {
+							deselected_subfolders[e[0]] = true;
+						}
+						Action.set_excluded_subfolders(Object.keys(deselected_subfolders));
+					});
+
+				body.append($("<tr>")
+					.append($("<td>").append(checkbox).append($("<span>").text(" " + e[0])))
+					.append($("<td>").text(Util.bytes_to_human(e[1])))
+					.append($("<td>").text(Util.bytes_to_human(e[2])))
+					.append($("<td>").text(Util.bytes_to_human(Math.max(e[1] - e[2], 0)))));
+			});
+
+			if (rows.length > 0) {
+				$("#Subfolders").show();
+			} else {
+				$("#Subfolders").hide();
+			}
+		},
+
+		// List the best-compressing file types, to help tune excludes.
+		set_extension_ratios: function(ratios) {
+			var list = $("#Extension_Ratios").empty();
+			var exts = Object.keys(ratios).filter(function(ext) { return ratios[ext] < 1.0; });
+
+			exts.sort(function(a, b) { return ratios[a] - ratios[b]; });
+			exts.slice(0, 12).forEach(function(ext) {
+				list.append($("<li>").text((ext || "(none)") + " " + Util.format_number(ratios[ext], 2) + "x"));
+			});
+		},
+
+		// Each group is [file count, size on disk], most files first.
+		set_algorithm_breakdown: function(groups) {
+			var names = Object.keys(groups);
+			names.sort(function(a, b) { return groups[b][0] - groups[a][0]; });
+
+			// Worth a line only if there's a mix.
+			if (names.length < 2) {
+				$("#Algorithm_Breakdown").hide();
+				return;
+			}
+
+			$("#Algorithm_Breakdown").text(names.map(function(name) {
+				var label = name == "Uncompressed" ? "uncompressed" : name;
+				return Util.format_number(groups[name][0], 0) + " files " + label +
+					" (" + Util.bytes_to_human(groups[name][1]) + ")";
+			}).join(", ")).show();
+		},
+
+		set_estimate: function(saved, ratio) {
+			$("#Estimate_Saved").text(Util.bytes_to_human(saved));
+			$("#Estimate_Ratio").text(Util.format_number(ratio, 2));
+			$("#Estimate").show();
+		},
+
+		// Each result is [name, size on disk ratio, MiB/s].
+		set_benchmark: function(results) {
+			var list = $("#Benchmark");
+			list.empty();
+			results.forEach(function(r) {
+				list.append($("<li>").text(r[0] + ": " + Util.format_number(r[1] * 100, 1) + "% of original, " + Util.format_number(r[2], 1) + " MiB/s"));
+			});
+			list.show();
+		},
+
+		// Compare on-disk sizes, since that's the space actually reclaimed.
+		set_compression_result: function(before, after, stopped) {
+			var saved = before.physical_size - after.physical_size;
+			var pct = before.physical_size > 0 ? saved / before.physical_size * 100 : 0;
+
+			$("#Result_Saved").text(Util.bytes_to_human(Math.max(saved, 0)));
+			$("#Result_Pct").text(Util.format_number(Math.max(pct, 0), 1));
+			$("#Result_When").text(stopped ? "before stopping" : "this run");
+			$("#Result").show();
+		},
+
+		set_disk_info: function(free, total) {
+			$("#Disk_Free").text(Util.bytes_to_human(free));
+			$("#Disk_Total").text(Util.bytes_to_human(total));
+			$("#Disk_Info").show();
+		},
+
+		set_equivalent_command: function(cmd) {
+			$("#Equivalent_Command").text(cmd);
+		},
+
+		set_lifetime_stats: function(bytes_saved, files) {
+			$("#Lifetime_Saved").text(Util.bytes_to_human(bytes_saved));
+			$("#Lifetime_Files").text(Util.format_number(files, 0));
+		},
+
+		set_recent_folders: function(paths) {
+			var select = $("#Recent_Folders").empty();
+			select.append($("<option>").attr("value", "").text("Recent…"));
+
+			paths.forEach(function(path, index) {
+				select.append($("<option>").attr("value", index).text(path));
+			});
+
+			select.val("");
+			if (paths.length > 0) {
+				select.show();
+			} else {
+				select.hide();
+			}
+		},
+
+		set_named_presets: function(names) {
+			var select = $("#Named_Presets").empty();
+			select.append($("<option>").attr("value", "").text("Load…"));
+
+			names.forEach(function(name) {
+				select.append($("<option>").attr("value", name).text(name));
+			});
+
+			select.val("");
+		},
+
+		set_undo_available: function(folder) {
+			undo_folder = folder;
+			$("#Button_Undo").attr("title", folder ? "Decompress the files last compressed in " + folder : "");
+			if (!folder) {
+				$("#Button_Undo").hide();
+			}
+		},
+
+		set_queue: function(pending, active) {
+			var list = $("#Queue_List").empty();
+
+			if (active != null) {
+				list.append($("<li>").addClass("active").text("▶ " + active));
+			}
+
+			pending.forEach(function(path) {
+				list.append($("<li>").text(path));
+			});
+
+			if (active != null || pending.length > 0) {
+				$("#Queue").show();
+			} else {
+				$("#Queue").hide();
+			}
+		},
+
+		set_errors: function(count, sample, paths) {
+			var list = $("#Error_List").empty().hide();
+
+			if (count == 0) {
+				$("#Errors").hide();
+				return;
+			}
+
+			sample.forEach(function(error, index) {
+				var reveal = $("<a>").addClass("reveal").attr("title", "Show in Explorer").text("📂");
+				reveal.on("click", function() {
+					Action.reveal_file(paths[index]);
+				});
+				list.append($("<li>").append(reveal).append(document.createTextNode(" " + error)));
+			});
+
+			if (count > sample.length) {
+				list.append($("<li>").text("... and " + (count - sample.length) + " more"));
+			}
+
+			$("#Error_Count").text(count);
+			$("#Errors").show();
+		},
+
+		exclude_preview: function(matched, unmatched, sample) {
+			$("#Exclude_Preview_Text").text(
+				"Excludes match " + Util.format_number(matched, 0) + " of " +
+				Util.format_number(matched + unmatched, 0) + " files in " + current_folder);
+			var list = $("#Exclude_Preview_List");
+			list.empty();
+			sample.forEach(function(path) {
+				list.append($("<li>").text(path));
+			});
+			if (matched > sample.length) {
+				list.append($("<li>").text("… and " + Util.format_number(matched - sample.length, 0) + " more"));
+			}
+			$("#Exclude_Preview").show();
+		},
+
+		testing_config: function() {
+			testing_config = true;
+		},
+
+		toast: function(text) {
+			var toast = $("#Toast");
+			toast.text(text).show();
+			clearTimeout(toast_timeout);
+			toast_timeout = setTimeout(function() { toast.hide(); }, 4000);
 		},
 
 		analysis_complete: function() {
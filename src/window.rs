@@ -0,0 +1,188 @@
+// Helpers for saving and restoring the GUI window's position, hiding it away
+// in the tray, and showing progress on its taskbar button.
+//
+// web-view doesn't expose its window handle, so we look it up by title and use
+// the Win32 API directly.
+
+use std::ffi::OsStr;
+use std::os::windows::ffi::OsStrExt;
+
+use winapi::shared::minwindef::DWORD;
+use winapi::shared::windef::{HWND, RECT};
+use winapi::shared::winerror::SUCCEEDED;
+use winapi::shared::wtypesbase::CLSCTX_INPROC_SERVER;
+use winapi::um::combaseapi::CoCreateInstance;
+use winapi::um::processthreadsapi::GetCurrentProcessId;
+use winapi::um::shobjidl_core::{CLSID_TaskbarList, ITaskbarList3, TBPF_NOPROGRESS, TBPF_NORMAL};
+use winapi::um::winuser::{
+    FindWindowW, FlashWindowEx, GetSystemMetrics, GetWindowRect, GetWindowThreadProcessId,
+    IsIconic, IsWindowVisible, IsZoomed, MonitorFromRect, PostMessageW, SetForegroundWindow,
+    SetWindowPos, ShowWindow, FLASHWINFO, FLASHW_ALL, FLASHW_TIMERNOFG, MONITOR_DEFAULTTONULL,
+    SM_CXSCREEN, SM_CYSCREEN, SWP_NOACTIVATE, SWP_NOZORDER, SW_HIDE, SW_RESTORE, WM_CLOSE,
+};
+use winapi::Interface;
+
+use crate::state::WindowGeometry;
+
+#[derive(Clone, Copy)]
+pub struct Window(HWND);
+
+impl Window {
+    /// Find our own top-level window with the given title.
+    pub fn find(title: &str) -> Option<Self> {
+        let title: Vec<u16> = OsStr::new(title).encode_wide().chain(Some(0)).collect();
+        let hwnd = unsafe { FindWindowW(std::ptr::null(), title.as_ptr()) };
+
+        if hwnd.is_null() {
+            return None;
+        }
+
+        let mut pid: DWORD = 0;
+        unsafe { GetWindowThreadProcessId(hwnd, &mut pid) };
+
+        if pid == unsafe { GetCurrentProcessId() } {
+            Some(Self(hwnd))
+        } else {
+            None
+        }
+    }
+
+    pub fn is_minimized(&self) -> bool {
+        unsafe { IsIconic(self.0) != 0 }
+    }
+
+    pub fn is_visible(&self) -> bool {
+        unsafe { IsWindowVisible(self.0) != 0 }
+    }
+
+    /// Hide the window, taking it off the taskbar.
+    pub fn hide(&self) {
+        unsafe { ShowWindow(self.0, SW_HIDE) };
+    }
+
+    /// Show and restore the window from a minimized or hidden state.
+    pub fn show(&self) {
+        unsafe {
+            ShowWindow(self.0, SW_RESTORE);
+            SetForegroundWindow(self.0);
+        }
+    }
+
+    /// Flash the taskbar button until the window is brought to the front.
+    pub fn flash(&self) {
+        let mut info = FLASHWINFO {
+            cbSize: std::mem::size_of::<FLASHWINFO>() as u32,
+            hwnd: self.0,
+            dwFlags: FLASHW_ALL | FLASHW_TIMERNOFG,
+            uCount: 0,
+            dwTimeout: 0,
+        };
+
+        unsafe { FlashWindowEx(&mut info) };
+    }
+
+    /// Fill the taskbar button to a percentage, or clear it with `None`.
+    ///
+    /// COM must already be initialized on the calling thread, as it is on the
+    /// web-view thread.
+    pub fn set_progress(&self, pct: Option<u32>) {
+        let mut taskbar: *mut ITaskbarList3 = std::ptr::null_mut();
+        let hr = unsafe {
+            CoCreateInstance(
+                &CLSID_TaskbarList,
+                std::ptr::null_mut(),
+                CLSCTX_INPROC_SERVER,
+                &ITaskbarList3::uuidof(),
+                &mut taskbar as *mut *mut ITaskbarList3 as *mut _,
+            )
+        };
+
+        if !SUCCEEDED(hr) || taskbar.is_null() {
+            return;
+        }
+
+        unsafe {
+            let taskbar = &*taskbar;
+            if SUCCEEDED(taskbar.HrInit()) {
+                match pct {
+                    Some(pct) => {
+                        taskbar.SetProgressState(self.0, TBPF_NORMAL);
+                        taskbar.SetProgressValue(self.0, u64::from(pct.min(100)), 100);
+                    }
+                    None => {
+                        taskbar.SetProgressState(self.0, TBPF_NOPROGRESS);
+                    }
+                }
+            }
+            taskbar.Release();
+        }
+    }
+
+    /// Ask the window to close, as if the user clicked the close button.
+    pub fn close(&self) {
+        unsafe { PostMessageW(self.0, WM_CLOSE, 0, 0) };
+    }
+
+    /// The current position and size, unless minimized or maximized.
+    pub fn geometry(&self) -> Option<WindowGeometry> {
+        if unsafe { IsIconic(self.0) != 0 || IsZoomed(self.0) != 0 } {
+            return None;
+        }
+
+        let mut rect: RECT = unsafe { std::mem::zeroed() };
+        if unsafe { GetWindowRect(self.0, &mut rect) } == 0 {
+            return None;
+        }
+
+        Some(WindowGeometry {
+            x: rect.left,
+            y: rect.top,
+            width: rect.right - rect.left,
+            height: rect.bottom - rect.top,
+        })
+    }
+
+    /// Move to a saved position, or the default size centered on the primary
+    /// monitor if it's missing or no longer visible.
+    pub fn restore(&self, saved: Option<WindowGeometry>, default: (i32, i32)) {
+        let geometry = saved.filter(is_on_screen).unwrap_or_else(|| {
+            let (width, height) = default;
+            let (screen_width, screen_height) =
+                unsafe { (GetSystemMetrics(SM_CXSCREEN), GetSystemMetrics(SM_CYSCREEN)) };
+
+            WindowGeometry {
+                x: ((screen_width - width) / 2).max(0),
+                y: ((screen_height - height) / 2).max(0),
+                width,
+                height,
+            }
+        });
+
+        unsafe {
+            SetWindowPos(
+                self.0,
+                std::ptr::null_mut(),
+                geometry.x,
+                geometry.y,
+                geometry.width,
+                geometry.height,
+                SWP_NOZORDER | SWP_NOACTIVATE,
+            );
+        }
+    }
+}
+
+fn is_on_screen(geometry: &WindowGeometry) -> bool {
+    if geometry.width <= 0 || geometry.height <= 0 {
+        return false;
+    }
+
+    let rect = RECT {
+        left: geometry.x,
+        top: geometry.y,
+        right: geometry.x + geometry.width,
+        bottom: geometry.y + geometry.height,
+    };
+
+    unsafe { !MonitorFromRect(&rect, MONITOR_DEFAULTTONULL).is_null() }
+}